@@ -0,0 +1,33 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The `code`s of the error responses, shared with clients of the api.
+pub mod codes {
+    pub const INVALID_CREDENTIALS: &str = "invalid_credentials";
+    pub const FORBIDDEN: &str = "forbidden";
+    pub const TOO_MANY_ATTEMPTS: &str = "too_many_attempts";
+    pub const INVALID_VALUE: &str = "invalid_value";
+    pub const WEAK_PASSWORD: &str = "weak_password";
+    pub const NOT_FOUND: &str = "not_found";
+    pub const NAME_CONFLICT: &str = "name_conflict";
+    pub const NODE_NAME_INVALID: &str = "node_name_invalid";
+    pub const BAD_REQUEST: &str = "bad_request";
+    pub const INTERNAL_ERROR: &str = "internal_error";
+    pub const TOKEN_MISSING: &str = "token_missing";
+    pub const TOKEN_WRONG_SCHEME: &str = "token_wrong_scheme";
+    pub const TOKEN_INVALID: &str = "token_invalid";
+    pub const TOKEN_EXPIRED: &str = "token_expired";
+    pub const TOKEN_BAD_COUNT: &str = "token_bad_count";
+    pub const INSUFFICIENT_SCOPE: &str = "insufficient_scope";
+    pub const SESSION_REQUIRED: &str = "session_required";
+}
+
+/// The json body of every error response of the api.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct ErrorBody {
+    /// One of `codes`, or a code a client does not know yet.
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
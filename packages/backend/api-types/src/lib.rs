@@ -0,0 +1,22 @@
+//! The request and response types and the error codes of the api, shared by
+//! the backend and its clients. Unlike the backend, this crate builds without
+//! a database or rocket, so clients only depend on what they send and receive.
+//!
+//! With the `diesel` feature, the backend can load `Node` and `User` from its
+//! database directly.
+
+#[cfg(feature = "diesel")]
+#[macro_use]
+extern crate diesel;
+
+pub mod errors;
+mod nodes;
+mod users;
+
+pub use errors::{codes, ErrorBody};
+pub use nodes::{
+    ChangeNodeContent, ChangeNodeName, ChangeParentPayload,
+    ChangeParentResponse, CreateNodePayload, DeleteNode, NewNodePayload, Node,
+    NodeId, NodeName, OwnedPath, Path, NOTE_EXTENSION,
+};
+pub use users::{AuthResponse, Credentials, User, UserId};
@@ -0,0 +1,95 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::users::UserId;
+
+pub type NodeName = String;
+pub type NodeId = i32;
+
+pub type Path = [NodeName];
+pub type OwnedPath = Vec<NodeName>;
+
+/// The extension of the files notes are written to.
+pub const NOTE_EXTENSION: &str = ".md";
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewNodePayload {
+    pub name: String,
+    pub is_directory: bool,
+    pub content: Option<String>,
+}
+
+#[cfg_attr(feature = "diesel", derive(Queryable))]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Node {
+    pub node_id: NodeId,
+    pub node_name: NodeName,
+
+    pub parent_id: Option<NodeId>,
+    #[serde(skip_serializing)]
+    #[schemars(skip)]
+    pub parent_is_directory: Option<bool>,
+    pub owner_id: UserId,
+
+    pub is_directory: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+impl Node {
+    /// Checks whether the given string is a valid node name. Names starting
+    /// with a `.` are hidden entries on disk, so they are rejected by every
+    /// store and never end up in an export.
+    pub fn is_name_valid(name: &str) -> bool {
+        // Based on https://stackoverflow.com/a/35352640 prevent several
+        // charactes to ensure compatibility with usual file systems.
+        !name.is_empty()
+            && !name.starts_with('.')
+            && name.chars().all(|c| {
+                !matches!(c, '\\' | '/' | ':' | '*' | '"' | '<' | '>' | '|')
+            })
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateNodePayload {
+    pub parent: OwnedPath,
+    pub node: NewNodePayload,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeNodeContent {
+    pub path: OwnedPath,
+    pub new_content: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeNodeName<'a> {
+    pub path: OwnedPath,
+    pub new_name: &'a str,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeParentPayload {
+    pub node_path: OwnedPath,
+    pub new_parent_path: OwnedPath,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeParentResponse {
+    pub old_path: OwnedPath,
+    pub new_path: OwnedPath,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteNode {
+    pub path: OwnedPath,
+}
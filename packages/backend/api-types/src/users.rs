@@ -0,0 +1,24 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub type UserId = i32;
+
+#[cfg_attr(feature = "diesel", derive(Queryable))]
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct User {
+    pub id: UserId,
+    pub username: String,
+    #[serde(skip)]
+    pub password_hash: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct AuthResponse {
+    pub token: String,
+}
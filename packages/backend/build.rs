@@ -0,0 +1,103 @@
+//! Generates the list of embedded migration versions and the list of webapp
+//! files that are embedded into the binary with the `embed-webapp` feature.
+//! The webapp build is read from `MN_WEBAPP_BUILD_DIR`, which defaults to
+//! `../webapp/build`.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    write_migration_versions();
+
+    println!("cargo:rerun-if-env-changed=MN_WEBAPP_BUILD_DIR");
+    if env::var_os("CARGO_FEATURE_EMBED_WEBAPP").is_none() {
+        return;
+    }
+
+    let dir = match env::var_os("MN_WEBAPP_BUILD_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("../webapp/build"),
+    };
+    let dir = dir.canonicalize().unwrap_or_else(|_| {
+        panic!(
+            "The webapp build {} does not exist, run yarn build first",
+            dir.display()
+        )
+    });
+
+    let mut files = Vec::new();
+    collect_files(&dir, &dir, &mut files);
+    // The files are looked up with a binary search.
+    files.sort();
+
+    let mut code = String::from("&[\n");
+    for (name, path) in files {
+        println!("cargo:rerun-if-changed={}", path.display());
+        code.push_str(&format!(
+            "    ({:?}, include_bytes!({:?})),\n",
+            name, path
+        ));
+    }
+    code.push_str("]\n");
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("webapp_assets.rs");
+    fs::write(out, code).unwrap();
+}
+
+/// Writes the versions of the migrations that `embed_migrations!` embeds, so
+/// the pending migrations can be found without running them. Like diesel, the
+/// version is the part of the directory name before the first `_` without
+/// dashes.
+fn write_migration_versions() {
+    let dir = if env::var_os("CARGO_FEATURE_POSTGRES").is_some() {
+        "migrations_postgres"
+    } else {
+        "migrations"
+    };
+    println!("cargo:rerun-if-changed={}", dir);
+
+    let mut versions = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if !path.is_dir() {
+            continue;
+        }
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let version = name.split('_').next().unwrap().replace('-', "");
+        versions.push(version);
+    }
+    versions.sort();
+
+    let mut code = String::from("&[\n");
+    for version in versions {
+        code.push_str(&format!("    {:?},\n", version));
+    }
+    code.push_str("]\n");
+
+    let out =
+        Path::new(&env::var("OUT_DIR").unwrap()).join("migration_versions.rs");
+    fs::write(out, code).unwrap();
+}
+
+/// Adds every file below `dir` with its path relative to `root`, separated by
+/// `/`.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) {
+    println!("cargo:rerun-if-changed={}", dir.display());
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_files(root, &path, files);
+        } else {
+            let name = path
+                .strip_prefix(root)
+                .unwrap()
+                .iter()
+                .map(|part| part.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((name, path));
+        }
+    }
+}
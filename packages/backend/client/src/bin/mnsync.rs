@@ -0,0 +1,117 @@
+use dotenv::dotenv;
+use notify::{watcher, RecursiveMode, Watcher};
+use std::error::Error;
+use std::io::{stdin, BufRead};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+
+use client::sync;
+use client::{Auth, Client};
+
+const USAGE: &str =
+    "Usage: mnsync [--once] [--interval <seconds>] [--password-stdin]
+              <server> <username> <dir>
+
+Mirrors the notebook of <username> on <server>, e.g. http://localhost:8000,
+into <dir>, with folders for directories and .md files for notes. Local edits
+are pushed as soon as they are saved and the server is polled for changes
+every --interval seconds (defaults to 30). With --once it syncs a single time
+and exits.
+
+If both sides changed a note, the version of the server is kept and the local
+version is written to a conflict copy next to it. The state of the last sync is
+kept in <dir>/.mnsync.json.
+
+Set MN_SYNC_TOKEN to a personal access token with the read and write scopes to
+authenticate with it. Otherwise the password is prompted for, or read from the
+first line of stdin with --password-stdin.";
+
+/// The time to wait for more changes after a local change.
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+fn main() -> Result<(), Box<dyn Error>> {
+    dotenv().ok();
+
+    let mut once = false;
+    let mut interval = Duration::from_secs(30);
+    let mut password_stdin = false;
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--once" => once = true,
+            "--password-stdin" => password_stdin = true,
+            "--interval" => {
+                match args.next().and_then(|secs| secs.parse().ok()) {
+                    Some(secs) => interval = Duration::from_secs(secs),
+                    None => usage_error(),
+                }
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => usage_error(),
+            _ => positional.push(arg),
+        }
+    }
+    let (server_url, username, dir) = match positional.as_slice() {
+        [server_url, username, dir] => {
+            (server_url, username, PathBuf::from(dir))
+        }
+        _ => usage_error(),
+    };
+
+    let auth = match std::env::var("MN_SYNC_TOKEN") {
+        Ok(token) => Auth::Token(token),
+        Err(_) => Auth::Password {
+            username: username.clone(),
+            password: read_password(password_stdin)?,
+        },
+    };
+    let mut client = Client::new(server_url, auth);
+    std::fs::create_dir_all(&dir)?;
+
+    let report = sync::sync(&dir, server_url, username, &mut client)?;
+    print!("{}", report);
+    if once {
+        return Ok(());
+    }
+
+    let (tx, rx) = channel();
+    let mut watcher = watcher(tx, DEBOUNCE)?;
+    watcher.watch(&dir, RecursiveMode::Recursive)?;
+    loop {
+        match rx.recv_timeout(interval) {
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        // Sync once for all changes that piled up.
+        while rx.try_recv().is_ok() {}
+
+        // Keep watching if the server is unreachable or a file changed while
+        // it was synced, the next sync retries.
+        match sync::sync(&dir, server_url, username, &mut client) {
+            Ok(report) => print!("{}", report),
+            Err(err) => eprintln!("Sync failed: {}", err),
+        }
+    }
+}
+
+fn usage_error() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+fn read_password(from_stdin: bool) -> std::io::Result<String> {
+    if from_stdin {
+        let mut password = String::new();
+        stdin().lock().read_line(&mut password)?;
+        let len = password.trim_end_matches(&['\r', '\n'][..]).len();
+        password.truncate(len);
+        Ok(password)
+    } else {
+        rpassword::read_password_from_tty(Some("Password: "))
+    }
+}
@@ -0,0 +1,161 @@
+use serde_json::Value;
+use std::fmt;
+
+use api_types::codes;
+
+/// The `code` of an error response, see `api_types::codes`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ErrorCode {
+    InvalidCredentials,
+    Forbidden,
+    TooManyAttempts,
+    InvalidValue,
+    WeakPassword,
+    NotFound,
+    NameConflict,
+    NodeNameInvalid,
+    BadRequest,
+    InternalError,
+    TokenMissing,
+    TokenWrongScheme,
+    TokenInvalid,
+    TokenExpired,
+    TokenBadCount,
+    InsufficientScope,
+    SessionRequired,
+    /// A code this client does not know yet.
+    Other(String),
+}
+
+impl ErrorCode {
+    pub fn from_code(code: &str) -> ErrorCode {
+        match code {
+            codes::INVALID_CREDENTIALS => ErrorCode::InvalidCredentials,
+            codes::FORBIDDEN => ErrorCode::Forbidden,
+            codes::TOO_MANY_ATTEMPTS => ErrorCode::TooManyAttempts,
+            codes::INVALID_VALUE => ErrorCode::InvalidValue,
+            codes::WEAK_PASSWORD => ErrorCode::WeakPassword,
+            codes::NOT_FOUND => ErrorCode::NotFound,
+            codes::NAME_CONFLICT => ErrorCode::NameConflict,
+            codes::NODE_NAME_INVALID => ErrorCode::NodeNameInvalid,
+            codes::BAD_REQUEST => ErrorCode::BadRequest,
+            codes::INTERNAL_ERROR => ErrorCode::InternalError,
+            codes::TOKEN_MISSING => ErrorCode::TokenMissing,
+            codes::TOKEN_WRONG_SCHEME => ErrorCode::TokenWrongScheme,
+            codes::TOKEN_INVALID => ErrorCode::TokenInvalid,
+            codes::TOKEN_EXPIRED => ErrorCode::TokenExpired,
+            codes::TOKEN_BAD_COUNT => ErrorCode::TokenBadCount,
+            codes::INSUFFICIENT_SCOPE => ErrorCode::InsufficientScope,
+            codes::SESSION_REQUIRED => ErrorCode::SessionRequired,
+            _ => ErrorCode::Other(String::from(code)),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            ErrorCode::InvalidCredentials => codes::INVALID_CREDENTIALS,
+            ErrorCode::Forbidden => codes::FORBIDDEN,
+            ErrorCode::TooManyAttempts => codes::TOO_MANY_ATTEMPTS,
+            ErrorCode::InvalidValue => codes::INVALID_VALUE,
+            ErrorCode::WeakPassword => codes::WEAK_PASSWORD,
+            ErrorCode::NotFound => codes::NOT_FOUND,
+            ErrorCode::NameConflict => codes::NAME_CONFLICT,
+            ErrorCode::NodeNameInvalid => codes::NODE_NAME_INVALID,
+            ErrorCode::BadRequest => codes::BAD_REQUEST,
+            ErrorCode::InternalError => codes::INTERNAL_ERROR,
+            ErrorCode::TokenMissing => codes::TOKEN_MISSING,
+            ErrorCode::TokenWrongScheme => codes::TOKEN_WRONG_SCHEME,
+            ErrorCode::TokenInvalid => codes::TOKEN_INVALID,
+            ErrorCode::TokenExpired => codes::TOKEN_EXPIRED,
+            ErrorCode::TokenBadCount => codes::TOKEN_BAD_COUNT,
+            ErrorCode::InsufficientScope => codes::INSUFFICIENT_SCOPE,
+            ErrorCode::SessionRequired => codes::SESSION_REQUIRED,
+            ErrorCode::Other(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The server responded with an error body.
+    Api {
+        status: u16,
+        code: ErrorCode,
+        message: String,
+        details: Option<Value>,
+    },
+    /// The server could not be reached.
+    Unreachable(String),
+    /// The server responded with a body that is not the expected json.
+    InvalidResponse(String),
+}
+
+impl Error {
+    /// The code of the error response, if the server responded.
+    pub fn code(&self) -> Option<&ErrorCode> {
+        match self {
+            Error::Api { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Api {
+                status,
+                code,
+                message,
+                ..
+            } => write!(f, "Server error {} ({}): {}", status, code, message),
+            Error::Unreachable(err) => write!(f, "Server unreachable: {}", err),
+            Error::InvalidResponse(err) => {
+                write!(f, "Invalid response: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::InvalidResponse(err.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::jwt::AuthTokenError;
+    use backend::BackendError;
+
+    #[test]
+    fn it_parses_the_codes() {
+        for code in &["name_conflict", "token_expired", "session_required"] {
+            assert_eq!(ErrorCode::from_code(code).as_str(), *code);
+        }
+        assert_eq!(ErrorCode::from_code("not_found"), ErrorCode::NotFound);
+        assert_eq!(
+            ErrorCode::from_code(BackendError::Conflict.code()),
+            ErrorCode::NameConflict
+        );
+        assert_eq!(
+            ErrorCode::from_code(AuthTokenError::SessionRequired.code()),
+            ErrorCode::SessionRequired
+        );
+        assert_eq!(
+            ErrorCode::from_code("new_code"),
+            ErrorCode::Other(String::from("new_code"))
+        );
+    }
+}
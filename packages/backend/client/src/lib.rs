@@ -0,0 +1,236 @@
+//! A typed client for the v1 api of the backend, for tools that work with the
+//! notebook of a user. It uses the request and response types of the
+//! `api-types` crate, like the backend, so both stay in sync. The `sync` module
+//! mirrors the notebook into a local directory for the `mnsync` binary.
+//!
+//! ```ignore
+//! let auth = Auth::Password {
+//!     username: String::from("alice"),
+//!     password: String::from("secret"),
+//! };
+//! let mut client = Client::new("http://localhost:8000", auth);
+//! for node in client.nodes()? {
+//!     println!("{}", node.node_name);
+//! }
+//! ```
+
+mod error;
+pub mod sync;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+
+use api_types::{
+    AuthResponse, ChangeNodeContent, ChangeNodeName, ChangeParentPayload,
+    CreateNodePayload, Credentials, DeleteNode, ErrorBody,
+};
+
+pub use api_types::{
+    ChangeParentResponse, NewNodePayload, Node, NodeId, Path, User,
+};
+pub use error::{Error, ErrorCode, Result};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How the client authenticates.
+pub enum Auth {
+    /// A personal access token.
+    Token(String),
+    /// Logs in with the password and logs in again once the JWT expired.
+    Password { username: String, password: String },
+}
+
+pub struct Client {
+    agent: ureq::Agent,
+    api_url: String,
+    auth: Auth,
+    token: Option<String>,
+}
+
+impl Client {
+    /// Creates a client for the server at `server_url`, e.g.
+    /// `http://localhost:8000`. Logs in with the first request.
+    pub fn new(server_url: &str, auth: Auth) -> Client {
+        let token = match &auth {
+            Auth::Token(token) => Some(token.clone()),
+            Auth::Password { .. } => None,
+        };
+        // The backend closes connections it kept alive, and ureq does not retry
+        // a POST on a closed pooled connection.
+        let agent = ureq::agent();
+        agent.set_max_pool_connections(0);
+        Client {
+            agent,
+            api_url: format!("{}/api/v1", server_url.trim_end_matches('/')),
+            auth,
+            token,
+        }
+    }
+
+    /// The token requests are sent with, if the client logged in already.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// Uses the token for the next requests, e.g. a JWT of an earlier login.
+    pub fn set_token(&mut self, token: String) {
+        self.token = Some(token);
+    }
+
+    /// Logs in with the password and returns the new JWT. Returns the access
+    /// token of `Auth::Token` as is.
+    pub fn login(&mut self) -> Result<String> {
+        let credentials = match &self.auth {
+            Auth::Password { username, password } => Credentials {
+                username: username.clone(),
+                password: password.clone(),
+            },
+            Auth::Token(token) => return Ok(token.clone()),
+        };
+        let mut request = self.agent.post(&self.url("/user/auth"));
+        request.timeout(TIMEOUT);
+        let response = check(request.send_json(to_json(&credentials)?))?;
+        let token = parse::<AuthResponse>(response)?.token;
+        self.token = Some(token.clone());
+        Ok(token)
+    }
+
+    /// Fetches the user the client is logged in as.
+    pub fn profile(&mut self) -> Result<User> {
+        parse(self.send::<()>("GET", "/user/profile", None)?)
+    }
+
+    /// Fetches all nodes of the user.
+    pub fn nodes(&mut self) -> Result<Vec<Node>> {
+        parse(self.send::<()>("GET", "/node", None)?)
+    }
+
+    /// Creates a node in the directory at `parent`, or a root node if
+    /// `parent` is empty.
+    pub fn create_node(
+        &mut self,
+        parent: &Path,
+        node: NewNodePayload,
+    ) -> Result<Node> {
+        let payload = CreateNodePayload {
+            parent: parent.to_vec(),
+            node,
+        };
+        parse(self.send("POST", "/node", Some(&payload))?)
+    }
+
+    pub fn change_content(
+        &mut self,
+        path: &Path,
+        content: &str,
+    ) -> Result<Node> {
+        let payload = ChangeNodeContent {
+            path: path.to_vec(),
+            new_content: String::from(content),
+        };
+        parse(self.send("PUT", "/node/content", Some(&payload))?)
+    }
+
+    pub fn rename(&mut self, path: &Path, new_name: &str) -> Result<Node> {
+        let payload = ChangeNodeName {
+            path: path.to_vec(),
+            new_name,
+        };
+        parse(self.send("PUT", "/node/name", Some(&payload))?)
+    }
+
+    /// Moves the node into the directory at `new_parent_path`, or to the root
+    /// if it is empty.
+    pub fn move_node(
+        &mut self,
+        path: &Path,
+        new_parent_path: &Path,
+    ) -> Result<ChangeParentResponse> {
+        let payload = ChangeParentPayload {
+            node_path: path.to_vec(),
+            new_parent_path: new_parent_path.to_vec(),
+        };
+        parse(self.send("PUT", "/node/parent", Some(&payload))?)
+    }
+
+    /// Deletes the node and everything below it.
+    pub fn delete(&mut self, path: &Path) -> Result<()> {
+        let payload = DeleteNode {
+            path: path.to_vec(),
+        };
+        self.send("DELETE", "/node", Some(&payload))?;
+        Ok(())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.api_url, path)
+    }
+
+    /// Sends an authenticated request. Logs in again once if the server
+    /// rejects the JWT of a previous login, e.g. because it expired.
+    fn send<B: Serialize>(
+        &mut self,
+        method: &str,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<ureq::Response> {
+        let body = body.map(to_json).transpose()?;
+        let mut retried = false;
+        loop {
+            let token = match &self.token {
+                Some(token) => token.clone(),
+                None => self.login()?,
+            };
+            let mut request = self.agent.request(method, &self.url(path));
+            request
+                .timeout(TIMEOUT)
+                .set("Authorization", &format!("Bearer {}", token));
+            let response = match &body {
+                Some(body) => request.send_json(body.clone()),
+                None => request.call(),
+            };
+
+            let can_retry = !retried
+                && response.status() == 401
+                && match self.auth {
+                    Auth::Password { .. } => true,
+                    Auth::Token(_) => false,
+                };
+            if can_retry {
+                self.token = None;
+                retried = true;
+                continue;
+            }
+            return check(response);
+        }
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Value> {
+    Ok(serde_json::to_value(value).map_err(std::io::Error::from)?)
+}
+
+fn parse<T: DeserializeOwned>(response: ureq::Response) -> Result<T> {
+    Ok(response.into_json_deserialize()?)
+}
+
+/// Turns error responses into `Error::Api`.
+fn check(response: ureq::Response) -> Result<ureq::Response> {
+    if let Some(err) = response.synthetic_error() {
+        return Err(Error::Unreachable(err.to_string()));
+    }
+    if response.ok() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let body = response.into_json_deserialize::<ErrorBody>()?;
+    Err(Error::Api {
+        status,
+        code: ErrorCode::from_code(&body.code),
+        message: body.message,
+        details: body.details,
+    })
+}
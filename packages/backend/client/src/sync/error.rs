@@ -0,0 +1,55 @@
+use std::fmt;
+use std::path::PathBuf;
+
+pub type SyncResult<T> = Result<T, SyncError>;
+
+/// Why a sync failed. The manifest is only saved after a successful sync, so
+/// the next sync repeats the missing changes.
+#[derive(Debug)]
+pub enum SyncError {
+    /// The directory or the manifest can not be read or written.
+    Io(std::io::Error),
+    /// A request to the server failed.
+    Api(crate::Error),
+    /// The directory is synced with the notebook of another user or server.
+    OtherNotebook {
+        server_url: String,
+        username: String,
+    },
+    /// The file was edited while it was synced.
+    Changed(PathBuf),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Io(err) => write!(f, "IO error: {}", err),
+            SyncError::Api(err) => write!(f, "{}", err),
+            SyncError::OtherNotebook {
+                server_url,
+                username,
+            } => write!(
+                f,
+                "The directory is synced with the notebook of {} on {}",
+                username, server_url
+            ),
+            SyncError::Changed(file) => {
+                write!(f, "{} was edited during the sync", file.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+impl From<std::io::Error> for SyncError {
+    fn from(err: std::io::Error) -> SyncError {
+        SyncError::Io(err)
+    }
+}
+
+impl From<crate::Error> for SyncError {
+    fn from(err: crate::Error) -> SyncError {
+        SyncError::Api(err)
+    }
+}
@@ -0,0 +1,54 @@
+//! The state of the last sync, stored next to the synced files.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use super::SyncResult;
+
+/// The name of the manifest in the synced directory. Hidden files are not
+/// synced.
+pub const MANIFEST_FILE: &str = ".mnsync.json";
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Item {
+    Directory,
+    /// A file with the hex encoded SHA-256 hash of its content.
+    File {
+        hash: String,
+    },
+}
+
+/// The nodes both sides had after the last sync, keyed by their path joined
+/// with `/`. A side changed a node if it differs from the manifest.
+#[derive(Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub server_url: String,
+    pub username: String,
+    pub items: BTreeMap<String, Item>,
+}
+
+impl Manifest {
+    /// Loads the manifest of `dir`, or returns `None` if it was never synced.
+    pub fn load(dir: &Path) -> SyncResult<Option<Manifest>> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let manifest = serde_json::from_slice(&fs::read(path)?)
+            .map_err(std::io::Error::from)?;
+        Ok(Some(manifest))
+    }
+
+    /// Replaces the manifest of `dir` atomically.
+    pub fn save(&self, dir: &Path) -> SyncResult<()> {
+        let json =
+            serde_json::to_vec_pretty(self).map_err(std::io::Error::from)?;
+        let tmp_path = dir.join(format!("{}.tmp", MANIFEST_FILE));
+        fs::write(&tmp_path, json)?;
+        fs::rename(tmp_path, dir.join(MANIFEST_FILE))?;
+        Ok(())
+    }
+}
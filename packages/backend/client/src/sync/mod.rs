@@ -0,0 +1,467 @@
+//! Mirrors the nodes of a user into a local directory and back, using the v1
+//! api. The directory uses the layout of `export`: folders for directories and
+//! `.md` files for files.
+//!
+//! A sync compares the local directory and the server with the manifest of
+//! the last sync. Changes of one side are applied to the other. If both sides
+//! changed a node in different ways, the server version is kept and the local
+//! version is written to a conflict copy next to it, which is uploaded like
+//! any other new file. A change wins over a deletion. Local directories that
+//! were deleted on the server are kept as long as they contain files that are
+//! not synced.
+
+mod error;
+mod manifest;
+
+pub use error::{SyncError, SyncResult};
+pub use manifest::{Item, Manifest, MANIFEST_FILE};
+
+use ring::digest::{digest, SHA256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{Client, NewNodePayload};
+use api_types::{Node, NodeId, NodeName, NOTE_EXTENSION};
+
+/// A node of either side. Files have their content.
+#[derive(Clone, Debug)]
+struct Entry {
+    item: Item,
+    content: Option<String>,
+}
+
+impl Entry {
+    fn directory() -> Entry {
+        Entry {
+            item: Item::Directory,
+            content: None,
+        }
+    }
+
+    fn file(content: String) -> Entry {
+        Entry {
+            item: Item::File {
+                hash: to_hex(digest(&SHA256, content.as_bytes()).as_ref()),
+            },
+            content: Some(content),
+        }
+    }
+
+    fn is_directory(&self) -> bool {
+        self.item == Item::Directory
+    }
+}
+
+/// The nodes of one side keyed by their path joined with `/`. Parents sort
+/// before their children.
+type Tree = BTreeMap<String, Entry>;
+
+/// What a sync changed, with the paths joined with `/`.
+#[derive(Default, Debug)]
+pub struct SyncReport {
+    /// Nodes changed on the server.
+    pub pushed: Vec<String>,
+    /// Nodes changed in the local directory.
+    pub pulled: Vec<String>,
+    /// Conflict copies of local nodes.
+    pub conflicts: Vec<String>,
+    /// Local files that can not be synced since their name is not a valid
+    /// node name.
+    pub skipped: Vec<String>,
+    /// Local directories deleted on the server that are kept, since they
+    /// contain files that are not synced, e.g. images or hidden files.
+    pub kept: Vec<String>,
+}
+
+impl SyncReport {
+    pub fn is_empty(&self) -> bool {
+        self.pushed.is_empty()
+            && self.pulled.is_empty()
+            && self.conflicts.is_empty()
+    }
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let groups = [
+            ("pushed", &self.pushed),
+            ("pulled", &self.pulled),
+            ("conflict copy", &self.conflicts),
+            ("skipped", &self.skipped),
+            ("kept", &self.kept),
+        ];
+        for (label, paths) in groups.iter() {
+            for path in paths.iter() {
+                writeln!(f, "{} {}", label, path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Syncs `dir` with the nodes of the user on the server once. The directory
+/// must have been synced with the same server and user before, or must not
+/// contain a manifest.
+pub fn sync(
+    dir: &Path,
+    server_url: &str,
+    username: &str,
+    client: &mut Client,
+) -> SyncResult<SyncReport> {
+    let previous = Manifest::load(dir)?;
+    if let Some(previous) = &previous {
+        if previous.server_url != server_url || previous.username != username {
+            return Err(SyncError::OtherNotebook {
+                server_url: previous.server_url.clone(),
+                username: previous.username.clone(),
+            });
+        }
+    }
+    let base = previous.map(|manifest| manifest.items).unwrap_or_default();
+
+    let mut report = SyncReport::default();
+    let local = scan(dir, &mut report)?;
+    let remote_tree = remote_tree(client.nodes()?);
+    let target = merge(&local, &remote_tree, &base, &mut report);
+
+    // The local directory is changed first, since it is checked for edits
+    // made in the meantime. The manifest stays untouched if anything fails,
+    // so the next sync repeats the missing changes.
+    apply_local(dir, &local, &target, &mut report)?;
+    apply_remote(client, &remote_tree, &target, &mut report)?;
+
+    let mut items: BTreeMap<String, Item> = target
+        .into_iter()
+        .map(|(path, entry)| (path, entry.item))
+        .collect();
+    // Kept directories stay in the manifest, so the next sync removes them
+    // once they are empty instead of creating them on the server again.
+    for path in &report.kept {
+        items.insert(path.clone(), Item::Directory);
+    }
+    let manifest = Manifest {
+        server_url: String::from(server_url),
+        username: String::from(username),
+        items,
+    };
+    if manifest.items != base || !dir.join(MANIFEST_FILE).exists() {
+        manifest.save(dir)?;
+    }
+    Ok(report)
+}
+
+/// Reads the nodes in `dir`. Hidden entries and files without the `.md`
+/// extension are ignored.
+fn scan(dir: &Path, report: &mut SyncReport) -> SyncResult<Tree> {
+    let mut tree = Tree::new();
+    scan_dir(dir, "", &mut tree, report)?;
+    Ok(tree)
+}
+
+fn scan_dir(
+    dir: &Path,
+    prefix: &str,
+    tree: &mut Tree,
+    report: &mut SyncReport,
+) -> SyncResult<()> {
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let file_name = dir_entry.file_name().to_string_lossy().into_owned();
+        if file_name.starts_with('.') {
+            continue;
+        }
+        let is_dir = dir_entry.file_type()?.is_dir();
+        let name = if is_dir {
+            file_name.as_str()
+        } else if file_name.ends_with(NOTE_EXTENSION) {
+            &file_name[..file_name.len() - NOTE_EXTENSION.len()]
+        } else {
+            continue;
+        };
+
+        let path = format!("{}{}", prefix, name);
+        if name.is_empty() || !Node::is_name_valid(name) {
+            report.skipped.push(path);
+            continue;
+        }
+        if is_dir {
+            tree.insert(path.clone(), Entry::directory());
+            scan_dir(&dir_entry.path(), &format!("{}/", path), tree, report)?;
+        } else {
+            let content = fs::read_to_string(dir_entry.path())?;
+            tree.insert(path, Entry::file(content));
+        }
+    }
+    Ok(())
+}
+
+fn remote_tree(nodes: Vec<Node>) -> Tree {
+    let names: HashMap<NodeId, (NodeName, Option<NodeId>)> = nodes
+        .iter()
+        .map(|node| (node.node_id, (node.node_name.clone(), node.parent_id)))
+        .collect();
+
+    let mut tree = Tree::new();
+    for node in nodes {
+        let mut parts = vec![node.node_name.clone()];
+        let mut parent_id = node.parent_id;
+        while let Some(id) = parent_id {
+            match names.get(&id) {
+                // A cycle can not be longer than the number of nodes.
+                Some((name, next)) if parts.len() <= names.len() => {
+                    parts.push(name.clone());
+                    parent_id = *next;
+                }
+                _ => break,
+            }
+        }
+        parts.reverse();
+        let entry = if node.is_directory {
+            Entry::directory()
+        } else {
+            Entry::file(node.content.unwrap_or_default())
+        };
+        tree.insert(parts.join("/"), entry);
+    }
+    tree
+}
+
+/// Returns the nodes both sides have after the sync.
+fn merge(
+    local: &Tree,
+    remote: &Tree,
+    base: &BTreeMap<String, Item>,
+    report: &mut SyncReport,
+) -> Tree {
+    let mut taken: BTreeSet<String> =
+        local.keys().chain(remote.keys()).cloned().collect();
+    // The conflict copies of local nodes.
+    let mut renamed = BTreeMap::new();
+    let mut target = Tree::new();
+
+    let paths: BTreeSet<&String> = local
+        .keys()
+        .chain(remote.keys())
+        .chain(base.keys())
+        .collect();
+    for path in paths {
+        let l = local.get(path);
+        let r = remote.get(path);
+        let b = base.get(path);
+        let chosen = if l.map(|e| &e.item) == r.map(|e| &e.item) {
+            l
+        } else if l.map(|e| &e.item) == b {
+            r
+        } else if r.map(|e| &e.item) == b {
+            l
+        } else {
+            match (l, r) {
+                (Some(l), Some(r)) => {
+                    let copy = conflict_path(path, &mut taken);
+                    target.insert(copy.clone(), l.clone());
+                    report.conflicts.push(copy.clone());
+                    renamed.insert(path.clone(), copy);
+                    Some(r)
+                }
+                (l, None) => l,
+                (None, r) => r,
+            }
+        };
+        if let Some(entry) = chosen {
+            target.insert(path.clone(), entry.clone());
+        }
+    }
+
+    // Nodes kept on one side may now be below a file or a missing directory,
+    // e.g. after a conflict between a directory and a file. They are moved
+    // into the conflict copy, and missing directories are created.
+    let mut fixed = Tree::new();
+    for (path, entry) in target {
+        let parts: Vec<&str> = path.split('/').collect();
+        let mut new_path = String::new();
+        for part in &parts[..parts.len() - 1] {
+            if !new_path.is_empty() {
+                new_path.push('/');
+            }
+            new_path.push_str(part);
+            match fixed.get(&new_path).map(Entry::is_directory) {
+                Some(true) => {}
+                Some(false) => {
+                    new_path = renamed
+                        .entry(new_path.clone())
+                        .or_insert_with(|| conflict_path(&new_path, &mut taken))
+                        .clone();
+                    if !fixed.get(&new_path).map_or(false, Entry::is_directory)
+                    {
+                        fixed.insert(new_path.clone(), Entry::directory());
+                        if !report.conflicts.contains(&new_path) {
+                            report.conflicts.push(new_path.clone());
+                        }
+                    }
+                }
+                None => {
+                    fixed.insert(new_path.clone(), Entry::directory());
+                }
+            }
+        }
+        let name = parts[parts.len() - 1];
+        if new_path.is_empty() {
+            fixed.insert(String::from(name), entry);
+        } else {
+            fixed.insert(format!("{}/{}", new_path, name), entry);
+        }
+    }
+    fixed
+}
+
+/// Returns an unused path for the conflict copy of the node at `path`.
+fn conflict_path(path: &str, taken: &mut BTreeSet<String>) -> String {
+    let time = chrono::Utc::now().format("%Y-%m-%d %H%M%S");
+    let mut copy = format!("{} (conflict {})", path, time);
+    let mut counter = 2;
+    while taken.contains(&copy) {
+        copy = format!("{} (conflict {} {})", path, time, counter);
+        counter += 1;
+    }
+    taken.insert(copy.clone());
+    copy
+}
+
+fn local_path(dir: &Path, path: &str, entry: &Entry) -> PathBuf {
+    let mut local = dir.to_path_buf();
+    local.extend(path.split('/'));
+    if entry.is_directory() {
+        local
+    } else {
+        local.with_file_name(format!(
+            "{}{}",
+            path.rsplit('/').next().unwrap_or(path),
+            NOTE_EXTENSION
+        ))
+    }
+}
+
+fn apply_local(
+    dir: &Path,
+    local: &Tree,
+    target: &Tree,
+    report: &mut SyncReport,
+) -> SyncResult<()> {
+    // Children are removed before their parents.
+    for (path, entry) in local.iter().rev() {
+        let keep = target
+            .get(path)
+            .map_or(false, |t| t.is_directory() == entry.is_directory());
+        if keep {
+            continue;
+        }
+        let file = local_path(dir, path, entry);
+        if entry.is_directory() {
+            // The synced entries in it are removed already, anything left is
+            // not synced and must not be lost.
+            if fs::read_dir(&file)?.next().is_some() {
+                report.kept.push(path.clone());
+                continue;
+            }
+            fs::remove_dir(file)?;
+        } else {
+            check_unchanged(&file, entry)?;
+            fs::remove_file(file)?;
+        }
+        report.pulled.push(path.clone());
+    }
+
+    for (path, entry) in target {
+        let file = local_path(dir, path, entry);
+        match local.get(path) {
+            Some(l) if l.item == entry.item => continue,
+            Some(l) if !l.is_directory() && !entry.is_directory() => {
+                check_unchanged(&file, l)?;
+            }
+            _ => {}
+        }
+        match &entry.content {
+            Some(content) => write_atomically(&file, content)?,
+            None => fs::create_dir_all(file)?,
+        }
+        // New conflict copies are pushed, not pulled.
+        if !report.conflicts.contains(path) {
+            report.pulled.push(path.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Fails if the file was edited since it was scanned, so the edit is not
+/// overwritten. The next sync picks the edit up.
+fn check_unchanged(file: &Path, scanned: &Entry) -> SyncResult<()> {
+    let current = Entry::file(fs::read_to_string(file)?);
+    if current.item != scanned.item {
+        return Err(SyncError::Changed(file.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Writes the file next to its destination first, so editors never see a
+/// partially written file.
+fn write_atomically(file: &Path, content: &str) -> SyncResult<()> {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_file = file.with_file_name(format!(".{}.mnsync-tmp", name));
+    fs::write(&tmp_file, content)?;
+    fs::rename(tmp_file, file)?;
+    Ok(())
+}
+
+fn apply_remote(
+    client: &mut Client,
+    remote_tree: &Tree,
+    target: &Tree,
+    report: &mut SyncReport,
+) -> SyncResult<()> {
+    // Children are deleted before their parents.
+    for (path, entry) in remote_tree.iter().rev() {
+        let keep = target
+            .get(path)
+            .map_or(false, |t| t.is_directory() == entry.is_directory());
+        if !keep {
+            client.delete(&split(path))?;
+            report.pushed.push(path.clone());
+        }
+    }
+
+    for (path, entry) in target {
+        let parts = split(path);
+        match remote_tree.get(path) {
+            Some(r) if r.item == entry.item => continue,
+            Some(r) if !r.is_directory() && !entry.is_directory() => {
+                client.change_content(
+                    &parts,
+                    entry.content.as_deref().unwrap_or_default(),
+                )?;
+            }
+            _ => {
+                let (name, parent) = parts.split_last().unwrap();
+                let node = NewNodePayload {
+                    name: name.clone(),
+                    is_directory: entry.is_directory(),
+                    content: entry.content.clone(),
+                };
+                client.create_node(parent, node)?;
+            }
+        }
+        if !report.pushed.contains(path) {
+            report.pushed.push(path.clone());
+        }
+    }
+    Ok(())
+}
+
+fn split(path: &str) -> Vec<NodeName> {
+    path.split('/').map(String::from).collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
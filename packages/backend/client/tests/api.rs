@@ -0,0 +1,135 @@
+//! Tests the client against a backend listening on a local port.
+
+mod common;
+
+use backend::jwt;
+use backend::models::User;
+use client::{Auth, Client, Error, ErrorCode, NewNodePayload};
+use common::{start_backend, Backend, PASSWORD, SECRET, USERNAME};
+
+fn login(backend: &Backend, password: &str) -> Client {
+    let auth = Auth::Password {
+        username: String::from(USERNAME),
+        password: String::from(password),
+    };
+    Client::new(&backend.url, auth)
+}
+
+fn path(parts: &[&str]) -> Vec<String> {
+    parts.iter().map(|part| String::from(*part)).collect()
+}
+
+fn directory(name: &str) -> NewNodePayload {
+    NewNodePayload {
+        name: String::from(name),
+        is_directory: true,
+        content: None,
+    }
+}
+
+fn assert_code<T: std::fmt::Debug>(result: client::Result<T>, code: ErrorCode) {
+    match result {
+        Err(err) => assert_eq!(err.code(), Some(&code), "{}", err),
+        Ok(value) => panic!("expected {}, got {:?}", code, value),
+    }
+}
+
+#[test]
+fn it_logs_in_and_fetches_the_profile() {
+    let backend = start_backend();
+
+    let mut client = login(&backend, PASSWORD);
+    assert!(client.token().is_none());
+    let user = client.profile().unwrap();
+    assert_eq!(user.id, backend.user_id);
+    assert_eq!(user.username, USERNAME);
+    assert!(client.token().is_some());
+
+    let mut client = login(&backend, "wrong password");
+    match client.profile() {
+        Err(Error::Api { status, code, .. }) => {
+            assert_eq!(status, 401);
+            assert_eq!(code, ErrorCode::InvalidCredentials);
+        }
+        Err(err) => panic!("expected invalid credentials, got {}", err),
+        Ok(user) => panic!("expected invalid credentials, got {}", user.id),
+    }
+
+    let mut client =
+        Client::new(&backend.url, Auth::Token(String::from("mnpat_unknown")));
+    assert_code(client.nodes(), ErrorCode::TokenInvalid);
+}
+
+#[test]
+fn it_manages_nodes() {
+    let backend = start_backend();
+    let mut client = login(&backend, PASSWORD);
+
+    let projects = client.create_node(&[], directory("Projects")).unwrap();
+    client.create_node(&[], directory("Archive")).unwrap();
+    let todo = client
+        .create_node(
+            &path(&["Projects"]),
+            NewNodePayload {
+                name: String::from("Todo"),
+                is_directory: false,
+                content: Some(String::from("- [ ] client")),
+            },
+        )
+        .unwrap();
+    assert_eq!(todo.parent_id, Some(projects.node_id));
+    assert_code(
+        client.create_node(&[], directory("Projects")),
+        ErrorCode::NameConflict,
+    );
+
+    let changed = client
+        .change_content(&path(&["Projects", "Todo"]), "- [x] client")
+        .unwrap();
+    assert_eq!(changed.content.as_deref(), Some("- [x] client"));
+    let renamed = client.rename(&path(&["Projects", "Todo"]), "Done").unwrap();
+    assert_eq!(renamed.node_id, todo.node_id);
+    assert_code(
+        client.rename(&path(&["Projects", "Done"]), "a/b"),
+        ErrorCode::NodeNameInvalid,
+    );
+
+    let moved = client
+        .move_node(&path(&["Projects", "Done"]), &path(&["Archive"]))
+        .unwrap();
+    assert_eq!(moved.old_path, path(&["Projects", "Done"]));
+    assert_eq!(moved.new_path, path(&["Archive", "Done"]));
+    assert_code(
+        client.move_node(&path(&["Archive"]), &path(&["Archive", "Done"])),
+        ErrorCode::InvalidValue,
+    );
+
+    client.delete(&path(&["Archive"])).unwrap();
+    assert_code(client.delete(&path(&["Archive"])), ErrorCode::NotFound);
+    let nodes = client.nodes().unwrap();
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(nodes[0].node_name, "Projects");
+}
+
+#[test]
+fn it_logs_in_again_once_the_token_expired() {
+    let backend = start_backend();
+    let expired_config = jwt::Config::with_secret(SECRET, -100, 0);
+    let user = User {
+        id: backend.user_id,
+        username: String::from(USERNAME),
+        password_hash: String::new(),
+    };
+    let expired = jwt::Claims::from_user(user, &expired_config)
+        .to_token(&expired_config)
+        .unwrap();
+
+    let mut client = login(&backend, PASSWORD);
+    client.set_token(expired.clone());
+    assert!(client.nodes().unwrap().is_empty());
+    assert_ne!(client.token(), Some(expired.as_str()));
+
+    // Access tokens can not be refreshed.
+    let mut client = Client::new(&backend.url, Auth::Token(expired));
+    assert_code(client.nodes(), ErrorCode::TokenExpired);
+}
@@ -0,0 +1,84 @@
+//! Starts a backend for the tests, listening on a local port with its database
+//! in a temporary sqlite file.
+
+// Not every test uses every item.
+#![allow(dead_code)]
+
+use rocket::config::{Config, Environment};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+use backend::models::LockoutConfig;
+use backend::passwords::PasswordConfig;
+use backend::store::{DieselNodeStore, NodeStore};
+use backend::{api, database, jwt, user_management};
+
+pub const USERNAME: &str = "alice";
+pub const PASSWORD: &str = "client-test-password-4711";
+pub const SECRET: &str = "client test secret";
+
+pub struct Backend {
+    pub url: String,
+    pub user_id: i32,
+    /// Contains the database and is removed once the test is done.
+    _dir: TempDir,
+}
+
+/// Starts a backend with the user `alice` and no nodes.
+pub fn start_backend() -> Backend {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let dir = TempDir::new().unwrap();
+    let database = dir.path().join("backend.sqlite");
+
+    let pool = database::create_pool(database.to_str().unwrap()).unwrap();
+    let conn = pool.get().unwrap();
+    database::run_migrations(&conn).unwrap();
+    let user = user_management::create(
+        &conn,
+        &PasswordConfig::default(),
+        USERNAME,
+        PASSWORD,
+    )
+    .unwrap();
+    drop(conn);
+
+    thread::spawn(move || {
+        let config = Config::build(Environment::Development)
+            .address("127.0.0.1")
+            .port(port)
+            .finalize()
+            .unwrap();
+        let read_pool = database::DbReadPool::shared(&pool);
+        rocket::custom(config)
+            .manage(jwt::Config::with_secret(SECRET, 100, 0))
+            .manage(LockoutConfig::default())
+            .manage(PasswordConfig::default())
+            .manage(Box::new(DieselNodeStore::new(
+                pool.clone(),
+                read_pool.clone(),
+            )) as Box<dyn NodeStore>)
+            .manage(read_pool)
+            .manage(pool)
+            .mount("/api/v1", api::v1::get_routes())
+            .register(api::catchers::get_catchers())
+            .launch();
+    });
+
+    for _ in 0..100 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return Backend {
+                url: format!("http://127.0.0.1:{}", port),
+                user_id: user.id,
+                _dir: dir,
+            };
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("The backend did not start");
+}
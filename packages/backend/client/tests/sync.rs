@@ -0,0 +1,136 @@
+//! Tests the sync client end to end against a backend listening on a local
+//! port, with two directories standing in for two machines of the same user.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+use client::sync::{self, SyncReport};
+use client::{Auth, Client, NewNodePayload};
+use common::{start_backend, PASSWORD, USERNAME};
+
+fn login(server_url: &str) -> Client {
+    let auth = Auth::Password {
+        username: String::from(USERNAME),
+        password: String::from(PASSWORD),
+    };
+    Client::new(server_url, auth)
+}
+
+/// Creates the file `Projects/Todo` on the server.
+fn create_nodes(server_url: &str) {
+    let mut client = login(server_url);
+    let projects = NewNodePayload {
+        name: String::from("Projects"),
+        is_directory: true,
+        content: None,
+    };
+    let todo = NewNodePayload {
+        name: String::from("Todo"),
+        is_directory: false,
+        content: Some(String::from("- [ ] sync")),
+    };
+    client.create_node(&[], projects).unwrap();
+    client
+        .create_node(&[String::from("Projects")], todo)
+        .unwrap();
+}
+
+struct Machine {
+    dir: TempDir,
+    server_url: String,
+    client: Client,
+}
+
+impl Machine {
+    fn new(server_url: &str) -> Machine {
+        Machine {
+            dir: TempDir::new().unwrap(),
+            server_url: String::from(server_url),
+            client: login(server_url),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    fn sync(&mut self) -> SyncReport {
+        sync::sync(
+            self.dir.path(),
+            &self.server_url,
+            USERNAME,
+            &mut self.client,
+        )
+        .unwrap()
+    }
+
+    fn read(&self, path: &str) -> Option<String> {
+        fs::read_to_string(self.path().join(path)).ok()
+    }
+
+    fn write(&self, path: &str, content: &str) {
+        fs::write(self.path().join(path), content).unwrap();
+    }
+}
+
+#[test]
+fn it_syncs_two_directories_through_the_server() {
+    let backend = start_backend();
+    create_nodes(&backend.url);
+    let mut a = Machine::new(&backend.url);
+    let mut b = Machine::new(&backend.url);
+
+    // The first sync pulls the notebook.
+    let report = a.sync();
+    assert_eq!(report.pulled, vec!["Projects", "Projects/Todo"]);
+    assert_eq!(a.read("Projects/Todo.md").unwrap(), "- [ ] sync");
+    assert!(a.path().join(sync::MANIFEST_FILE).exists());
+
+    // Local edits are pushed and pulled by the other machine.
+    a.write("Projects/Todo.md", "- [x] sync");
+    a.write("Ideas.md", "idea");
+    a.write("notes.txt", "not a note");
+    let report = a.sync();
+    assert_eq!(report.pushed, vec!["Ideas", "Projects/Todo"]);
+    b.sync();
+    assert_eq!(b.read("Projects/Todo.md").unwrap(), "- [x] sync");
+    assert_eq!(b.read("Ideas.md").unwrap(), "idea");
+    assert!(b.read("notes.txt").is_none());
+
+    // Concurrent edits keep the first pushed version and a conflict copy of
+    // the other one.
+    a.write("Projects/Todo.md", "from a");
+    b.write("Projects/Todo.md", "from b");
+    a.sync();
+    let report = b.sync();
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(b.read("Projects/Todo.md").unwrap(), "from a");
+    let copy = format!("{}.md", report.conflicts[0]);
+    assert!(copy.starts_with("Projects/Todo (conflict "));
+    assert_eq!(b.read(&copy).unwrap(), "from b");
+    a.sync();
+    assert_eq!(a.read(&copy).unwrap(), "from b");
+
+    // Deletions are synced, too.
+    fs::remove_file(a.path().join("Ideas.md")).unwrap();
+    a.sync();
+    let report = b.sync();
+    assert_eq!(report.pulled, vec!["Ideas"]);
+    assert!(b.read("Ideas.md").is_none());
+
+    // A directory deleted on the server keeps the files that are not synced.
+    a.write("Projects/image.png", "png");
+    fs::remove_dir_all(b.path().join("Projects")).unwrap();
+    b.sync();
+    let report = a.sync();
+    assert_eq!(report.kept, vec!["Projects"]);
+    assert!(a.read("Projects/Todo.md").is_none());
+    assert_eq!(a.read("Projects/image.png").unwrap(), "png");
+    assert!(!b.path().join("Projects").exists());
+
+    assert!(a.sync().is_empty());
+    assert!(b.sync().is_empty());
+}
@@ -0,0 +1,17 @@
+-- Personal access tokens allow scripts to authenticate without knowing the
+-- password of a user. Only the SHA-256 hash of a token is stored. Timestamps
+-- are unix timestamps in seconds.
+create table access_tokens
+(
+    id           integer primary key not null,
+    user_id      integer             not null references users (id) on delete cascade,
+    token_name   varchar(255)        not null,
+    token_hash   text                not null unique,
+    -- Comma separated list of scopes, e.g. "read,write".
+    scopes       text                not null,
+    created_at   bigint              not null,
+    last_used_at bigint,
+    expires_at   bigint,
+
+    unique (user_id, token_name)
+);
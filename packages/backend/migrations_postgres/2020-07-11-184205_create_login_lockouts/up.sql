@@ -0,0 +1,14 @@
+-- Failed login attempts, tracked per username and per client ip address.
+-- Timestamps are unix timestamps in seconds.
+create table login_lockouts
+(
+    id              serial primary key  not null,
+    -- Either "username" or "ip".
+    lockout_kind    varchar(16)         not null,
+    lockout_key     varchar(255)        not null,
+    failed_attempts integer             not null,
+    last_failed_at  bigint              not null,
+    locked_until    bigint,
+
+    unique (lockout_kind, lockout_key)
+);
@@ -0,0 +1,68 @@
+//! Error catchers returning the same json body as `BackendError`, so clients
+//! can rely on a single error format even if a request fails before reaching
+//! a handler, e.g. because of a missing token or a malformed body.
+
+use rocket::{catch, catchers, Catcher, Request};
+use rocket_contrib::json::Json;
+
+use crate::errors::{codes, ErrorBody};
+use crate::jwt::AuthFailure;
+
+fn error_body(code: &'static str, message: &str) -> Json<ErrorBody> {
+    Json(ErrorBody {
+        code: String::from(code),
+        message: String::from(message),
+        details: None,
+    })
+}
+
+/// Returns the body describing why an authentication guard failed, if one did.
+fn auth_failure(req: &Request) -> Option<Json<ErrorBody>> {
+    let AuthFailure(failure) = req.local_cache(|| AuthFailure(None));
+    failure.map(|err| error_body(err.code(), err.message()))
+}
+
+#[catch(400)]
+fn bad_request(req: &Request) -> Json<ErrorBody> {
+    auth_failure(req)
+        .unwrap_or_else(|| error_body(codes::BAD_REQUEST, "Malformed request"))
+}
+
+#[catch(401)]
+fn unauthorized(req: &Request) -> Json<ErrorBody> {
+    auth_failure(req).unwrap_or_else(|| {
+        error_body(codes::INVALID_CREDENTIALS, "Invalid credentials")
+    })
+}
+
+#[catch(403)]
+fn forbidden(req: &Request) -> Json<ErrorBody> {
+    auth_failure(req)
+        .unwrap_or_else(|| error_body(codes::FORBIDDEN, "Forbidden"))
+}
+
+#[catch(404)]
+fn not_found() -> Json<ErrorBody> {
+    error_body(codes::NOT_FOUND, "Not found")
+}
+
+#[catch(422)]
+fn unprocessable_entity() -> Json<ErrorBody> {
+    error_body(codes::INVALID_VALUE, "The request body is invalid")
+}
+
+#[catch(500)]
+fn internal_error() -> Json<ErrorBody> {
+    error_body(codes::INTERNAL_ERROR, "Internal server error")
+}
+
+pub fn get_catchers() -> Vec<Catcher> {
+    catchers![
+        bad_request,
+        unauthorized,
+        forbidden,
+        not_found,
+        unprocessable_entity,
+        internal_error
+    ]
+}
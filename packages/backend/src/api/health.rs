@@ -0,0 +1,31 @@
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::{self, get, State};
+use rocket_contrib::json::{Json, JsonValue};
+use serde_json::json;
+
+use crate::database::DbReadPool;
+use crate::health::{self, HealthConfig, Readiness};
+
+/// Responds as long as the backend handles requests at all.
+#[get("/live")]
+pub fn live() -> JsonValue {
+    JsonValue(json!({ "status": "ok" }))
+}
+
+/// Responds with 503 and the failed checks if the backend can not handle
+/// requests, e.g. because the database is not usable.
+#[get("/ready")]
+pub fn ready(
+    pool: State<DbReadPool>,
+    cfg: State<HealthConfig>,
+) -> Custom<Json<Readiness>> {
+    let readiness = health::check_readiness(&pool, &cfg);
+    let status = if readiness.is_ready() {
+        Status::Ok
+    } else {
+        log::warn!("Not ready: {:?}", readiness);
+        Status::ServiceUnavailable
+    };
+    Custom(status, Json(readiness))
+}
@@ -0,0 +1,23 @@
+use rocket::http::ContentType;
+use rocket::response::Content;
+use rocket::{self, get, State};
+
+use crate::database::DbReadPool;
+use crate::metrics::Metrics;
+use crate::{BackendResult, DbConnectionPool};
+
+/// Exposes the metrics in the Prometheus text format. Only mounted with
+/// `metrics.enabled` and not below `/api`, so it is not reachable through the
+/// load balancer. Requires no authentication.
+#[get("/metrics")]
+pub fn metrics(
+    metrics: State<Metrics>,
+    pool: State<DbConnectionPool>,
+    read_pool: State<DbReadPool>,
+) -> BackendResult<Content<String>> {
+    let text = metrics.gather(&pool, &read_pool)?;
+    Ok(Content(
+        ContentType::with_params("text", "plain", ("version", "0.0.4")),
+        text,
+    ))
+}
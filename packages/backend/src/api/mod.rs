@@ -1 +1,5 @@
+pub mod catchers;
+pub mod health;
+pub mod metrics;
 pub mod v1;
+pub mod v2;
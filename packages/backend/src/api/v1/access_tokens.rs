@@ -0,0 +1,76 @@
+use rocket::{self, delete, get, post, State};
+use rocket_contrib::json::Json;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::database::{retry_on_busy, DbReadPool};
+use crate::models::{AccessToken, AccessTokenId, Scope};
+use crate::{jwt, BackendError, BackendResult, DbConnectionPool};
+
+/// Lists all personal access tokens of the user.
+#[get("/user/tokens")]
+pub fn get_tokens(
+    session: jwt::Session,
+    pool: State<DbReadPool>,
+) -> BackendResult<Json<Vec<AccessToken>>> {
+    let conn = pool.get()?;
+    let tokens = AccessToken::fetch_all_for_user(&conn, session.id())?;
+    Ok(Json(tokens))
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateTokenPayload {
+    name: String,
+    scopes: Vec<Scope>,
+    /// Unix timestamp in seconds after which the token is no longer valid.
+    expires_at: Option<i64>,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateTokenResponse {
+    /// The actual token. This is the only time it is revealed.
+    token: String,
+    access_token: AccessToken,
+}
+
+/// Creates a new personal access token. The `admin` scope cannot be granted
+/// through the api, only with `mnctl token create`.
+#[post("/user/tokens", data = "<payload>")]
+pub fn create_token(
+    session: jwt::Session,
+    pool: State<DbConnectionPool>,
+    payload: Json<CreateTokenPayload>,
+) -> BackendResult<Json<CreateTokenResponse>> {
+    if payload.scopes.contains(&Scope::Admin) {
+        return Err(BackendError::Forbidden);
+    }
+
+    let conn = pool.get()?;
+    let (access_token, token) = retry_on_busy(|| {
+        AccessToken::create(
+            &conn,
+            session.id(),
+            &payload.name,
+            &payload.scopes,
+            payload.expires_at,
+        )
+    })?;
+    Ok(Json(CreateTokenResponse {
+        token,
+        access_token,
+    }))
+}
+
+/// Revokes a personal access token of the user.
+#[delete("/user/tokens/<id>")]
+pub fn revoke_token(
+    session: jwt::Session,
+    pool: State<DbConnectionPool>,
+    id: AccessTokenId,
+) -> BackendResult<()> {
+    let conn = pool.get()?;
+    retry_on_busy(|| AccessToken::revoke(&conn, session.id(), id))?;
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use rocket::{self, delete, get, State};
+use rocket_contrib::json::Json;
+
+use crate::database::{retry_on_busy, DbReadPool};
+use crate::models::{LoginLockout, LoginLockoutId};
+use crate::{jwt, BackendResult, DbConnectionPool};
+
+/// Lists all usernames and ip addresses with failed login attempts, including
+/// the ones that are currently locked out.
+#[get("/admin/lockouts")]
+pub fn get_lockouts(
+    _admin: jwt::AdminAccess,
+    pool: State<DbReadPool>,
+) -> BackendResult<Json<Vec<LoginLockout>>> {
+    let conn = pool.get()?;
+    let lockouts = LoginLockout::fetch_all(&conn)?;
+    Ok(Json(lockouts))
+}
+
+/// Forgets the failed login attempts of a username or ip address, which lifts
+/// a lockout immediately.
+#[delete("/admin/lockouts/<id>")]
+pub fn clear_lockout(
+    _admin: jwt::AdminAccess,
+    pool: State<DbConnectionPool>,
+    id: LoginLockoutId,
+) -> BackendResult<()> {
+    let conn = pool.get()?;
+    retry_on_busy(|| LoginLockout::delete_by_id(&conn, id))?;
+    Ok(())
+}
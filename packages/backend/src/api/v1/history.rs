@@ -0,0 +1,34 @@
+use rocket::http::{ContentType, Status};
+use rocket::response::Responder;
+use rocket::{self, get, Request, Response, State};
+use std::io::Cursor;
+
+use crate::history::History;
+use crate::{jwt, BackendError, BackendResult};
+
+/// A git bundle, sent as a file download.
+pub struct Bundle(Vec<u8>);
+
+impl Responder<'static> for Bundle {
+    fn respond_to(self, _: &Request) -> Result<Response<'static>, Status> {
+        Response::build()
+            .header(ContentType::Binary)
+            .raw_header(
+                "Content-Disposition",
+                "attachment; filename=\"notebook.bundle\"",
+            )
+            .sized_body(Cursor::new(self.0))
+            .ok()
+    }
+}
+
+/// Responds the history of the user as a git bundle. Not found if the history
+/// is disabled or empty.
+#[get("/history/bundle")]
+pub fn bundle(
+    claims: jwt::ReadAccess,
+    history: Option<State<History>>,
+) -> BackendResult<Bundle> {
+    let history = history.ok_or(BackendError::NotFound)?;
+    Ok(Bundle(history.bundle(claims.id())?))
+}
@@ -0,0 +1,13 @@
+use jsonwebtoken::jwk::JwkSet;
+use rocket::{self, get, State};
+use rocket_contrib::json::Json;
+
+use crate::jwt;
+
+/// Publishes the public keys JWTs are verified with, so other services can
+/// verify our JWTs without knowing a secret. Empty if JWTs are signed with a
+/// shared secret.
+#[get("/jwks.json")]
+pub fn jwks(jwt_cfg: State<jwt::Config>) -> Json<JwkSet> {
+    Json(jwt_cfg.jwks())
+}
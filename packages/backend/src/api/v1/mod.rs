@@ -1,10 +1,29 @@
+mod access_tokens;
+mod admin;
+mod history;
+mod jwks;
 mod nodes;
+mod openapi;
 mod users;
 
 use rocket::{routes, Route};
 
+// The request and response types, shared with clients of the api.
+pub use nodes::{
+    ChangeNodeContent, ChangeNodeName, ChangeParentPayload,
+    ChangeParentResponse, CreateNodePayload, DeleteNode,
+};
+pub use users::{AuthResponse, Credentials};
+
 pub fn get_routes() -> Vec<Route> {
     routes![
+        access_tokens::create_token,
+        access_tokens::get_tokens,
+        access_tokens::revoke_token,
+        admin::clear_lockout,
+        admin::get_lockouts,
+        history::bundle,
+        jwks::jwks,
         users::auth,
         users::profile,
         nodes::change_content,
@@ -12,6 +31,7 @@ pub fn get_routes() -> Vec<Route> {
         nodes::change_parent,
         nodes::create_node,
         nodes::delete,
-        nodes::get_nodes
+        nodes::get_nodes,
+        openapi::openapi
     ]
 }
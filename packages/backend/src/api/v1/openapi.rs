@@ -0,0 +1,476 @@
+//! The OpenAPI 3 document of the v1 api, served at `/api/v1/openapi.json`.
+//!
+//! The paths and their parameters are generated from the routes returned by
+//! `get_routes`. Each handler is listed here with a summary and its signature,
+//! which has to match the handler to compile. The required authorization and
+//! the schemas of the request and response are read from the types of the
+//! signature. The tests fail if a route is missing from that list or vice
+//! versa.
+
+use jsonwebtoken::jwk::JwkSet;
+use rocket::{self, get, State};
+use rocket_contrib::json::Json;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+use super::history::Bundle;
+use super::users::ClientIp;
+use super::{access_tokens, admin, get_routes, history, jwks, nodes, users};
+use crate::database::DbReadPool;
+use crate::errors::{BackendResult, ErrorBody};
+use crate::history::History;
+use crate::models::{
+    AccessToken, AccessTokenId, LockoutConfig, LoginLockout, LoginLockoutId,
+    Node, Scope, User,
+};
+use crate::passwords::PasswordConfig;
+use crate::store::NodeStore;
+use crate::{jwt, DbConnectionPool};
+
+/// What a route requires in the `Authorization` header.
+#[derive(Clone, Copy, Debug)]
+enum Auth {
+    None,
+    /// A JWT from `/user/auth`, access tokens are rejected.
+    Session,
+    /// A JWT or an access token with the scope.
+    Scope(&'static str),
+}
+
+/// The body of a request or a successful response.
+enum Body {
+    Empty,
+    Json(Box<Schema>),
+    Binary,
+}
+
+struct Operation {
+    summary: &'static str,
+    auth: Auth,
+    request: Body,
+    response: Body,
+}
+
+fn json<T: JsonSchema>(gen: &mut SchemaGenerator) -> Body {
+    Body::Json(Box::new(gen.subschema_for::<T>()))
+}
+
+/// What an argument of a handler adds to the description of its route.
+trait Argument {
+    fn auth() -> Option<Auth> {
+        None
+    }
+
+    fn body(_gen: &mut SchemaGenerator) -> Option<Body> {
+        None
+    }
+}
+
+impl Argument for jwt::Session {
+    fn auth() -> Option<Auth> {
+        Some(Auth::Session)
+    }
+}
+
+impl Argument for jwt::ReadAccess {
+    fn auth() -> Option<Auth> {
+        Some(Auth::Scope(Scope::Read.as_str()))
+    }
+}
+
+impl Argument for jwt::WriteAccess {
+    fn auth() -> Option<Auth> {
+        Some(Auth::Scope(Scope::Write.as_str()))
+    }
+}
+
+impl Argument for jwt::AdminAccess {
+    fn auth() -> Option<Auth> {
+        Some(Auth::Scope(Scope::Admin.as_str()))
+    }
+}
+
+impl Argument for ClientIp {}
+
+impl<T: Send + Sync + 'static> Argument for State<'_, T> {}
+
+impl<T: Send + Sync + 'static> Argument for Option<State<'_, T>> {}
+
+/// The ids in the path, which are described by `path_item`.
+impl Argument for i32 {}
+
+impl<T: JsonSchema> Argument for Json<T> {
+    fn body(gen: &mut SchemaGenerator) -> Option<Body> {
+        Some(json::<T>(gen))
+    }
+}
+
+/// The body of a successful response of a handler.
+trait Response {
+    fn body(gen: &mut SchemaGenerator) -> Body;
+}
+
+impl Response for () {
+    fn body(_gen: &mut SchemaGenerator) -> Body {
+        Body::Empty
+    }
+}
+
+impl Response for Bundle {
+    fn body(_gen: &mut SchemaGenerator) -> Body {
+        Body::Binary
+    }
+}
+
+impl<T: JsonSchema> Response for Json<T> {
+    fn body(gen: &mut SchemaGenerator) -> Body {
+        json::<T>(gen)
+    }
+}
+
+impl<T: Response> Response for BackendResult<T> {
+    fn body(gen: &mut SchemaGenerator) -> Body {
+        T::body(gen)
+    }
+}
+
+/// Describes a handler by its name and signature, which is checked against
+/// the handler. A response type without a schema is described by the type
+/// after `as`.
+macro_rules! operation {
+    (@response $response:ty) => { $response };
+    (@response $response:ty, $schema:ty) => { $schema };
+    (
+        $gen:ident,
+        $summary:expr,
+        $($path:ident)::+($($arg:ty),* $(,)?) -> $response:ty $(as $schema:ty)?
+    ) => {{
+        let _: fn($($arg),*) -> $response = $($path)::+;
+        let name = *[$(stringify!($path)),+].last().unwrap();
+        let auth = None$(.or_else(<$arg as Argument>::auth))*;
+        let request = None$(.or_else(|| <$arg as Argument>::body($gen)))*;
+        let operation = Operation {
+            summary: $summary,
+            auth: auth.unwrap_or(Auth::None),
+            request: request.unwrap_or(Body::Empty),
+            response: <operation!(@response $response $(, $schema)?)
+                as Response>::body($gen),
+        };
+        (name, operation)
+    }};
+}
+
+/// Describes the handlers of the v1 routes by their names.
+fn operations(gen: &mut SchemaGenerator) -> Vec<(&'static str, Operation)> {
+    vec![
+        operation! {
+            gen,
+            "Log in and get a JWT",
+            users::auth(
+                State<jwt::Config>,
+                State<LockoutConfig>,
+                State<PasswordConfig>,
+                ClientIp,
+                Json<users::Credentials>,
+                State<DbReadPool>,
+                State<DbConnectionPool>
+            ) -> BackendResult<Json<users::AuthResponse>>
+        },
+        operation! {
+            gen,
+            "Get the logged in user",
+            users::profile(jwt::ReadAccess, State<DbReadPool>)
+                -> BackendResult<Json<User>>
+        },
+        operation! {
+            gen,
+            "List the access tokens of the user",
+            access_tokens::get_tokens(jwt::Session, State<DbReadPool>)
+                -> BackendResult<Json<Vec<AccessToken>>>
+        },
+        operation! {
+            gen,
+            "Create an access token",
+            access_tokens::create_token(
+                jwt::Session,
+                State<DbConnectionPool>,
+                Json<access_tokens::CreateTokenPayload>
+            ) -> BackendResult<Json<access_tokens::CreateTokenResponse>>
+        },
+        operation! {
+            gen,
+            "Revoke an access token",
+            access_tokens::revoke_token(
+                jwt::Session,
+                State<DbConnectionPool>,
+                AccessTokenId
+            ) -> BackendResult<()>
+        },
+        operation! {
+            gen,
+            "List the login lockouts",
+            admin::get_lockouts(jwt::AdminAccess, State<DbReadPool>)
+                -> BackendResult<Json<Vec<LoginLockout>>>
+        },
+        operation! {
+            gen,
+            "Lift a login lockout",
+            admin::clear_lockout(
+                jwt::AdminAccess,
+                State<DbConnectionPool>,
+                LoginLockoutId
+            ) -> BackendResult<()>
+        },
+        operation! {
+            gen,
+            "Download the history as a git bundle",
+            history::bundle(jwt::ReadAccess, Option<State<History>>)
+                -> BackendResult<Bundle>
+        },
+        operation! {
+            gen,
+            "Get the public keys the JWTs are signed with",
+            jwks::jwks(State<jwt::Config>) -> Json<JwkSet> as Json<Value>
+        },
+        operation! {
+            gen,
+            "List all nodes of the user",
+            nodes::get_nodes(jwt::ReadAccess, State<Box<dyn NodeStore>>)
+                -> BackendResult<Json<Vec<Node>>>
+        },
+        operation! {
+            gen,
+            "Create a node",
+            nodes::create_node(
+                jwt::WriteAccess,
+                State<Box<dyn NodeStore>>,
+                Json<nodes::CreateNodePayload>
+            ) -> BackendResult<Json<Node>>
+        },
+        operation! {
+            gen,
+            "Change the content of a file",
+            nodes::change_content(
+                jwt::WriteAccess,
+                State<Box<dyn NodeStore>>,
+                Json<nodes::ChangeNodeContent>
+            ) -> BackendResult<Json<Node>>
+        },
+        operation! {
+            gen,
+            "Rename a node",
+            nodes::change_name(
+                jwt::WriteAccess,
+                State<Box<dyn NodeStore>>,
+                Json<nodes::ChangeNodeName>
+            ) -> BackendResult<Json<Node>>
+        },
+        operation! {
+            gen,
+            "Move a node into another directory",
+            nodes::change_parent(
+                jwt::WriteAccess,
+                State<Box<dyn NodeStore>>,
+                Json<nodes::ChangeParentPayload>
+            ) -> BackendResult<Json<nodes::ChangeParentResponse>>
+        },
+        operation! {
+            gen,
+            "Delete a node and everything below it",
+            nodes::delete(
+                jwt::WriteAccess,
+                State<Box<dyn NodeStore>>,
+                Json<nodes::DeleteNode>
+            ) -> BackendResult<()>
+        },
+        operation! {
+            gen,
+            "Get this document",
+            openapi() -> Json<Value>
+        },
+    ]
+}
+
+fn content(body: Body) -> Option<Value> {
+    match body {
+        Body::Empty => None,
+        Body::Json(schema) => {
+            Some(json!({ "application/json": { "schema": schema } }))
+        }
+        Body::Binary => Some(json!({
+            "application/octet-stream": {
+                "schema": { "type": "string", "format": "binary" }
+            }
+        })),
+    }
+}
+
+/// Converts a rocket path like `/user/tokens/<id>` to the OpenAPI path and
+/// its parameters. Every dynamic segment of the v1 routes is an id.
+fn path_item(path: &str) -> (String, Vec<Value>) {
+    let mut parameters = Vec::new();
+    let segments: Vec<String> = path
+        .split('/')
+        .map(|segment| {
+            if segment.starts_with('<') && segment.ends_with('>') {
+                let name = &segment[1..segment.len() - 1];
+                parameters.push(json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer", "format": "int32" }
+                }));
+                format!("{{{}}}", name)
+            } else {
+                String::from(segment)
+            }
+        })
+        .collect();
+    (segments.join("/"), parameters)
+}
+
+/// Builds the OpenAPI document of the v1 routes. Routes without an entry in
+/// `operations` are left out.
+pub fn document() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let error = gen.subschema_for::<ErrorBody>();
+    let mut operations = operations(&mut gen);
+
+    let mut paths = Map::new();
+    for route in get_routes() {
+        let name = route.name.unwrap_or_default();
+        let index = match operations.iter().position(|(n, _)| *n == name) {
+            Some(index) => index,
+            None => continue,
+        };
+        let (_, operation) = operations.swap_remove(index);
+        let (path, parameters) = path_item(route.uri.path());
+
+        let mut item = json!({
+            "operationId": name,
+            "summary": operation.summary,
+            "responses": {
+                "200": { "description": "Success" },
+                "default": {
+                    "description": "An error, see `code`",
+                    "content": { "application/json": { "schema": error } }
+                }
+            }
+        });
+        if !parameters.is_empty() {
+            item["parameters"] = Value::from(parameters);
+        }
+        item["security"] = match operation.auth {
+            Auth::None => json!([]),
+            Auth::Session => json!([{ "bearer": [] }]),
+            Auth::Scope(scope) => json!([{ "bearer": [scope] }]),
+        };
+        if let Some(content) = content(operation.request) {
+            item["requestBody"] =
+                json!({ "required": true, "content": content });
+        }
+        if let Some(content) = content(operation.response) {
+            item["responses"]["200"]["content"] = content;
+        }
+
+        let method = route.method.as_str().to_lowercase();
+        paths.entry(path).or_insert_with(|| json!({}))[method] = item;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Markdown Notebook",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "servers": [{ "url": "/api/v1" }],
+        "paths": paths,
+        "components": {
+            "schemas": gen.definitions(),
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" }
+            }
+        }
+    })
+}
+
+#[get("/openapi.json")]
+pub fn openapi() -> Json<Value> {
+    Json(document())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The prefix of the references to the schemas in `components`.
+    const SCHEMAS_PATH: &str = "#/components/schemas/";
+
+    /// Adds the names of the schemas referenced below `value` to `refs`.
+    fn collect_refs<'a>(value: &'a Value, refs: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    match (key.as_str(), value) {
+                        ("$ref", Value::String(reference)) => {
+                            refs.push(&reference[SCHEMAS_PATH.len()..])
+                        }
+                        _ => collect_refs(value, refs),
+                    }
+                }
+            }
+            Value::Array(values) => {
+                values.iter().for_each(|value| collect_refs(value, refs))
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn it_describes_every_route() {
+        let mut gen = SchemaSettings::openapi3().into_generator();
+        let mut names: Vec<&str> =
+            operations(&mut gen).iter().map(|(name, _)| *name).collect();
+        let document = document();
+
+        for route in get_routes() {
+            let name = route.name.unwrap_or_default();
+            let position = names.iter().position(|n| *n == name);
+            assert!(position.is_some(), "{} is not in the document", name);
+            names.remove(position.unwrap());
+
+            let (path, _) = path_item(route.uri.path());
+            let method = route.method.as_str().to_lowercase();
+            assert_eq!(document["paths"][&path][&method]["operationId"], name);
+        }
+        assert!(names.is_empty(), "{:?} are no routes", names);
+    }
+
+    #[test]
+    fn it_describes_the_payloads() {
+        let document = document();
+        let schemas = &document["components"]["schemas"];
+        let mut refs = Vec::new();
+        collect_refs(&document, &mut refs);
+        for reference in refs {
+            assert!(schemas.get(reference).is_some(), "{}", reference);
+        }
+
+        let node = &document["paths"]["/node/parent"]["put"];
+        assert_eq!(node["security"], json!([{ "bearer": ["write"] }]));
+        let tokens = &document["paths"]["/user/tokens"]["get"];
+        assert_eq!(tokens["security"], json!([{ "bearer": [] }]));
+        let payload = &schemas["ChangeParentPayload"];
+        assert_eq!(payload["required"], json!(["newParentPath", "nodePath"]));
+        assert_eq!(
+            document["paths"]["/user/tokens/{id}"]["delete"]["parameters"][0]
+                ["name"],
+            "id"
+        );
+        // Fields that are never serialized are not part of the schema.
+        assert!(schemas["Node"]["properties"]["parentIsDirectory"].is_null());
+        assert!(schemas["AccessToken"]["properties"]["tokenHash"].is_null());
+    }
+}
@@ -1,50 +1,112 @@
-use rocket::{self, get, post, State};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{self, get, post, Request, State};
 use rocket_contrib::json::Json;
-use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 
+use crate::config::ServerSection;
+use crate::database::{retry_on_busy, DbReadPool};
 use crate::errors::{BackendError, BackendResult};
 use crate::jwt;
-use crate::models::User;
+use crate::models::{
+    LockoutConfig, LockoutKind, LoginLockout, User, UserQueries,
+};
+use crate::passwords::PasswordConfig;
 use crate::{user_management, DbConnectionPool};
 
-#[derive(Deserialize)]
-pub struct Credentials {
-    username: String,
-    password: String,
-}
+pub use api_types::{AuthResponse, Credentials};
+
+/// Request guard for the ip address of the client. This is the address of the
+/// connection, unless the request comes from one of the trusted proxies in the
+/// server configuration, e.g. the load balancer, which passes the address of
+/// the client in the `X-Real-IP` header.
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
 
-#[derive(Serialize)]
-pub struct AuthResponse {
-    token: String,
+    fn from_request(req: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let remote = match req.remote() {
+            Some(remote) => remote.ip(),
+            None => return Outcome::Success(ClientIp(None)),
+        };
+        let trusted = match req.guard::<State<ServerSection>>() {
+            Outcome::Success(server) => {
+                server.trusted_proxies.contains(&remote)
+            }
+            _ => false,
+        };
+        if !trusted {
+            return Outcome::Success(ClientIp(Some(remote)));
+        }
+
+        let real_ip = req
+            .headers()
+            .get_one("X-Real-IP")
+            .and_then(|ip| ip.trim().parse().ok());
+        Outcome::Success(ClientIp(Some(real_ip.unwrap_or(remote))))
+    }
 }
 
-/// Handles the login process of a user.
+/// Handles the login process of a user. Failed attempts are counted per
+/// username and per client ip address, too many of them block further logins
+/// for a while. The writer connection is only taken to count or clear the
+/// attempts, not while the password is verified.
 #[post("/user/auth", data = "<credentials>")]
 pub fn auth(
     jwt_cfg: State<jwt::Config>,
+    lockout_cfg: State<LockoutConfig>,
+    password_cfg: State<PasswordConfig>,
+    client_ip: ClientIp,
     credentials: Json<Credentials>,
+    read_pool: State<DbReadPool>,
     pool: State<DbConnectionPool>,
 ) -> BackendResult<Json<AuthResponse>> {
-    let conn = pool.get()?;
+    let ip = client_ip.0.map(|ip| ip.to_string());
+    let mut keys = vec![(LockoutKind::Username, credentials.username.as_str())];
+    if let Some(ip) = &ip {
+        keys.push((LockoutKind::Ip, ip.as_str()));
+    }
+    LoginLockout::check(&read_pool.get()?, &keys)?;
+
     match user_management::check_user(
-        &conn,
+        &read_pool,
+        &pool,
+        &password_cfg,
         &credentials.username,
         &credentials.password,
     )? {
         Some(user) => {
+            let conn = pool.get()?;
+            // Only forget the attempts for the username. Otherwise an attacker
+            // with one valid account could reset the counter of their ip.
+            retry_on_busy(|| {
+                LoginLockout::clear(
+                    &conn,
+                    LockoutKind::Username,
+                    &credentials.username,
+                )
+            })?;
             let token =
                 jwt::Claims::from_user(user, &jwt_cfg).to_token(&jwt_cfg)?;
             Ok(Json(AuthResponse { token }))
         }
-        None => Err(BackendError::InvalidCredentials),
+        None => {
+            let conn = pool.get()?;
+            for (kind, key) in keys {
+                retry_on_busy(|| {
+                    LoginLockout::record_failure(&conn, &lockout_cfg, kind, key)
+                })?;
+            }
+            Err(BackendError::InvalidCredentials)
+        }
     }
 }
 
 /// Responds the user data of a user to that user.
 #[get("/user/profile")]
 pub fn profile(
-    claims: jwt::Claims,
-    pool: State<DbConnectionPool>,
+    claims: jwt::ReadAccess,
+    pool: State<DbReadPool>,
 ) -> BackendResult<Json<User>> {
     let conn = pool.get()?;
     let user = User::load_by_id(&conn, claims.id())?;
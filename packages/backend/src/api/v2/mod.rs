@@ -0,0 +1,20 @@
+//! The second version of the api addresses nodes by their ids, which do not
+//! change when a node or one of its ancestors is renamed or moved. The paths
+//! of v1 are still available through `GET /nodes/by-path`.
+
+mod nodes;
+
+use rocket::{routes, Route};
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        nodes::change_node,
+        nodes::create_child,
+        nodes::create_root,
+        nodes::delete_node,
+        nodes::get_by_path,
+        nodes::get_children,
+        nodes::get_node,
+        nodes::get_roots
+    ]
+}
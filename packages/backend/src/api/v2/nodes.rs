@@ -0,0 +1,125 @@
+use rocket::response::status::{Created, NoContent};
+use rocket::{self, delete, get, patch, post, State};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Deserializer};
+
+use crate::models::{NewNodePayload, Node, NodeId, OwnedPath};
+use crate::store::{NodeChange, NodeRef, NodeStore};
+use crate::{jwt, BackendResult};
+
+#[get("/nodes")]
+pub fn get_roots(
+    claims: jwt::ReadAccess,
+    store: State<Box<dyn NodeStore>>,
+) -> BackendResult<Json<Vec<Node>>> {
+    Ok(Json(store.list(claims.id(), NodeRef::ROOT)?))
+}
+
+/// Returns the node at the path, whose names are separated by `/`.
+#[get("/nodes/by-path?<path>")]
+pub fn get_by_path(
+    claims: jwt::ReadAccess,
+    store: State<Box<dyn NodeStore>>,
+    path: String,
+) -> BackendResult<Json<Node>> {
+    let path: OwnedPath = path.split('/').map(String::from).collect();
+    Ok(Json(store.fetch(claims.id(), NodeRef::Path(&path))?))
+}
+
+#[get("/nodes/<id>")]
+pub fn get_node(
+    claims: jwt::ReadAccess,
+    store: State<Box<dyn NodeStore>>,
+    id: NodeId,
+) -> BackendResult<Json<Node>> {
+    Ok(Json(store.fetch(claims.id(), NodeRef::Id(id))?))
+}
+
+#[get("/nodes/<id>/children")]
+pub fn get_children(
+    claims: jwt::ReadAccess,
+    store: State<Box<dyn NodeStore>>,
+    id: NodeId,
+) -> BackendResult<Json<Vec<Node>>> {
+    Ok(Json(store.list(claims.id(), NodeRef::Id(id))?))
+}
+
+fn create(
+    claims: jwt::WriteAccess,
+    store: State<Box<dyn NodeStore>>,
+    parent: NodeRef,
+    payload: &NewNodePayload,
+) -> BackendResult<Created<Json<Node>>> {
+    let node = store.insert(claims.id(), parent, payload)?;
+    let location = format!("/api/v2/nodes/{}", node.node_id);
+    Ok(Created(location, Some(Json(node))))
+}
+
+#[post("/nodes", data = "<payload>")]
+pub fn create_root(
+    claims: jwt::WriteAccess,
+    store: State<Box<dyn NodeStore>>,
+    payload: Json<NewNodePayload>,
+) -> BackendResult<Created<Json<Node>>> {
+    create(claims, store, NodeRef::ROOT, &payload)
+}
+
+#[post("/nodes/<id>/children", data = "<payload>")]
+pub fn create_child(
+    claims: jwt::WriteAccess,
+    store: State<Box<dyn NodeStore>>,
+    id: NodeId,
+    payload: Json<NewNodePayload>,
+) -> BackendResult<Created<Json<Node>>> {
+    create(claims, store, NodeRef::Id(id), &payload)
+}
+
+/// The changes of `PATCH /nodes/<id>`. Fields that are missing stay as they
+/// are, a `parentId` of `null` moves the node to the root.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ChangeNodePayload {
+    name: Option<String>,
+    content: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    parent_id: Option<Option<NodeId>>,
+}
+
+/// Distinguishes a `null` field from a missing one, which is `None` by
+/// default.
+fn present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Applies all changes or, if one of them fails, none of them.
+#[patch("/nodes/<id>", data = "<payload>")]
+pub fn change_node(
+    claims: jwt::WriteAccess,
+    store: State<Box<dyn NodeStore>>,
+    id: NodeId,
+    payload: Json<ChangeNodePayload>,
+) -> BackendResult<Json<Node>> {
+    let change = NodeChange {
+        name: payload.name.as_deref(),
+        parent: payload.parent_id.map(|parent_id| match parent_id {
+            Some(parent_id) => NodeRef::Id(parent_id),
+            None => NodeRef::ROOT,
+        }),
+        content: payload.content.as_deref(),
+    };
+    Ok(Json(store.change(claims.id(), NodeRef::Id(id), &change)?))
+}
+
+#[delete("/nodes/<id>")]
+pub fn delete_node(
+    claims: jwt::WriteAccess,
+    store: State<Box<dyn NodeStore>>,
+    id: NodeId,
+) -> BackendResult<NoContent> {
+    store.delete(claims.id(), NodeRef::Id(id))?;
+    Ok(NoContent)
+}
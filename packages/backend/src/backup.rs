@@ -0,0 +1,460 @@
+//! Consistent snapshots of the sqlite database, optionally gzip compressed and
+//! encrypted with a passphrase, and restoring them.
+//!
+//! Snapshots are taken with `VACUUM INTO`, which reads the database in a single
+//! transaction, so the server keeps running and writing meanwhile.
+//!
+//! Encrypted snapshots start with `ENCRYPTED_MAGIC`, followed by the salt used
+//! to derive the key from the passphrase with Argon2id, the nonce and the
+//! ChaCha20-Poly1305 encrypted (and possibly compressed) database.
+
+use chrono::prelude::*;
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::BackupSection;
+use crate::{database, integrity, BackendError, BackendResult, DbConnection};
+
+/// The first bytes of every sqlite database file.
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+/// The first bytes of every gzip file.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+/// The first bytes of every encrypted snapshot, including the format version.
+const ENCRYPTED_MAGIC: &[u8] = b"MNSNAPSHOT1";
+
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const KEY_LENGTH: u32 = 32;
+
+/// Snapshots created by the server start with this prefix, followed by the
+/// time of the snapshot. Only files with this prefix are pruned.
+const SNAPSHOT_PREFIX: &str = "markdown-notebook-";
+
+#[derive(Clone, Default, Debug)]
+pub struct SnapshotOptions {
+    /// Whether the snapshot is gzip compressed.
+    pub gzip: bool,
+    /// The snapshot is encrypted with this passphrase if set.
+    pub passphrase: Option<String>,
+}
+
+impl SnapshotOptions {
+    /// The file name of a snapshot taken at the given time.
+    pub fn file_name(&self, time: DateTime<Utc>) -> String {
+        let mut name = format!(
+            "{}{}.sqlite",
+            SNAPSHOT_PREFIX,
+            time.format("%Y%m%dT%H%M%SZ")
+        );
+        if self.gzip {
+            name.push_str(".gz");
+        }
+        if self.passphrase.is_some() {
+            name.push_str(".enc");
+        }
+        name
+    }
+}
+
+/// Returns an error if the backend is built for postgres, which has its own
+/// tools like `pg_dump`.
+fn ensure_sqlite() -> BackendResult<()> {
+    if cfg!(feature = "postgres") {
+        Err(BackendError::InvalidConfig(String::from(
+            "Snapshots are only supported with sqlite, use pg_dump instead",
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn invalid_snapshot(reason: &str) -> BackendError {
+    BackendError::InvalidSnapshot(String::from(reason))
+}
+
+/// Writes a consistent snapshot of the connected database to `destination`,
+/// which must not exist yet.
+pub fn snapshot(
+    conn: &DbConnection,
+    destination: &Path,
+    options: &SnapshotOptions,
+) -> BackendResult<()> {
+    ensure_sqlite()?;
+    if destination.exists() {
+        return Err(BackendError::InvalidConfig(format!(
+            "{} already exists",
+            destination.display()
+        )));
+    }
+
+    let partial = destination.with_extension("partial");
+    let _ = fs::remove_file(&partial);
+    diesel::sql_query("VACUUM INTO ?")
+        .bind::<Text, _>(partial.to_string_lossy().into_owned())
+        .execute(conn)?;
+
+    if options.gzip || options.passphrase.is_some() {
+        let result = fs::read(&partial)
+            .map_err(BackendError::from)
+            .and_then(|database| encode(database, options))
+            .and_then(|snapshot| {
+                fs::write(&partial, snapshot).map_err(BackendError::from)
+            });
+        if let Err(err) = result {
+            let _ = fs::remove_file(&partial);
+            return Err(err);
+        }
+    }
+
+    fs::rename(&partial, destination)?;
+    Ok(())
+}
+
+fn encode(
+    database: Vec<u8>,
+    options: &SnapshotOptions,
+) -> BackendResult<Vec<u8>> {
+    let mut data = database;
+    if options.gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data)?;
+        data = encoder.finish()?;
+    }
+    if let Some(passphrase) = &options.passphrase {
+        data = encrypt(data, passphrase)?;
+    }
+    Ok(data)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> BackendResult<LessSafeKey> {
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        mem_cost: 19 * 1024,
+        time_cost: 2,
+        hash_length: KEY_LENGTH,
+        ..argon2::Config::default()
+    };
+    let key = argon2::hash_raw(passphrase.as_bytes(), salt, &config)?;
+    let key = UnboundKey::new(&aead::CHACHA20_POLY1305, &key)
+        .map_err(|_| invalid_snapshot("Invalid key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+fn encrypt(mut data: Vec<u8>, passphrase: &str) -> BackendResult<Vec<u8>> {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LENGTH];
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rng.fill(&mut salt)
+        .and_then(|_| rng.fill(&mut nonce))
+        .map_err(|_| BackendError::TokenGeneration)?;
+
+    derive_key(passphrase, &salt)?
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(ENCRYPTED_MAGIC),
+            &mut data,
+        )
+        .map_err(|_| invalid_snapshot("Encryption failed"))?;
+
+    let mut snapshot = ENCRYPTED_MAGIC.to_vec();
+    snapshot.extend_from_slice(&salt);
+    snapshot.extend_from_slice(&nonce);
+    snapshot.extend_from_slice(&data);
+    Ok(snapshot)
+}
+
+fn decrypt(snapshot: &[u8], passphrase: &str) -> BackendResult<Vec<u8>> {
+    let header_length = ENCRYPTED_MAGIC.len() + SALT_LENGTH + NONCE_LENGTH;
+    if snapshot.len() < header_length {
+        return Err(invalid_snapshot("The snapshot is truncated"));
+    }
+    let salt = &snapshot[ENCRYPTED_MAGIC.len()..][..SALT_LENGTH];
+    let mut nonce = [0u8; NONCE_LENGTH];
+    nonce.copy_from_slice(
+        &snapshot[ENCRYPTED_MAGIC.len() + SALT_LENGTH..header_length],
+    );
+
+    let mut data = snapshot[header_length..].to_vec();
+    let length = derive_key(passphrase, salt)?
+        .open_in_place(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(ENCRYPTED_MAGIC),
+            &mut data,
+        )
+        .map_err(|_| {
+            invalid_snapshot("Wrong passphrase or the snapshot is corrupted")
+        })?
+        .len();
+    data.truncate(length);
+    Ok(data)
+}
+
+/// Whether the snapshot at the given path is encrypted and needs a
+/// passphrase to be read.
+pub fn is_encrypted(path: &Path) -> BackendResult<bool> {
+    let mut magic = vec![0u8; ENCRYPTED_MAGIC.len()];
+    let mut file = fs::File::open(path)?;
+    Ok(file.read_exact(&mut magic).is_ok() && magic == ENCRYPTED_MAGIC)
+}
+
+/// Reads the snapshot at the given path and returns the contained sqlite
+/// database. Decrypts and decompresses it if needed.
+pub fn read_snapshot(
+    path: &Path,
+    passphrase: Option<&str>,
+) -> BackendResult<Vec<u8>> {
+    let mut data = fs::read(path)?;
+    if data.starts_with(ENCRYPTED_MAGIC) {
+        let passphrase = passphrase.ok_or_else(|| {
+            invalid_snapshot(
+                "The snapshot is encrypted, a passphrase is needed",
+            )
+        })?;
+        data = decrypt(&data, passphrase)?;
+    }
+    if data.starts_with(GZIP_MAGIC) {
+        let mut database = Vec::new();
+        GzDecoder::new(data.as_slice()).read_to_end(&mut database)?;
+        data = database;
+    }
+    if !data.starts_with(SQLITE_MAGIC) {
+        return Err(invalid_snapshot("Not a sqlite database"));
+    }
+    Ok(data)
+}
+
+/// Replaces the database at `database_path` with the given snapshot. The
+/// snapshot is checked with `PRAGMA integrity_check` and migrated before it is
+/// swapped in. The previous database is kept next to it and its path is
+/// returned. The server must not be running.
+pub fn restore(
+    database_path: &Path,
+    snapshot: &Path,
+    passphrase: Option<&str>,
+) -> BackendResult<PathBuf> {
+    ensure_sqlite()?;
+    let database = read_snapshot(snapshot, passphrase)?;
+
+    let restored = sibling(database_path, "restore");
+    remove_database_files(&restored)?;
+    fs::write(&restored, database)?;
+    if let Err(err) = prepare_restored(&restored) {
+        let _ = remove_database_files(&restored);
+        return Err(err);
+    }
+
+    // Move the write-ahead log with the database, otherwise sqlite would apply
+    // it to the restored database.
+    let previous = sibling(
+        database_path,
+        &format!("before-restore-{}", Utc::now().format("%Y%m%dT%H%M%SZ")),
+    );
+    if database_path.exists() {
+        for suffix in &["", "-wal", "-shm"] {
+            let file = with_suffix(database_path, suffix);
+            if file.exists() {
+                fs::rename(&file, with_suffix(&previous, suffix))?;
+            }
+        }
+    }
+    fs::rename(&restored, database_path)?;
+
+    Ok(previous)
+}
+
+/// Checks the integrity of the restored database and runs the migrations.
+fn prepare_restored(path: &Path) -> BackendResult<()> {
+    let conn = DbConnection::establish(&path.to_string_lossy())?;
+    check_integrity(&conn)?;
+    database::run_migrations(&conn)?;
+    // Leave a single file behind that can be renamed.
+    conn.batch_execute("PRAGMA journal_mode = DELETE;")?;
+    Ok(())
+}
+
+/// Runs `PRAGMA integrity_check` and returns an error with its findings
+/// unless the database is fine.
+pub fn check_integrity(conn: &DbConnection) -> BackendResult<()> {
+    let findings = integrity::integrity_check(conn)?;
+    if findings.is_empty() {
+        Ok(())
+    } else {
+        Err(BackendError::InvalidSnapshot(findings.join("; ")))
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn sibling(database_path: &Path, name: &str) -> PathBuf {
+    with_suffix(database_path, &format!(".{}", name))
+}
+
+fn remove_database_files(path: &Path) -> BackendResult<()> {
+    for suffix in &["", "-wal", "-shm", "-journal"] {
+        let file = with_suffix(path, suffix);
+        if file.exists() {
+            fs::remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes all but the newest `retention` snapshots in the given directory.
+/// Returns the deleted snapshots.
+pub fn prune(dir: &Path, retention: usize) -> BackendResult<Vec<PathBuf>> {
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(SNAPSHOT_PREFIX) && !name.ends_with(".partial") {
+            snapshots.push(entry.path());
+        }
+    }
+    // The names contain the time, so they sort chronologically.
+    snapshots.sort();
+
+    let count = snapshots.len().saturating_sub(retention);
+    let outdated: Vec<_> = snapshots.drain(..count).collect();
+    for snapshot in &outdated {
+        fs::remove_file(snapshot)?;
+    }
+    Ok(outdated)
+}
+
+/// Configuration of the snapshots taken by the server.
+#[derive(Clone, Debug)]
+pub struct BackupConfig {
+    /// The directory snapshots are written to.
+    pub dir: PathBuf,
+    pub interval: Duration,
+    /// The number of snapshots that are kept.
+    pub retention: usize,
+    pub options: SnapshotOptions,
+}
+
+impl BackupConfig {
+    /// Returns `None` if no backup directory is configured, which disables
+    /// snapshots.
+    pub fn from_config(
+        cfg: &BackupSection,
+    ) -> BackendResult<Option<BackupConfig>> {
+        let dir = match &cfg.dir {
+            Some(dir) => dir.clone(),
+            None => return Ok(None),
+        };
+        ensure_sqlite()?;
+
+        Ok(Some(BackupConfig {
+            dir,
+            interval: Duration::from_secs(cfg.interval_minutes * 60),
+            retention: cfg.retention,
+            options: SnapshotOptions {
+                gzip: cfg.gzip,
+                passphrase: cfg.passphrase.clone(),
+            },
+        }))
+    }
+}
+
+/// Takes a snapshot into the backup directory and prunes old ones.
+pub fn take_scheduled_snapshot(
+    conn: &DbConnection,
+    cfg: &BackupConfig,
+) -> BackendResult<PathBuf> {
+    fs::create_dir_all(&cfg.dir)?;
+    let destination = cfg.dir.join(cfg.options.file_name(Utc::now()));
+    snapshot(conn, &destination, &cfg.options)?;
+    for pruned in prune(&cfg.dir, cfg.retention)? {
+        log::info!("Deleted outdated snapshot {}", pruned.display());
+    }
+    Ok(destination)
+}
+
+/// Starts a thread taking a snapshot every `cfg.interval`. It uses its own
+/// connection, so requests are not blocked while the snapshot is written.
+pub fn spawn_scheduler(database_url: String, cfg: BackupConfig) {
+    thread::spawn(move || loop {
+        thread::sleep(cfg.interval);
+        let result = DbConnection::establish(&database_url)
+            .map_err(BackendError::from)
+            .and_then(|conn| take_scheduled_snapshot(&conn, &cfg));
+        match result {
+            Ok(path) => log::info!("Wrote snapshot {}", path.display()),
+            Err(err) => log::error!("Failed to write snapshot: {}", err),
+        }
+    });
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+mod tests {
+    use super::*;
+    use crate::models::{User, UserQueries};
+
+    #[test]
+    fn it_restores_encrypted_snapshots() -> BackendResult<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let database_path = dir.join("notebook.sqlite");
+        let pool = database::create_pool(&database_path.to_string_lossy())?;
+        {
+            let conn = pool.get()?;
+            database::run_migrations(&conn)?;
+            crate::user_management::provision(&conn, "alice")?;
+        }
+
+        let options = SnapshotOptions {
+            gzip: true,
+            passphrase: Some(String::from("correct horse")),
+        };
+        let snapshot_path = dir.join(options.file_name(Utc::now()));
+        let conn = pool.get()?;
+        snapshot(&conn, &snapshot_path, &options)?;
+        drop(conn);
+        drop(pool);
+
+        assert!(is_encrypted(&snapshot_path)?);
+        assert!(read_snapshot(&snapshot_path, Some("wrong")).is_err());
+
+        restore(&database_path, &snapshot_path, Some("correct horse"))?;
+        let conn = DbConnection::establish(&database_path.to_string_lossy())?;
+        assert!(User::load_by_username(&conn, "alice").is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_prunes_old_snapshots() -> BackendResult<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let options = SnapshotOptions::default();
+        for day in 1..=3 {
+            let time = Utc.ymd(2020, 7, day).and_hms(3, 0, 0);
+            fs::write(dir.join(options.file_name(time)), "")?;
+        }
+        fs::write(dir.join("unrelated.sqlite"), "")?;
+
+        let pruned = prune(dir, 2)?;
+        assert_eq!(
+            pruned,
+            vec![dir.join("markdown-notebook-20200701T030000Z.sqlite")]
+        );
+        assert!(dir.join("unrelated.sqlite").exists());
+
+        Ok(())
+    }
+}
@@ -1,54 +1,85 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
 use dotenv::dotenv;
-use rocket::config::{Config, Environment};
-use rocket::{get, http::Method, routes};
-use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions};
-use std::env;
+use rocket::config::{Config as RocketConfig, Environment};
+use rocket::{get, routes};
 use std::error::Error;
+use std::path::PathBuf;
 
-use backend::{api, database, jwt};
+use backend::backup::{self, BackupConfig};
+use backend::config::Config;
+use backend::health::HealthConfig;
+use backend::history::History;
+use backend::logging::{self, LogConfig, RequestLogger, SlowConnectionLogger};
+use backend::metrics::{Metrics, PoolMetrics, READ_POOL, WRITE_POOL};
+use backend::models::LockoutConfig;
+use backend::webapp::Webapp;
+use backend::{api, database, jwt, store, BackendResult};
+
+const USAGE: &str = "Usage: backend [--config <file>] [--print-config]
+
+Starts the backend. The configuration is read from the given TOML file or the
+file at MN_CONFIG, if any, and the MN_* environment variables. With
+--print-config the effective configuration is printed with secrets redacted
+instead.";
 
 fn main() -> Result<(), Box<dyn Error>> {
     dotenv().ok();
 
-    let config = Config::build(Environment::Staging)
-        .address(
-            env::var("MN_HOST").unwrap_or_else(|_| String::from("0.0.0.0")),
-        )
-        .port(
-            env::var("MN_PORT")
-                .unwrap_or_else(|_| String::from("8000"))
-                .parse()
-                .expect("MN_PORT is not a valid port"),
-        )
-        .finalize()?;
+    let mut config_path = None;
+    let mut print_config = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" if config_path.is_none() => match args.next() {
+                Some(path) => config_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    std::process::exit(2);
+                }
+            },
+            "--print-config" => print_config = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
 
-    let allowed_origins = AllowedOrigins::all();
-    let cors = CorsOptions {
-        allowed_origins,
-        allowed_methods: vec![
-            Method::Get,
-            Method::Post,
-            Method::Put,
-            Method::Delete,
-        ]
-        .into_iter()
-        .map(From::from)
-        .collect(),
-        allowed_headers: AllowedHeaders::some(&[
-            "Authorization",
-            "Accept",
-            "Content-Type",
-        ]),
-        allow_credentials: true,
-        ..Default::default()
+    let config = exit_on_error(Config::load(config_path.as_deref()));
+    if print_config {
+        print!("{}", config.redacted().to_toml()?);
+        return Ok(());
     }
-    .to_cors()?;
 
-    let db_connection_pool = database::create_pool(
-        &env::var("MN_DATABASE_URL")
-            .expect("MN_DATABASE_URL env variable missing"),
+    let log_config = LogConfig::from_config(&config.log);
+    logging::init(&log_config)?;
+
+    let rocket_config = RocketConfig::build(Environment::Staging)
+        .address(config.server.host.as_str())
+        .port(config.server.port)
+        .finalize()?;
+    let cors = config.cors.to_cors()?;
+    let jwt_config = exit_on_error(jwt::Config::from_config(&config.jwt));
+    let webapp = exit_on_error(Webapp::from_config(&config.webapp));
+    let history = History::from_config(&config.history)?;
+
+    let database_url = config.database.url();
+    let metrics = Metrics::new(config.storage.backend)?;
+    let db_connection_pool = database::create_pool_with_events(
+        database_url,
+        config.database.pool_size,
+        Box::new(PoolMetrics {
+            metrics: metrics.clone(),
+            pool: WRITE_POOL,
+            inner: SlowConnectionLogger {
+                threshold: log_config.slow_connection_threshold,
+            },
+        }),
     )?;
 
     // Run migrations in a block so the connection is dropped immediately after
@@ -58,22 +89,74 @@ fn main() -> Result<(), Box<dyn Error>> {
         database::run_migrations(&conn)?;
     }
 
-    rocket::custom(config)
-        .manage(jwt::Config {
-            secret: env::var("MN_JWT_SECRET")
-                .expect("MN_JWT_SECRET env variable is missing."),
-            expire_in: chrono::Duration::weeks(4).num_seconds(),
-            validation_leeway: 60,
-        })
+    if let Some(backup_config) = BackupConfig::from_config(&config.backup)? {
+        backup::spawn_scheduler(String::from(database_url), backup_config);
+    }
+
+    let db_read_pool = database::create_read_pool(
+        database_url,
+        config.database.read_pool_size,
+        Box::new(PoolMetrics {
+            metrics: metrics.clone(),
+            pool: READ_POOL,
+            inner: SlowConnectionLogger {
+                threshold: log_config.slow_connection_threshold,
+            },
+        }),
+    )?;
+
+    let store = store::from_config(
+        &config.storage,
+        &db_connection_pool,
+        &db_read_pool,
+        history.clone(),
+    )?;
+
+    let mut rocket = rocket::custom(rocket_config)
+        .manage(jwt_config)
+        .manage(LockoutConfig::default())
+        .manage(config.password.clone())
+        .manage(config.proxy_auth.clone())
+        .manage(config.server.clone())
+        .manage(store)
         .manage(db_connection_pool)
-        .mount("/", routes![index])
+        .manage(db_read_pool)
+        .manage(HealthConfig::from_config(database_url, &config.health))
+        .manage(metrics.clone())
+        .mount("/health", routes![api::health::live, api::health::ready])
         .mount("/api/v1", api::v1::get_routes())
-        .attach(cors)
-        .launch();
+        .mount("/api/v2", api::v2::get_routes())
+        .register(api::catchers::get_catchers())
+        .attach(RequestLogger::new(&log_config))
+        .attach(metrics)
+        .attach(cors);
+    if config.metrics.enabled {
+        rocket = rocket.mount("/", routes![api::metrics::metrics]);
+    }
+    if let Some(history) = history {
+        rocket = rocket.manage(history);
+    }
+    rocket = match webapp {
+        Some(webapp) => rocket.mount("/", webapp.routes()),
+        None => rocket.mount("/", routes![index]),
+    };
+    rocket.launch();
 
     Ok(())
 }
 
+/// Returns the value or prints the error, e.g. an invalid configuration, and
+/// exits.
+fn exit_on_error<T>(result: BackendResult<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    }
+}
+
 #[get("/")]
 fn index() -> &'static str {
     "Hello from markdown-notebook!"
@@ -0,0 +1,78 @@
+use chrono::prelude::*;
+use dotenv::dotenv;
+use std::error::Error;
+use std::path::PathBuf;
+
+use backend::backup::{self, SnapshotOptions};
+use backend::config::Config;
+use backend::database;
+
+const USAGE: &str = "Usage: backup [--gzip] [--encrypt] <file or directory>
+
+Writes a consistent snapshot of the database, also while the backend is
+running. If a directory is given, the snapshot is written into it with a
+timestamped name. With --encrypt the passphrase is read from
+MN_BACKUP_PASSPHRASE or prompted for.";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    dotenv().ok();
+
+    let mut options = SnapshotOptions::default();
+    let mut encrypt = false;
+    let mut destination = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--gzip" => options.gzip = true,
+            "--encrypt" => encrypt = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if destination.is_none() && !arg.starts_with('-') => {
+                destination = Some(PathBuf::from(arg))
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+    let destination = match destination {
+        Some(destination) => destination,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let config = Config::load(None)?;
+    if encrypt {
+        options.passphrase = Some(match config.backup.passphrase.clone() {
+            Some(passphrase) => passphrase,
+            None => {
+                println!("Passphrase:");
+                let passphrase = rpassword::read_password()?;
+                println!("Repeat the passphrase:");
+                if rpassword::read_password()? != passphrase {
+                    eprintln!("The passphrases do not match.");
+                    std::process::exit(1);
+                }
+                passphrase
+            }
+        });
+    }
+
+    let destination = if destination.is_dir() {
+        destination.join(options.file_name(Utc::now()))
+    } else {
+        destination
+    };
+
+    let database_url = config.database.url();
+    let pool = database::create_pool(database_url)?;
+    let conn = pool.get()?;
+    backup::snapshot(&conn, &destination, &options)?;
+    println!("Wrote snapshot {}.", destination.display());
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use dotenv::dotenv;
+use std::error::Error;
+
+use backend::config::Config;
+use backend::database;
+use backend::integrity::{self, LOST_AND_FOUND};
+
+const USAGE: &str = "Usage: check_db [--repair]
+
+Checks the database for corruption, broken foreign keys, orphaned nodes,
+parent cycles, siblings with the same name, directories with content and files
+without content, and lists everything it finds. With --repair the broken nodes
+are moved into a lost+found directory of their owner. Take a backup before
+repairing. Exits with 1 if problems remain.";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    dotenv().ok();
+
+    let mut repair = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--repair" => repair = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let config = Config::load(None)?;
+    let database_url = config.database.url();
+    let pool = database::create_pool(database_url)?;
+    let conn = pool.get()?;
+
+    let mut problems = integrity::check(&conn)?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    println!("Found {} problems.", problems.len());
+
+    if repair && problems.iter().any(|problem| problem.is_repairable()) {
+        let repairs = integrity::repair(&conn, &problems)?;
+        println!("Moved {} nodes to {}.", repairs.moved, LOST_AND_FOUND);
+        println!(
+            "Gave {} files without content an empty content.",
+            repairs.filled
+        );
+        println!(
+            "Moved the content of {} directories into new files in {}.",
+            repairs.extracted, LOST_AND_FOUND
+        );
+
+        problems = integrity::check(&conn)?;
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        println!("{} problems remain.", problems.len());
+    }
+
+    if !problems.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,424 @@
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use dotenv::dotenv;
+use serde_json::json;
+use std::io::{stdin, BufRead};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use backend::config::Config;
+use backend::database::{retry_on_busy, DbReadPool};
+use backend::errors::codes;
+use backend::export::{self, ImportMode};
+use backend::history::{Change, History};
+use backend::models::schema::users;
+use backend::models::{AccessToken, Scope, User, UserId, UserQueries};
+use backend::store::{self, NodeRef, NodeStore, StorageBackend};
+use backend::DbConnectionPool;
+use backend::{database, user_management, BackendError, BackendResult};
+
+const USAGE: &str = "Usage: mnctl [--config <file>] [--json] <command>
+
+Commands:
+  user create <username> [--password-stdin]
+  user list
+  user passwd <username> [--password-stdin]
+  user delete <username>
+  token create <username> --name <name> --scopes <scopes> [--expires-days <n>]
+  db migrate
+  db status
+  node ls <username> [<path>]
+  node cat <username> <path>
+  node rm <username> <path>
+  node export <username> <dir>
+  node import <username> <dir> [--replace] [--dry-run]
+
+Passwords are prompted for, unless --password-stdin is given, which reads the
+password from the first line of stdin. Paths are separated by /. With --json
+the output and errors are printed as json.
+
+Tokens are personal access tokens, the scopes a comma separated list of read,
+write and admin. Without --expires-days the token does not expire. The token is
+printed just once.
+
+Exports write directories as folders and files as .md files into an empty
+directory. Imports read such a directory back and merge it into the nodes of
+the user: missing nodes are added and files are overwritten. With --replace all
+nodes of the user are deleted first. --dry-run only lists the changes.
+
+Exit codes: 0 on success, 1 on errors, 2 on invalid usage, 3 if the user or
+node does not exist and 4 if the input is rejected, e.g. a taken username or a
+weak password.";
+
+const EXIT_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_NOT_FOUND: i32 = 3;
+const EXIT_REJECTED: i32 = 4;
+
+struct Options {
+    config: Option<PathBuf>,
+    json: bool,
+    password_stdin: bool,
+    replace: bool,
+    dry_run: bool,
+    token_name: Option<String>,
+    scopes: Option<String>,
+    expires_days: Option<i64>,
+}
+
+fn main() {
+    dotenv().ok();
+
+    let mut options = Options {
+        config: None,
+        json: false,
+        password_stdin: false,
+        replace: false,
+        dry_run: false,
+        token_name: None,
+        scopes: None,
+        expires_days: None,
+    };
+    let mut command = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => match args.next() {
+                Some(path) => options.config = Some(PathBuf::from(path)),
+                None => usage_error(),
+            },
+            "--json" => options.json = true,
+            "--password-stdin" => options.password_stdin = true,
+            "--replace" => options.replace = true,
+            "--dry-run" => options.dry_run = true,
+            "--name" => match args.next() {
+                Some(name) => options.token_name = Some(name),
+                None => usage_error(),
+            },
+            "--scopes" => match args.next() {
+                Some(scopes) => options.scopes = Some(scopes),
+                None => usage_error(),
+            },
+            "--expires-days" => match args.next().map(|days| days.parse()) {
+                Some(Ok(days)) => options.expires_days = Some(days),
+                _ => usage_error(),
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') => usage_error(),
+            _ => command.push(arg),
+        }
+    }
+    let command: Vec<&str> = command.iter().map(String::as_str).collect();
+
+    if let Err(err) = run(&command, &options) {
+        if options.json {
+            eprintln!(
+                "{}",
+                json!({ "error": err.code(), "message": err.to_string() })
+            );
+        } else {
+            eprintln!("{}", err);
+        }
+        exit(match err.code() {
+            codes::NOT_FOUND => EXIT_NOT_FOUND,
+            codes::NAME_CONFLICT
+            | codes::WEAK_PASSWORD
+            | codes::INVALID_VALUE
+            | codes::NODE_NAME_INVALID => EXIT_REJECTED,
+            _ => EXIT_ERROR,
+        });
+    }
+}
+
+fn usage_error() -> ! {
+    eprintln!("{}", USAGE);
+    exit(EXIT_USAGE);
+}
+
+fn run(command: &[&str], options: &Options) -> BackendResult<()> {
+    // Reject invalid commands before touching the database.
+    match command {
+        ["user", "create", _]
+        | ["user", "list"]
+        | ["user", "passwd", _]
+        | ["user", "delete", _]
+        | ["db", "migrate"]
+        | ["db", "status"]
+        | ["node", "ls", _]
+        | ["node", "ls", _, _]
+        | ["node", "cat", _, _]
+        | ["node", "rm", _, _]
+        | ["node", "export", _, _]
+        | ["node", "import", _, _] => {}
+        ["token", "create", _]
+            if options.token_name.is_some() && options.scopes.is_some() => {}
+        _ => usage_error(),
+    }
+
+    let config = Config::load(options.config.as_deref())?;
+    let pool: DbConnectionPool = database::create_pool(config.database.url())?;
+    let conn = pool.get()?;
+    let json = options.json;
+    let history = History::from_config(&config.history)?;
+    // Like the backend, every command but `db` works on a migrated database.
+    if command[0] != "db" {
+        database::run_migrations(&conn)?;
+    }
+    // The pool has a single connection, so node commands drop `conn` before
+    // they use the store.
+    let read_pool = DbReadPool::shared(&pool);
+    let node_store = store::from_config(
+        &config.storage,
+        &pool,
+        &read_pool,
+        history.clone(),
+    )?;
+    let store = node_store.as_ref();
+    let database_storage = config.storage.backend == StorageBackend::Database;
+
+    match command {
+        ["user", "create", username] => {
+            let password = read_password(options)?;
+            let user = user_management::create(
+                &conn,
+                &config.password,
+                username,
+                &password,
+            )?;
+            print(json, &user, || {
+                format!("Created user {} (id: {}).", user.username, user.id)
+            });
+        }
+        ["user", "list"] => {
+            let users = users::table.order(users::id).load::<User>(&conn)?;
+            print(json, &users, || {
+                users
+                    .iter()
+                    .map(|user| format!("{}\t{}", user.id, user.username))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            });
+        }
+        ["user", "passwd", username] => {
+            let user = User::load_by_username(&conn, username)?;
+            let password = read_password(options)?;
+            let user = user_management::change_password(
+                &conn,
+                &config.password,
+                user.id,
+                &password,
+            )?;
+            print(json, &user, || {
+                format!("Changed the password of {}.", user.username)
+            });
+        }
+        ["user", "delete", username] => {
+            let user = User::load_by_username(&conn, username)?;
+            drop(conn);
+            // The id of the user can be reused, so nothing of the user must
+            // be left behind.
+            store.delete_all(user.id)?;
+            user_management::delete(&pool.get()?, user.id)?;
+            print(json, &user, || format!("Deleted user {}.", user.username));
+        }
+        ["token", "create", username] => {
+            let user = User::load_by_username(&conn, username)?;
+            let name = options.token_name.as_deref().unwrap_or_default();
+            let scopes = options.scopes.as_deref().unwrap_or_default();
+            let scopes = Scope::parse_list(scopes)?;
+            let expires_at = options.expires_days.map(|days| {
+                Utc::now().timestamp() + Duration::days(days).num_seconds()
+            });
+            let (access_token, token) = retry_on_busy(|| {
+                AccessToken::create(&conn, user.id, name, &scopes, expires_at)
+            })?;
+            let value = json!({ "token": token, "accessToken": access_token });
+            print(json, &value, || {
+                format!(
+                    "Created token {} (id: {}) for {}. It will not be shown \
+                     again:\n{}",
+                    access_token.token_name,
+                    access_token.id,
+                    user.username,
+                    token
+                )
+            });
+        }
+        ["db", "migrate"] => {
+            let applied = database::run_pending_migrations(&conn)?;
+            print(json, &json!({ "applied": applied }), || {
+                format!("Applied {} migrations.", applied.len())
+            });
+        }
+        ["db", "status"] => {
+            let pending = database::pending_migrations(&conn)?;
+            print(json, &json!({ "pending": pending }), || {
+                if pending.is_empty() {
+                    String::from("The database is up to date.")
+                } else {
+                    format!("Pending migrations:\n{}", pending.join("\n"))
+                }
+            });
+        }
+        ["node", "ls", username, path @ ..] => {
+            let user = User::load_by_username(&conn, username)?;
+            drop(conn);
+            let path = path.first().copied().unwrap_or_default();
+            list_nodes(store, json, user.id, path)?
+        }
+        ["node", "cat", username, path] => {
+            let user = User::load_by_username(&conn, username)?;
+            drop(conn);
+            let node = store.fetch(user.id, NodeRef::Path(&split(path)))?;
+            let content = match &node.content {
+                Some(content) => content.clone(),
+                // Directories have no content.
+                None => return Err(BackendError::InvalidValue),
+            };
+            if json {
+                println!("{}", json!(node));
+            } else {
+                print!("{}", content);
+            }
+        }
+        ["node", "rm", username, path] => {
+            let user = User::load_by_username(&conn, username)?;
+            drop(conn);
+            let node_path = split(path);
+            let node_id =
+                store.fetch(user.id, NodeRef::Path(&node_path))?.node_id;
+            store.delete(user.id, NodeRef::Id(node_id))?;
+            print(json, &json!({ "nodeId": node_id }), || {
+                format!("Deleted {}.", path)
+            });
+        }
+        ["node", "export", _, _] | ["node", "import", _, _]
+            if !database_storage =>
+        {
+            return Err(BackendError::InvalidConfig(String::from(
+                "Exports and imports require storage.backend database, the \
+                 filesystem backend already stores .md files",
+            )));
+        }
+        ["node", "export", username, dir] => {
+            let user = User::load_by_username(&conn, username)?;
+            let count = export::export(&conn, &user.id, Path::new(dir))?;
+            print(json, &json!({ "exported": count }), || {
+                format!("Exported {} nodes to {}.", count, dir)
+            });
+        }
+        ["node", "import", username, dir] => {
+            let user = User::load_by_username(&conn, username)?;
+            let mode = if options.replace {
+                ImportMode::Replace
+            } else {
+                ImportMode::Merge
+            };
+            let changes = export::import(
+                &conn,
+                &user.id,
+                Path::new(dir),
+                mode,
+                options.dry_run,
+            )?;
+            drop(conn);
+            let applied = !options.dry_run;
+            if let (Some(history), true) = (&history, applied) {
+                let description = format!("Import {}", dir);
+                let change = Change::Bulk(&description);
+                history.record(store, user.id, &user.username, change);
+            }
+            print(
+                json,
+                &json!({ "changes": changes, "applied": applied }),
+                || {
+                    let mut lines: Vec<String> =
+                        changes.iter().map(ToString::to_string).collect();
+                    lines.push(if applied {
+                        format!("Applied {} changes.", changes.len())
+                    } else {
+                        format!(
+                            "Dry run, {} changes not applied.",
+                            changes.len()
+                        )
+                    });
+                    lines.join("\n")
+                },
+            );
+        }
+        _ => usage_error(),
+    }
+
+    Ok(())
+}
+
+/// Prints the value as json or the text returned by `text`.
+fn print<T, F>(json: bool, value: &T, text: F)
+where
+    T: serde::Serialize,
+    F: FnOnce() -> String,
+{
+    if json {
+        println!("{}", json!(value));
+    } else {
+        println!("{}", text());
+    }
+}
+
+fn split(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|part| !part.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Lists the nodes in the directory at `path` or the root nodes if `path` is
+/// empty. Directories end with a `/`.
+fn list_nodes(
+    store: &dyn NodeStore,
+    json: bool,
+    user_id: UserId,
+    path: &str,
+) -> BackendResult<()> {
+    let mut nodes = store.list(user_id, NodeRef::Path(&split(path)))?;
+    for node in &mut nodes {
+        node.content = None;
+    }
+    print(json, &nodes, || {
+        nodes
+            .iter()
+            .map(|node| {
+                if node.is_directory {
+                    format!("{}/", node.node_name)
+                } else {
+                    node.node_name.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+    Ok(())
+}
+
+/// Reads the password from the first line of stdin or prompts for it twice.
+fn read_password(options: &Options) -> BackendResult<String> {
+    if options.password_stdin {
+        let mut password = String::new();
+        stdin().lock().read_line(&mut password)?;
+        let len = password.trim_end_matches(&['\r', '\n'][..]).len();
+        password.truncate(len);
+        return Ok(password);
+    }
+
+    let password = rpassword::read_password_from_tty(Some("Password: "))?;
+    let repeated =
+        rpassword::read_password_from_tty(Some("Repeat the password: "))?;
+    if password != repeated {
+        eprintln!("The passwords do not match.");
+        exit(EXIT_REJECTED);
+    }
+    Ok(password)
+}
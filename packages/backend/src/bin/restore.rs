@@ -0,0 +1,87 @@
+use dotenv::dotenv;
+use std::error::Error;
+use std::io::stdin;
+use std::path::{Path, PathBuf};
+
+use backend::backup;
+use backend::config::Config;
+
+const USAGE: &str = "Usage: restore [--yes] <snapshot>
+
+Replaces the database with the given snapshot created by the backup binary or
+the backend. The snapshot is checked and migrated before it is swapped in, the
+previous database is kept next to it. Stop the backend before restoring. The
+passphrase of encrypted snapshots is read from MN_BACKUP_PASSPHRASE or prompted
+for.";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    dotenv().ok();
+
+    let mut confirmed = false;
+    let mut snapshot = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-y" | "--yes" => confirmed = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if snapshot.is_none() && !arg.starts_with('-') => {
+                snapshot = Some(PathBuf::from(arg))
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+    let snapshot = match snapshot {
+        Some(snapshot) => snapshot,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let config = Config::load(None)?;
+    let passphrase = if backup::is_encrypted(&snapshot)? {
+        match &config.backup.passphrase {
+            Some(passphrase) => Some(passphrase.clone()),
+            None => {
+                println!("Passphrase:");
+                Some(rpassword::read_password()?)
+            }
+        }
+    } else {
+        None
+    };
+
+    let database_url = config.database.url();
+    if !confirmed {
+        println!(
+            "Do you really want to replace {} with {}? The backend must not be running. (y|N)",
+            database_url,
+            snapshot.display()
+        );
+        let mut choice = String::new();
+        stdin().read_line(&mut choice)?;
+        choice.make_ascii_lowercase();
+        if choice.trim() != "y" {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    let previous = backup::restore(
+        Path::new(database_url),
+        &snapshot,
+        passphrase.as_deref(),
+    )?;
+    println!(
+        "Restored {}. The previous database was moved to {}.",
+        snapshot.display(),
+        previous.display()
+    );
+
+    Ok(())
+}
@@ -1,33 +1,124 @@
-use crate::BackendResult;
+use crate::logging::{LogConfig, SlowConnectionLogger};
+use crate::{BackendError, BackendResult};
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
-use diesel::r2d2::{self, CustomizeConnection};
+use diesel::r2d2::{self, event::HandleEvent, CustomizeConnection};
+use diesel::sql_types::BigInt;
+use diesel_migrations::MigrationConnection;
+use std::collections::HashSet;
+use std::ops::Deref;
+use std::thread;
+use std::time::Duration;
 
+#[cfg(not(feature = "postgres"))]
 embed_migrations!();
+#[cfg(feature = "postgres")]
+embed_migrations!("migrations_postgres");
 
-/// This type represents a database provider agnostic connection handle.
+/// This type represents a database provider agnostic connection handle. It is
+/// a sqlite connection unless the `postgres` feature is enabled.
+#[cfg(not(feature = "postgres"))]
 pub type DbConnection = diesel::sqlite::SqliteConnection;
+#[cfg(feature = "postgres")]
+pub type DbConnection = diesel::pg::PgConnection;
 
 /// The type of the database pool to get connections from. Use as a route guard
-/// to get the applications connection pool.
-pub type DbConnectionPool =
-    r2d2::Pool<r2d2::ConnectionManager<diesel::sqlite::SqliteConnection>>;
+/// to get the applications connection pool. Routes that only read should use
+/// `DbReadPool` instead.
+pub type DbConnectionPool = r2d2::Pool<r2d2::ConnectionManager<DbConnection>>;
+
+/// A pool of read-only connections. Use as a route guard in routes that do not
+/// write, so they do not wait for the single sqlite writer connection.
+#[derive(Clone)]
+pub struct DbReadPool(DbConnectionPool);
+
+impl DbReadPool {
+    /// Uses the given pool for reads too, e.g. for an in-memory database that
+    /// can not be shared between connections.
+    pub fn shared(pool: &DbConnectionPool) -> DbReadPool {
+        DbReadPool(pool.clone())
+    }
+}
+
+impl Deref for DbReadPool {
+    type Target = DbConnectionPool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// The default maximum number of connections of a pool. Since sqlite only
+/// supports one write at a time, concurrent write tries result in a database
+/// locked error by diesel. To avoid that, sqlite uses a single writer
+/// connection and a separate pool for reads.
+#[cfg(not(feature = "postgres"))]
+pub const DEFAULT_POOL_SIZE: u32 = 1;
+#[cfg(feature = "postgres")]
+pub const DEFAULT_POOL_SIZE: u32 = 10;
+
+/// How long sqlite waits for a lock held by another connection, e.g. of a
+/// command line tool, before failing with `SQLITE_BUSY`.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
-struct SqliteConnectionCustomizer;
+struct ConnectionCustomizer {
+    read_only: bool,
+}
 
-impl CustomizeConnection<DbConnection, r2d2::Error>
-    for SqliteConnectionCustomizer
-{
+#[cfg(not(feature = "postgres"))]
+impl CustomizeConnection<DbConnection, r2d2::Error> for ConnectionCustomizer {
     fn on_acquire(&self, conn: &mut DbConnection) -> Result<(), r2d2::Error> {
         // Ensure that for every connection foreign keys are enforced.
         // See https://www.sqlite.org/foreignkeys.html#fk_enable
-        match conn.execute("PRAGMA foreign_keys = ON;") {
+        let mut pragmas = format!(
+            "PRAGMA foreign_keys = ON; PRAGMA busy_timeout = {};",
+            BUSY_TIMEOUT.as_millis()
+        );
+        if self.read_only {
+            pragmas.push_str(" PRAGMA query_only = ON;");
+        } else {
+            // In WAL mode readers do not block the writer and the other way
+            // round. The mode is stored in the database file, so it is enough
+            // to set it for the writer. See https://www.sqlite.org/wal.html
+            pragmas.push_str(" PRAGMA journal_mode = WAL;");
+        }
+        match conn.batch_execute(&pragmas) {
             Ok(_) => Ok(()),
             Err(err) => Err(r2d2::Error::QueryError(err)),
         }
     }
 }
 
+#[cfg(feature = "postgres")]
+impl CustomizeConnection<DbConnection, r2d2::Error> for ConnectionCustomizer {
+    fn on_acquire(&self, conn: &mut DbConnection) -> Result<(), r2d2::Error> {
+        // Postgres always enforces foreign keys.
+        if self.read_only {
+            conn.batch_execute(
+                "SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY;",
+            )
+            .map_err(r2d2::Error::QueryError)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs every connection in a transaction that is never committed, so tests
+/// can share a single database.
+#[derive(Debug)]
+struct TestTransactionCustomizer;
+
+impl CustomizeConnection<DbConnection, r2d2::Error>
+    for TestTransactionCustomizer
+{
+    fn on_acquire(&self, conn: &mut DbConnection) -> Result<(), r2d2::Error> {
+        ConnectionCustomizer { read_only: false }.on_acquire(conn)?;
+        conn.begin_test_transaction()
+            .map_err(r2d2::Error::QueryError)
+    }
+}
+
 /// Creates a database connection pool using the provided connection string to
 /// connect to the database.
 ///
@@ -39,18 +130,106 @@ impl CustomizeConnection<DbConnection, r2d2::Error>
 /// let connection = db_pool.get().unwrap();
 /// ```
 pub fn create_pool(database_url: &str) -> BackendResult<DbConnectionPool> {
-    let manager =
-        r2d2::ConnectionManager::<diesel::sqlite::SqliteConnection>::new(
-            database_url,
-        );
+    create_pool_with_events(
+        database_url,
+        DEFAULT_POOL_SIZE,
+        Box::new(SlowConnectionLogger {
+            threshold: LogConfig::default().slow_connection_threshold,
+        }),
+    )
+}
+
+/// Like `create_pool`, but with up to `max_size` connections and passes the
+/// checkout and checkin events of the pool to the given handler, e.g. to log
+/// slow connections or record metrics.
+pub fn create_pool_with_events(
+    database_url: &str,
+    max_size: u32,
+    event_handler: Box<dyn HandleEvent>,
+) -> BackendResult<DbConnectionPool> {
+    let manager = r2d2::ConnectionManager::<DbConnection>::new(database_url);
+    let pool = r2d2::Pool::builder()
+        .max_size(max_size)
+        .connection_customizer(Box::new(ConnectionCustomizer {
+            read_only: false,
+        }))
+        .event_handler(event_handler)
+        .build(manager)?;
+    Ok(pool)
+}
+
+/// Creates a pool of up to `max_size` read-only connections. Create it after
+/// the writer pool of `create_pool`, which switches sqlite to WAL mode.
+pub fn create_read_pool(
+    database_url: &str,
+    max_size: u32,
+    event_handler: Box<dyn HandleEvent>,
+) -> BackendResult<DbReadPool> {
+    let manager = r2d2::ConnectionManager::<DbConnection>::new(database_url);
+    let pool = r2d2::Pool::builder()
+        .max_size(max_size)
+        .connection_customizer(Box::new(ConnectionCustomizer {
+            read_only: true,
+        }))
+        .event_handler(event_handler)
+        .build(manager)?;
+    Ok(DbReadPool(pool))
+}
+
+/// The number of attempts of `retry_on_busy`.
+const BUSY_ATTEMPTS: u32 = 5;
+
+/// Whether the error is caused by a database locked by another connection.
+fn is_busy(err: &BackendError) -> bool {
+    match err {
+        BackendError::Diesel(diesel::result::Error::DatabaseError(_, info)) => {
+            info.message().starts_with("database is locked")
+        }
+        _ => false,
+    }
+}
+
+/// Calls `f` and calls it again with an exponential backoff as long as it
+/// fails because the database is locked, which can happen despite the busy
+/// timeout, e.g. if a transaction that read first tries to write. `f` should
+/// run a transaction, so that retrying it does not repeat partial writes.
+pub fn retry_on_busy<T, F>(mut f: F) -> BackendResult<T>
+where
+    F: FnMut() -> BackendResult<T>,
+{
+    let mut backoff = Duration::from_millis(20);
+    for _ in 1..BUSY_ATTEMPTS {
+        match f() {
+            Err(ref err) if is_busy(err) => {
+                log::warn!("Database is locked, retrying in {:?}", backoff);
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+    f()
+}
+
+/// Creates a pool for tests. With sqlite the database lives in memory, with
+/// postgres the database at `MN_TEST_DATABASE_URL` is used. It must not
+/// contain any tables, since the pool only has a single connection that runs
+/// in a transaction that is rolled back once the pool is dropped. The
+/// migrations are not run.
+pub fn create_test_pool() -> BackendResult<DbConnectionPool> {
+    #[cfg(not(feature = "postgres"))]
+    let database_url = String::from(":memory:");
+    #[cfg(feature = "postgres")]
+    let database_url = std::env::var("MN_TEST_DATABASE_URL").map_err(|_| {
+        BackendError::InvalidConfig(String::from(
+            "MN_TEST_DATABASE_URL is required to run the tests with postgres",
+        ))
+    })?;
+
+    let manager = r2d2::ConnectionManager::<DbConnection>::new(database_url);
     let pool = r2d2::Pool::builder()
-        // Since sqlite only supports one write at a time, concurrent write
-        // tries result in a database locked error by diesel. To avoid
-        // that, for now just use at max 1 connection. This could be
-        // improved by either retrying on that error or use another
-        // connection pool just for read operations.
         .max_size(1)
-        .connection_customizer(Box::new(SqliteConnectionCustomizer))
+        .connection_customizer(Box::new(TestTransactionCustomizer))
         .build(manager)?;
     Ok(pool)
 }
@@ -61,3 +240,71 @@ pub fn run_migrations(conn: &DbConnection) -> BackendResult<()> {
     embedded_migrations::run_with_output(&*conn, &mut std::io::stdout())?;
     Ok(())
 }
+
+/// Runs a trivial query to check that the database is usable.
+pub fn ping(conn: &DbConnection) -> BackendResult<()> {
+    conn.execute("SELECT 1")?;
+    Ok(())
+}
+
+/// The prefix of each line diesel writes when running a migration, followed by
+/// the version of the migration.
+const MIGRATION_OUTPUT_PREFIX: &str = "Running migration ";
+
+/// The versions of the embedded migrations, generated by build.rs.
+const MIGRATION_VERSIONS: &[&str] =
+    include!(concat!(env!("OUT_DIR"), "/migration_versions.rs"));
+
+#[derive(QueryableByName)]
+struct TableCount {
+    #[sql_type = "BigInt"]
+    count: i64,
+}
+
+#[cfg(not(feature = "postgres"))]
+const MIGRATIONS_TABLE_QUERY: &str = "SELECT COUNT(*) AS count \
+     FROM sqlite_master \
+     WHERE type = 'table' AND name = '__diesel_schema_migrations'";
+#[cfg(feature = "postgres")]
+const MIGRATIONS_TABLE_QUERY: &str = "SELECT COUNT(*) AS count \
+     FROM information_schema.tables \
+     WHERE table_schema = current_schema() \
+     AND table_name = '__diesel_schema_migrations'";
+
+/// Returns the versions of the embedded migrations that are not applied to the
+/// connected database yet. It only reads the table of the applied migrations,
+/// which does not exist before the first migration run, so it works with a
+/// read-only connection.
+pub fn pending_migrations(conn: &DbConnection) -> BackendResult<Vec<String>> {
+    let tables: TableCount =
+        diesel::sql_query(MIGRATIONS_TABLE_QUERY).get_result(conn)?;
+    let applied = if tables.count > 0 {
+        conn.previously_run_migration_versions()?
+    } else {
+        HashSet::new()
+    };
+
+    Ok(MIGRATION_VERSIONS
+        .iter()
+        .filter(|version| !applied.contains(**version))
+        .map(|version| String::from(*version))
+        .collect())
+}
+
+/// Runs the pending migrations like `run_migrations`, but returns the versions
+/// of the migrations that were run instead of printing them.
+pub fn run_pending_migrations(
+    conn: &DbConnection,
+) -> BackendResult<Vec<String>> {
+    let mut output = Vec::new();
+    embedded_migrations::run_with_output(&*conn, &mut output)?;
+    Ok(migration_versions(&output))
+}
+
+fn migration_versions(output: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .filter(|line| line.starts_with(MIGRATION_OUTPUT_PREFIX))
+        .map(|line| String::from(&line[MIGRATION_OUTPUT_PREFIX.len()..]))
+        .collect()
+}
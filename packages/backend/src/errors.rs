@@ -3,14 +3,20 @@ use diesel::result::DatabaseErrorKind;
 use rocket::config::Environment;
 use rocket::http::Status;
 use rocket::response::Responder;
-use rocket::{Request, Response};
+use rocket::{Outcome, Request, Response, State};
+use rocket_contrib::json::Json;
+use serde_json::json;
 use std::fmt;
-use std::io::Cursor;
+
+use crate::metrics::Metrics;
+
+pub use api_types::{codes, ErrorBody};
 
 pub type BackendResult<T> = Result<T, BackendError>;
 
 #[derive(Debug)]
 pub enum BackendError {
+    Argon2(argon2::Error),
     Bcrypt(bcrypt::BcryptError),
     Diesel(diesel::result::Error),
     DieselConnectionError(diesel::ConnectionError),
@@ -20,23 +26,52 @@ pub enum BackendError {
     /// diesel as diesel::r2d2::PoolError.
     R2D2Pool(r2d2::PoolError),
     EnvError(std::env::VarError),
+    Io(std::io::Error),
+    /// The configuration is invalid. Contains a description of the problem.
+    InvalidConfig(String),
+    /// A database snapshot can not be read or restored. Contains the reason.
+    InvalidSnapshot(String),
     JwtError(jsonwebtoken::errors::Error),
+    Prometheus(prometheus::Error),
     RocketCors(rocket_cors::Error),
     InvalidCredentials,
+    /// The personal access token used to authenticate is expired.
+    TokenExpired,
+    /// The request is authenticated but lacks the permission for the action,
+    /// e.g. an access token without the required scope.
+    Forbidden,
+    /// The system random number generator failed to generate a token.
+    TokenGeneration,
+    /// The system random number generator failed to generate a salt.
+    SaltGeneration,
+    /// Logins are blocked due to too many failed attempts. Contains the number
+    /// of seconds until the next attempt is allowed.
+    TooManyAttempts(i64),
     InvalidValue,
+    /// The password was rejected by the password policy. Contains the reason.
+    WeakPassword(String),
     NotFound,
+    /// There is no node at the given path.
+    PathNotFound(Vec<String>),
     Conflict,
     /// Indicates that a given file or directory name contains at least one
     /// invalid character.
     InvalidNodeName(String),
+    /// A git repository of the history can not be read or written.
+    Git(git2::Error),
 }
 
 impl BackendError {
     pub fn status(&self) -> Status {
         match self {
             BackendError::InvalidCredentials => Status::Unauthorized,
+            BackendError::TokenExpired => Status::Unauthorized,
+            BackendError::Forbidden => Status::Forbidden,
+            BackendError::TooManyAttempts(_) => Status::TooManyRequests,
             BackendError::InvalidValue => Status::UnprocessableEntity,
+            BackendError::WeakPassword(_) => Status::UnprocessableEntity,
             BackendError::NotFound => Status::NotFound,
+            BackendError::PathNotFound(_) => Status::NotFound,
             BackendError::Diesel(diesel::result::Error::NotFound) => {
                 Status::NotFound
             }
@@ -49,11 +84,106 @@ impl BackendError {
             _ => Status::InternalServerError,
         }
     }
+
+    /// A stable, machine-readable code identifying the kind of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            BackendError::InvalidCredentials => codes::INVALID_CREDENTIALS,
+            BackendError::TokenExpired => codes::TOKEN_EXPIRED,
+            BackendError::Forbidden => codes::FORBIDDEN,
+            BackendError::TooManyAttempts(_) => codes::TOO_MANY_ATTEMPTS,
+            BackendError::InvalidValue => codes::INVALID_VALUE,
+            BackendError::WeakPassword(_) => codes::WEAK_PASSWORD,
+            BackendError::NotFound
+            | BackendError::PathNotFound(_)
+            | BackendError::Diesel(diesel::result::Error::NotFound) => {
+                codes::NOT_FOUND
+            }
+            BackendError::Conflict
+            | BackendError::Diesel(diesel::result::Error::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                _,
+            )) => codes::NAME_CONFLICT,
+            BackendError::InvalidNodeName(_) => codes::NODE_NAME_INVALID,
+            _ => codes::INTERNAL_ERROR,
+        }
+    }
+
+    /// The name of the variant, used to count errors by kind.
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            BackendError::Argon2(_) => "Argon2",
+            BackendError::Bcrypt(_) => "Bcrypt",
+            BackendError::Diesel(_) => "Diesel",
+            BackendError::DieselConnectionError(_) => "DieselConnectionError",
+            BackendError::DieselMigration(_) => "DieselMigration",
+            BackendError::R2D2(_) => "R2D2",
+            BackendError::R2D2Pool(_) => "R2D2Pool",
+            BackendError::EnvError(_) => "EnvError",
+            BackendError::Io(_) => "Io",
+            BackendError::InvalidConfig(_) => "InvalidConfig",
+            BackendError::InvalidSnapshot(_) => "InvalidSnapshot",
+            BackendError::JwtError(_) => "JwtError",
+            BackendError::Prometheus(_) => "Prometheus",
+            BackendError::RocketCors(_) => "RocketCors",
+            BackendError::InvalidCredentials => "InvalidCredentials",
+            BackendError::TokenExpired => "TokenExpired",
+            BackendError::Forbidden => "Forbidden",
+            BackendError::TokenGeneration => "TokenGeneration",
+            BackendError::SaltGeneration => "SaltGeneration",
+            BackendError::TooManyAttempts(_) => "TooManyAttempts",
+            BackendError::InvalidValue => "InvalidValue",
+            BackendError::WeakPassword(_) => "WeakPassword",
+            BackendError::NotFound => "NotFound",
+            BackendError::PathNotFound(_) => "PathNotFound",
+            BackendError::Conflict => "Conflict",
+            BackendError::InvalidNodeName(_) => "InvalidNodeName",
+            BackendError::Git(_) => "Git",
+        }
+    }
+
+    /// Additional information about the error that helps a client to handle
+    /// it, like the offending path.
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            BackendError::TooManyAttempts(retry_after) => {
+                Some(json!({ "retryAfter": retry_after }))
+            }
+            BackendError::WeakPassword(reason) => {
+                Some(json!({ "reason": reason }))
+            }
+            BackendError::PathNotFound(path) => Some(json!({ "path": path })),
+            BackendError::InvalidNodeName(name) => {
+                Some(json!({ "name": name }))
+            }
+            _ => None,
+        }
+    }
+
+    /// Builds the body of the error response. The message of internal errors
+    /// is only revealed if `expose_internals` is set, since it may contain
+    /// details about the database or the configuration.
+    pub fn to_body(&self, expose_internals: bool) -> ErrorBody {
+        let message = if self.status() == Status::InternalServerError
+            && !expose_internals
+        {
+            String::from("Internal server error")
+        } else {
+            self.to_string()
+        };
+
+        ErrorBody {
+            code: String::from(self.code()),
+            message,
+            details: self.details(),
+        }
+    }
 }
 
 impl fmt::Display for BackendError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            BackendError::Argon2(err) => write!(f, "Argon2 error: {}", err),
             BackendError::Bcrypt(err) => write!(f, "Bcrypt error: {}", err),
             BackendError::Diesel(err) => write!(f, "Diesel error: {}", err),
             BackendError::DieselConnectionError(err) => {
@@ -67,19 +197,49 @@ impl fmt::Display for BackendError {
                 write!(f, "r2d2 pool error: {}", err)
             }
             BackendError::EnvError(err) => write!(f, "Env var error: {}", err),
+            BackendError::Io(err) => write!(f, "IO error: {}", err),
+            BackendError::InvalidConfig(reason) => {
+                write!(f, "Invalid configuration: {}", reason)
+            }
+            BackendError::InvalidSnapshot(reason) => {
+                write!(f, "Invalid snapshot: {}", reason)
+            }
             BackendError::JwtError(err) => write!(f, "JWT error: {}", err),
+            BackendError::Prometheus(err) => {
+                write!(f, "Prometheus error: {}", err)
+            }
             BackendError::RocketCors(err) => {
                 write!(f, "rocket cors error: {}", err)
             }
             BackendError::InvalidCredentials => {
                 write!(f, "Invalid credentials")
             }
+            BackendError::TokenExpired => write!(f, "Token expired"),
+            BackendError::Forbidden => write!(f, "Forbidden"),
+            BackendError::TokenGeneration => {
+                write!(f, "Failed to generate a random token")
+            }
+            BackendError::SaltGeneration => {
+                write!(f, "Failed to generate a random salt")
+            }
+            BackendError::TooManyAttempts(retry_after) => write!(
+                f,
+                "Too many failed login attempts, retry in {} seconds",
+                retry_after
+            ),
             BackendError::InvalidValue => write!(f, "Invalid value"),
+            BackendError::WeakPassword(reason) => {
+                write!(f, "Weak password: {}", reason)
+            }
             BackendError::NotFound => write!(f, "Entity not found"),
+            BackendError::PathNotFound(path) => {
+                write!(f, "No node found at /{}", path.join("/"))
+            }
             BackendError::Conflict => write!(f, "Conflict"),
             BackendError::InvalidNodeName(name) => {
                 write!(f, "Invalid node name: {}", name)
             }
+            BackendError::Git(err) => write!(f, "Git error: {}", err),
         }
     }
 }
@@ -88,6 +248,7 @@ impl std::error::Error for BackendError {
     #[allow(deprecated)]
     fn description(&self) -> &str {
         match self {
+            BackendError::Argon2(err) => err.description(),
             BackendError::Bcrypt(err) => err.description(),
             BackendError::Diesel(err) => err.description(),
             BackendError::DieselConnectionError(err) => err.description(),
@@ -95,29 +256,55 @@ impl std::error::Error for BackendError {
             BackendError::R2D2(err) => err.description(),
             BackendError::R2D2Pool(err) => err.description(),
             BackendError::EnvError(err) => err.description(),
+            BackendError::Io(err) => err.description(),
+            BackendError::InvalidConfig(_) => "Invalid configuration",
+            BackendError::InvalidSnapshot(_) => "Invalid snapshot",
             BackendError::JwtError(err) => err.description(),
+            BackendError::Prometheus(err) => err.description(),
             BackendError::RocketCors(err) => err.description(),
             BackendError::InvalidCredentials => "Invalid credentials",
+            BackendError::TokenExpired => "Token expired",
+            BackendError::Forbidden => "Forbidden",
+            BackendError::TokenGeneration => {
+                "Failed to generate a random token"
+            }
+            BackendError::SaltGeneration => "Failed to generate a random salt",
+            BackendError::TooManyAttempts(_) => {
+                "Too many failed login attempts"
+            }
             BackendError::InvalidValue => "Invalid value",
+            BackendError::WeakPassword(_) => "Weak password",
             BackendError::NotFound => "Entity not found",
+            BackendError::PathNotFound(_) => "No node found at path",
             BackendError::Conflict => "Conflict",
             BackendError::InvalidNodeName(_) => "Invalid node name",
+            BackendError::Git(err) => err.description(),
         }
     }
 }
 
 impl Responder<'static> for BackendError {
-    fn respond_to(self, _: &Request) -> Result<Response<'static>, Status> {
+    fn respond_to(self, req: &Request) -> Result<Response<'static>, Status> {
         let status = self.status();
-        println!("Respond {} for error \"{}\"", status, self);
-
-        match Environment::active() {
-            Ok(Environment::Development) => Response::build()
-                .status(status)
-                .sized_body(Cursor::new(format!("{{ \"err\": \"{}\" }}", self)))
-                .ok(),
-            _ => Response::build().status(status).ok(),
+        if status == Status::InternalServerError {
+            log::error!("Respond {} for error \"{}\"", status, self);
+        } else {
+            log::info!("Respond {} for error \"{}\"", status, self);
+        }
+        if let Outcome::Success(metrics) = req.guard::<State<Metrics>>() {
+            metrics.observe_error(&self);
         }
+
+        let expose_internals =
+            matches!(Environment::active(), Ok(Environment::Development));
+        let body = Json(self.to_body(expose_internals)).respond_to(req)?;
+        let mut response = Response::build_from(body);
+        response.status(status);
+        if let BackendError::TooManyAttempts(retry_after) = &self {
+            response.raw_header("Retry-After", retry_after.to_string());
+        }
+
+        response.ok()
     }
 }
 
@@ -131,6 +318,7 @@ macro_rules! impl_from_error {
     };
 }
 
+impl_from_error!(argon2::Error, BackendError::Argon2);
 impl_from_error!(bcrypt::BcryptError, BackendError::Bcrypt);
 impl_from_error!(diesel::ConnectionError, BackendError::DieselConnectionError);
 impl_from_error!(
@@ -138,7 +326,10 @@ impl_from_error!(
     BackendError::DieselMigration
 );
 impl_from_error!(std::env::VarError, BackendError::EnvError);
+impl_from_error!(std::io::Error, BackendError::Io);
+impl_from_error!(git2::Error, BackendError::Git);
 impl_from_error!(jsonwebtoken::errors::Error, BackendError::JwtError);
+impl_from_error!(prometheus::Error, BackendError::Prometheus);
 impl_from_error!(r2d2::Error, BackendError::R2D2);
 impl_from_error!(r2d2::PoolError, BackendError::R2D2Pool);
 impl_from_error!(rocket_cors::Error, BackendError::RocketCors);
@@ -148,10 +339,13 @@ impl From<diesel::result::Error> for BackendError {
     fn from(err: diesel::result::Error) -> BackendError {
         match err {
             diesel::result::Error::NotFound => BackendError::NotFound,
+            // With postgres this includes duplicate root node names, which
+            // are prevented by the nodes__unique_root_name index.
             diesel::result::Error::DatabaseError(
                 DatabaseErrorKind::UniqueViolation,
                 _,
             ) => BackendError::Conflict,
+            #[cfg(not(feature = "postgres"))]
             diesel::result::Error::DatabaseError(_, ref inner_err) => {
                 match inner_err.message() {
                     // Error message by the CheckInsertUniqueRootName and
@@ -167,3 +361,29 @@ impl From<diesel::result::Error> for BackendError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_escapes_the_error_body() -> Result<(), serde_json::Error> {
+        let err = BackendError::InvalidNodeName(String::from("a\"b"));
+        let body = serde_json::to_string(&err.to_body(false))?;
+        let body: serde_json::Value = serde_json::from_str(&body)?;
+
+        assert_eq!(body["code"], "node_name_invalid");
+        assert_eq!(body["details"]["name"], "a\"b");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_hides_internal_errors() {
+        let err = BackendError::InvalidConfig(String::from("secret detail"));
+
+        assert_eq!(err.to_body(false).code, "internal_error");
+        assert!(!err.to_body(false).message.contains("secret detail"));
+        assert!(err.to_body(true).message.contains("secret detail"));
+    }
+}
@@ -0,0 +1,483 @@
+//! Writes the node tree of a user to a directory and reads it back, so a
+//! notebook can be moved between servers or inspected with other tools.
+//!
+//! Directories become folders and files become `.md` files, e.g. the file
+//! `Projects/Todo` is written to `<dir>/Projects/Todo.md`. Reading a directory
+//! back ignores hidden entries and files without the `.md` extension. Node
+//! names never start with a `.`, so no node is lost on the way. Nodes with
+//! such names from older databases abort an export before anything is written.
+
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+
+use crate::models::schema::nodes;
+use crate::models::{
+    NewNodePayload, Node, NodeId, NodeName, NodeQueries, OwnedPath, UserId,
+};
+use crate::{BackendError, BackendResult, DbConnection};
+
+pub use api_types::NOTE_EXTENSION;
+
+/// How an import treats the nodes the user already has.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImportMode {
+    /// Keeps the existing nodes, adds the missing ones and overwrites the
+    /// content of files that differ.
+    Merge,
+    /// Deletes all nodes of the user before adding the imported ones.
+    Replace,
+}
+
+/// A change an import makes to the nodes of a user, in the order they are
+/// applied.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum Change {
+    Delete {
+        path: OwnedPath,
+        #[serde(skip)]
+        node_id: NodeId,
+    },
+    Create {
+        path: OwnedPath,
+        #[serde(skip)]
+        content: Option<String>,
+    },
+    Update {
+        path: OwnedPath,
+        #[serde(skip)]
+        node_id: NodeId,
+        #[serde(skip)]
+        content: String,
+    },
+    /// The node is a file on one side and a directory on the other side and
+    /// is kept as it is.
+    Conflict { path: OwnedPath },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Delete { path, .. } => {
+                write!(f, "delete {}", path.join("/"))
+            }
+            Change::Create { path, content } => write!(
+                f,
+                "create {}{}",
+                path.join("/"),
+                if content.is_none() { "/" } else { "" }
+            ),
+            Change::Update { path, .. } => {
+                write!(f, "update {}", path.join("/"))
+            }
+            Change::Conflict { path } => {
+                write!(f, "conflict {} (kept)", path.join("/"))
+            }
+        }
+    }
+}
+
+/// A node read from the directory. Directories have no content.
+struct Entry {
+    name: NodeName,
+    content: Option<String>,
+    children: Vec<Entry>,
+}
+
+/// Writes all nodes of the user into `dir`, which must not exist or be empty.
+/// Returns the number of written nodes.
+pub fn export(
+    conn: &DbConnection,
+    user_id: &UserId,
+    dir: &std::path::Path,
+) -> BackendResult<usize> {
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} is not empty", dir.display()),
+        )
+        .into());
+    }
+
+    let children = children_by_parent(Node::fetch_all_for_user(conn, user_id)?);
+    check_names(&children, None, &mut Vec::new())?;
+    fs::create_dir_all(dir)?;
+    write_nodes(&children, None, dir)
+}
+
+/// Fails with `BackendError::InvalidNodeName` and the path of the first node
+/// that can not be written: nodes with invalid names, e.g. `..` or hidden
+/// ones, and directories named like the file of a sibling, e.g. `Todo.md` next
+/// to the file `Todo`.
+fn check_names(
+    children: &HashMap<Option<NodeId>, Vec<Node>>,
+    parent_id: Option<NodeId>,
+    path: &mut OwnedPath,
+) -> BackendResult<()> {
+    let mut taken = HashSet::new();
+    for node in children.get(&parent_id).into_iter().flatten() {
+        path.push(node.node_name.clone());
+        let entry_name = if node.is_directory {
+            node.node_name.clone()
+        } else {
+            format!("{}{}", node.node_name, NOTE_EXTENSION)
+        };
+        if !Node::is_name_valid(&node.node_name) || !taken.insert(entry_name) {
+            return Err(BackendError::InvalidNodeName(path.join("/")));
+        }
+        check_names(children, Some(node.node_id), path)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+fn write_nodes(
+    children: &HashMap<Option<NodeId>, Vec<Node>>,
+    parent_id: Option<NodeId>,
+    dir: &std::path::Path,
+) -> BackendResult<usize> {
+    let mut count = 0;
+    for node in children.get(&parent_id).into_iter().flatten() {
+        if node.is_directory {
+            let path = dir.join(&node.node_name);
+            fs::create_dir(&path)?;
+            count += write_nodes(children, Some(node.node_id), &path)?;
+        } else {
+            let path =
+                dir.join(format!("{}{}", node.node_name, NOTE_EXTENSION));
+            let content = node.content.as_deref().unwrap_or_default();
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)?
+                .write_all(content.as_bytes())?;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Reads the nodes in `dir` and returns the changes that make the nodes of
+/// the user match them. The changes are only applied if `dry_run` is false,
+/// in a single transaction.
+pub fn import(
+    conn: &DbConnection,
+    user_id: &UserId,
+    dir: &std::path::Path,
+    mode: ImportMode,
+    dry_run: bool,
+) -> BackendResult<Vec<Change>> {
+    let entries = read_entries(dir, &mut Vec::new())?;
+
+    conn.transaction::<_, BackendError, _>(|| {
+        let children =
+            children_by_parent(Node::fetch_all_for_user(conn, user_id)?);
+        let mut changes = Vec::new();
+        match mode {
+            ImportMode::Merge => {
+                merge(&children, None, &entries, &mut Vec::new(), &mut changes)
+            }
+            ImportMode::Replace => {
+                for node in children.get(&None).into_iter().flatten() {
+                    changes.push(Change::Delete {
+                        path: vec![node.node_name.clone()],
+                        node_id: node.node_id,
+                    });
+                }
+                create(&entries, &mut Vec::new(), &mut changes);
+            }
+        }
+
+        if !dry_run {
+            apply(conn, user_id, &changes)?;
+        }
+        Ok(changes)
+    })
+}
+
+/// Reads the entries of `dir` sorted by name. `path` is the path of `dir`
+/// relative to the imported directory.
+fn read_entries(
+    dir: &std::path::Path,
+    path: &mut OwnedPath,
+) -> BackendResult<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let file_name = dir_entry.file_name();
+        let file_name = match file_name.to_str() {
+            Some(file_name) => file_name,
+            None => {
+                return Err(BackendError::InvalidNodeName(
+                    file_name.to_string_lossy().into_owned(),
+                ))
+            }
+        };
+        if file_name.starts_with('.') {
+            continue;
+        }
+
+        let entry = if dir_entry.file_type()?.is_dir() {
+            path.push(String::from(file_name));
+            let children = read_entries(&dir_entry.path(), path)?;
+            path.pop();
+            Entry {
+                name: String::from(file_name),
+                content: None,
+                children,
+            }
+        } else if let Some(name) = file_name.strip_suffix(NOTE_EXTENSION) {
+            Entry {
+                name: String::from(name),
+                content: Some(fs::read_to_string(dir_entry.path())?),
+                children: Vec::new(),
+            }
+        } else {
+            continue;
+        };
+
+        if !Node::is_name_valid(&entry.name) {
+            path.push(entry.name);
+            return Err(BackendError::InvalidNodeName(path.join("/")));
+        }
+        entries.push(entry);
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    // `a.md` and the directory `a` would be the same node.
+    if let Some(pair) =
+        entries.windows(2).find(|pair| pair[0].name == pair[1].name)
+    {
+        path.push(pair[0].name.clone());
+        return Err(BackendError::InvalidNodeName(path.join("/")));
+    }
+    Ok(entries)
+}
+
+fn children_by_parent(nodes: Vec<Node>) -> HashMap<Option<NodeId>, Vec<Node>> {
+    let mut children: HashMap<Option<NodeId>, Vec<Node>> = HashMap::new();
+    for node in nodes {
+        children.entry(node.parent_id).or_default().push(node);
+    }
+    for nodes in children.values_mut() {
+        nodes.sort_by(|a, b| a.node_name.cmp(&b.node_name));
+    }
+    children
+}
+
+fn merge(
+    children: &HashMap<Option<NodeId>, Vec<Node>>,
+    parent_id: Option<NodeId>,
+    entries: &[Entry],
+    path: &mut OwnedPath,
+    changes: &mut Vec<Change>,
+) {
+    let existing = children.get(&parent_id).map(Vec::as_slice).unwrap_or(&[]);
+    for entry in entries {
+        path.push(entry.name.clone());
+        let node = existing.iter().find(|node| node.node_name == entry.name);
+        match (node, &entry.content) {
+            (None, _) => {
+                changes.push(Change::Create {
+                    path: path.clone(),
+                    content: entry.content.clone(),
+                });
+                create(&entry.children, path, changes);
+            }
+            (Some(node), None) if node.is_directory => merge(
+                children,
+                Some(node.node_id),
+                &entry.children,
+                path,
+                changes,
+            ),
+            (Some(node), Some(content)) if !node.is_directory => {
+                if node.content.as_ref() != Some(content) {
+                    changes.push(Change::Update {
+                        path: path.clone(),
+                        node_id: node.node_id,
+                        content: content.clone(),
+                    });
+                }
+            }
+            (Some(_), _) => {
+                changes.push(Change::Conflict { path: path.clone() })
+            }
+        }
+        path.pop();
+    }
+}
+
+/// Adds a change creating each entry and its children below `path`.
+fn create(entries: &[Entry], path: &mut OwnedPath, changes: &mut Vec<Change>) {
+    for entry in entries {
+        path.push(entry.name.clone());
+        changes.push(Change::Create {
+            path: path.clone(),
+            content: entry.content.clone(),
+        });
+        create(&entry.children, path, changes);
+        path.pop();
+    }
+}
+
+fn apply(
+    conn: &DbConnection,
+    user_id: &UserId,
+    changes: &[Change],
+) -> BackendResult<()> {
+    for change in changes {
+        match change {
+            Change::Delete { node_id, .. } => {
+                diesel::delete(nodes::table.find(node_id)).execute(conn)?;
+            }
+            Change::Create { path, content } => {
+                let (name, parent_path) = path.split_last().unwrap();
+                Node::insert(
+                    conn,
+                    user_id,
+                    parent_path,
+                    &NewNodePayload {
+                        name: name.clone(),
+                        is_directory: content.is_none(),
+                        content: content.clone(),
+                    },
+                )?;
+            }
+            Change::Update {
+                node_id, content, ..
+            } => {
+                diesel::update(nodes::table.find(node_id))
+                    .set(nodes::content.eq(content))
+                    .execute(conn)?;
+            }
+            Change::Conflict { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+    use crate::user_management;
+
+    fn insert(
+        conn: &DbConnection,
+        owner_id: UserId,
+        parent_path: &[&str],
+        name: &str,
+        content: Option<&str>,
+    ) {
+        let parent_path: Vec<String> =
+            parent_path.iter().map(|part| String::from(*part)).collect();
+        Node::insert(
+            conn,
+            &owner_id,
+            &parent_path,
+            &NewNodePayload {
+                name: String::from(name),
+                is_directory: content.is_none(),
+                content: content.map(String::from),
+            },
+        )
+        .unwrap();
+    }
+
+    fn content(conn: &DbConnection, owner_id: UserId, path: &[&str]) -> String {
+        let path: Vec<String> =
+            path.iter().map(|part| String::from(*part)).collect();
+        Node::fetch_by_path_for_user(conn, &owner_id, &path)
+            .unwrap()
+            .content
+            .unwrap()
+    }
+
+    #[test]
+    fn it_exports_and_imports_a_notebook() -> BackendResult<()> {
+        let pool = database::create_test_pool()?;
+        let conn = pool.get()?;
+        database::run_migrations(&conn)?;
+        let alice = user_management::provision(&conn, "alice")?;
+        let bob = user_management::provision(&conn, "bob")?;
+
+        insert(&conn, alice.id, &[], "Projects", None);
+        insert(&conn, alice.id, &["Projects"], "Todo", Some("- [ ] export"));
+        insert(&conn, alice.id, &[], "Ideas", Some("notes"));
+
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        assert_eq!(export(&conn, &alice.id, dir)?, 3);
+        assert_eq!(
+            fs::read_to_string(dir.join("Projects/Todo.md"))?,
+            "- [ ] export"
+        );
+        // The directory must be empty.
+        assert!(export(&conn, &alice.id, dir).is_err());
+
+        // Bob has a file where alice has a directory, which is kept.
+        insert(&conn, bob.id, &[], "Projects", Some("bob's"));
+        insert(&conn, bob.id, &[], "Ideas", Some("old"));
+        fs::write(dir.join("README.txt"), "ignored")?;
+
+        let changes = import(&conn, &bob.id, dir, ImportMode::Merge, true)?;
+        let changes: Vec<String> =
+            changes.iter().map(ToString::to_string).collect();
+        assert_eq!(changes, vec!["update Ideas", "conflict Projects (kept)"]);
+        assert_eq!(content(&conn, bob.id, &["Ideas"]), "old");
+
+        import(&conn, &bob.id, dir, ImportMode::Merge, false)?;
+        assert_eq!(content(&conn, bob.id, &["Ideas"]), "notes");
+        assert_eq!(content(&conn, bob.id, &["Projects"]), "bob's");
+
+        import(&conn, &bob.id, dir, ImportMode::Replace, false)?;
+        assert_eq!(
+            content(&conn, bob.id, &["Projects", "Todo"]),
+            "- [ ] export"
+        );
+
+        // Invalid node names are rejected before anything is changed.
+        fs::write(dir.join("a|b.md"), "")?;
+        assert!(matches!(
+            import(&conn, &bob.id, dir, ImportMode::Replace, false),
+            Err(BackendError::InvalidNodeName(_))
+        ));
+        assert_eq!(Node::fetch_all_for_user(&conn, &bob.id)?.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_nothing_if_a_name_can_not_be_exported() -> BackendResult<()> {
+        let pool = database::create_test_pool()?;
+        let conn = pool.get()?;
+        database::run_migrations(&conn)?;
+        let alice = user_management::provision(&conn, "alice")?;
+        insert(&conn, alice.id, &[], "Projects", None);
+        insert(&conn, alice.id, &["Projects"], "Todo", Some("- [ ] export"));
+        insert(&conn, alice.id, &[], "Ideas", Some("notes"));
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path().join("export");
+
+        // Older databases may contain names the stores reject now.
+        for name in &[".notes", ".."] {
+            diesel::update(nodes::table.filter(nodes::parent_id.is_not_null()))
+                .set(nodes::node_name.eq(*name))
+                .execute(&conn)?;
+            match export(&conn, &alice.id, &dir) {
+                Err(BackendError::InvalidNodeName(path)) => {
+                    assert_eq!(path, format!("Projects/{}", name))
+                }
+                result => panic!("Unexpected result {:?}", result),
+            }
+            assert!(!dir.exists());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+//! Liveness and readiness checks, e.g. for the docker health check.
+
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::HealthSection;
+use crate::database::{self, DbReadPool};
+
+/// How long the readiness check waits for a database connection.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug)]
+pub struct HealthConfig {
+    /// The directory the free disk space is reported for, usually the one
+    /// containing the database file. `None` if the database is not stored
+    /// locally, e.g. with postgres.
+    pub data_dir: Option<PathBuf>,
+    /// The backend is not ready if less disk space is available.
+    pub min_free_disk_bytes: u64,
+}
+
+impl HealthConfig {
+    /// Creates a config for the directory of the database file at
+    /// `database_url`, if it is a sqlite database.
+    pub fn from_config(
+        database_url: &str,
+        cfg: &HealthSection,
+    ) -> HealthConfig {
+        let data_dir = if cfg!(feature = "postgres") {
+            None
+        } else {
+            match Path::new(database_url).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => {
+                    Some(dir.to_path_buf())
+                }
+                _ => Some(PathBuf::from(".")),
+            }
+        };
+
+        HealthConfig {
+            data_dir,
+            min_free_disk_bytes: cfg.min_free_disk_mb * 1024 * 1024,
+        }
+    }
+}
+
+/// The result of a single readiness check. The error is a fixed description,
+/// since the route is not authenticated; the cause is logged instead.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn passed() -> Check {
+        Check {
+            ok: true,
+            error: None,
+        }
+    }
+
+    fn failed(error: &str) -> Check {
+        Check {
+            ok: false,
+            error: Some(String::from(error)),
+        }
+    }
+
+    /// Fails with `error` and logs the cause if the result is an error.
+    fn from_result<T, E: fmt::Display>(
+        result: &Result<T, E>,
+        error: &str,
+    ) -> Check {
+        match result {
+            Ok(_) => Check::passed(),
+            Err(err) => {
+                log::warn!("{}: {}", error, err);
+                Check::failed(error)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationsCheck {
+    #[serde(flatten)]
+    pub check: Check,
+    /// The versions of the migrations that are not applied yet.
+    pub pending: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DiskCheck {
+    #[serde(flatten)]
+    pub check: Check,
+    pub free_bytes: Option<u64>,
+    pub min_free_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Readiness {
+    pub database: Check,
+    pub migrations: MigrationsCheck,
+    pub disk: DiskCheck,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.database.ok && self.migrations.check.ok && self.disk.check.ok
+    }
+}
+
+/// Checks whether the backend is able to handle requests: A connection can be
+/// checked out and queried, all migrations are applied and there is enough
+/// free disk space. The database checks only read, so they use the read pool
+/// and do not wait for the writer.
+pub fn check_readiness(pool: &DbReadPool, cfg: &HealthConfig) -> Readiness {
+    let (database, migrations) = match pool.get_timeout(CONNECTION_TIMEOUT) {
+        Ok(conn) => {
+            let query = database::ping(&conn);
+            let pending = database::pending_migrations(&conn);
+            let migrations = match &pending {
+                Ok(pending) if !pending.is_empty() => MigrationsCheck {
+                    check: Check::failed("Pending migrations"),
+                    pending: pending.clone(),
+                },
+                _ => MigrationsCheck {
+                    check: Check::from_result(
+                        &pending,
+                        "The migrations can not be read",
+                    ),
+                    pending: Vec::new(),
+                },
+            };
+            (Check::from_result(&query, "The query failed"), migrations)
+        }
+        Err(err) => {
+            let error = "No database connection available";
+            log::warn!("{}: {}", error, err);
+            (
+                Check::failed(error),
+                MigrationsCheck {
+                    check: Check::failed(error),
+                    pending: Vec::new(),
+                },
+            )
+        }
+    };
+
+    let disk = match &cfg.data_dir {
+        Some(data_dir) => {
+            let free_bytes = fs2::available_space(data_dir);
+            DiskCheck {
+                check: match &free_bytes {
+                    Ok(free) if *free < cfg.min_free_disk_bytes => {
+                        Check::failed("Not enough free disk space")
+                    }
+                    result => Check::from_result(
+                        result,
+                        "The free disk space can not be read",
+                    ),
+                },
+                free_bytes: free_bytes.ok(),
+                min_free_bytes: cfg.min_free_disk_bytes,
+            }
+        }
+        None => DiskCheck {
+            check: Check::passed(),
+            free_bytes: None,
+            min_free_bytes: cfg.min_free_disk_bytes,
+        },
+    };
+
+    Readiness {
+        database,
+        migrations,
+        disk,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BackendResult;
+
+    #[test]
+    fn it_reports_pending_migrations() -> BackendResult<()> {
+        let pool = database::create_test_pool()?;
+        let read_pool = DbReadPool::shared(&pool);
+        let cfg = HealthConfig {
+            data_dir: Some(std::env::temp_dir()),
+            min_free_disk_bytes: 0,
+        };
+
+        let readiness = check_readiness(&read_pool, &cfg);
+        assert!(readiness.database.ok);
+        assert!(!readiness.migrations.pending.is_empty());
+        assert!(!readiness.is_ready());
+
+        // The check does not run the migrations, so all of them are applied
+        // afterwards.
+        let applied = database::run_pending_migrations(&*pool.get()?)?;
+        assert_eq!(applied, readiness.migrations.pending);
+        let readiness = check_readiness(&read_pool, &cfg);
+        assert!(readiness.migrations.pending.is_empty());
+        assert!(readiness.is_ready());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,527 @@
+//! Mirrors the nodes of each user into a bare git repository, so the history
+//! of the notes can be read with `git log` and `git blame`.
+//!
+//! Every change of a node is committed with the user as author and the layout
+//! of `export`: folders for directories and `.md` files for files, see
+//! `entry_name` for the names git can not store. A commit only replaces the
+//! entries at the paths of its change, except for the first commit and bulk
+//! changes, which commit all nodes of the user. So changes made while the
+//! history was disabled, or whose commit failed, are part of the next bulk
+//! change. The repository of a user is `<dir>/<user id>.git`.
+//!
+//! `store::RecordingNodeStore` commits the changes of every write to a store.
+
+use git2::{
+    ErrorCode, ObjectType, Oid, Repository, Signature, Tree, TreeBuilder,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::config::HistorySection;
+use crate::errors::codes;
+use crate::export::NOTE_EXTENSION;
+use crate::models::{Node, NodeId, Path, UserId};
+use crate::store::{NodeRef, NodeStore};
+use crate::{BackendError, BackendResult};
+
+/// The branch the history is committed to.
+const BRANCH: &str = "refs/heads/master";
+
+/// The host of the author email addresses, `<username>@<host>`.
+const EMAIL_HOST: &str = "markdown-notebook";
+
+/// Git does not store empty directories, so they get this empty file.
+const KEEP_FILE: &str = ".keep";
+
+const FILE_MODE: i32 = 0o100_644;
+const TREE_MODE: i32 = 0o040_000;
+
+/// The change a commit records, used for the commit message.
+#[derive(Clone, Copy, Debug)]
+pub enum Change<'a> {
+    Create(&'a Path),
+    Edit(&'a Path),
+    Rename {
+        from: &'a Path,
+        to: &'a Path,
+    },
+    Move {
+        from: &'a Path,
+        to: &'a Path,
+    },
+    Delete(&'a Path),
+    /// Many nodes changed at once, e.g. by an import. Contains a description.
+    Bulk(&'a str),
+}
+
+impl fmt::Display for Change<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Create(path) => write!(f, "Create {}", path.join("/")),
+            Change::Edit(path) => write!(f, "Edit {}", path.join("/")),
+            Change::Rename { from, to } => write!(
+                f,
+                "Rename {} to {}",
+                from.join("/"),
+                to.last().map(String::as_str).unwrap_or_default()
+            ),
+            Change::Move { from, to } => {
+                write!(f, "Move {} to {}", from.join("/"), to.join("/"))
+            }
+            Change::Delete(path) => write!(f, "Delete {}", path.join("/")),
+            Change::Bulk(description) => write!(f, "{}", description),
+        }
+    }
+}
+
+impl Change<'_> {
+    /// The paths whose entries change, in the order they are replaced.
+    fn paths(&self) -> Vec<&Path> {
+        match *self {
+            Change::Create(path)
+            | Change::Edit(path)
+            | Change::Delete(path) => {
+                vec![path]
+            }
+            Change::Rename { from, to } | Change::Move { from, to } => {
+                vec![from, to]
+            }
+            Change::Bulk(_) => Vec::new(),
+        }
+    }
+}
+
+/// The repositories of all users. Clones share the locks, so the history can
+/// be given to a store and to the routes.
+#[derive(Clone)]
+pub struct History {
+    dir: PathBuf,
+    /// Commits of concurrent requests of a user are written one after another.
+    locks: Arc<Mutex<HashMap<UserId, Arc<Mutex<()>>>>>,
+}
+
+impl History {
+    pub fn new(dir: &std::path::Path) -> BackendResult<History> {
+        fs::create_dir_all(dir)?;
+        Ok(History {
+            dir: dir.to_path_buf(),
+            locks: Arc::default(),
+        })
+    }
+
+    /// Returns the history configured in the `history` section, or `None` if
+    /// it is disabled.
+    pub fn from_config(cfg: &HistorySection) -> BackendResult<Option<History>> {
+        match &cfg.dir {
+            Some(dir) => Ok(Some(Self::new(dir)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn repository_path(&self, user_id: UserId) -> PathBuf {
+        self.dir.join(format!("{}.git", user_id))
+    }
+
+    /// Returns the lock of the repository of the user.
+    fn lock(&self, user_id: UserId) -> Arc<Mutex<()>> {
+        let mut locks =
+            self.locks.lock().unwrap_or_else(|err| err.into_inner());
+        locks.entry(user_id).or_default().clone()
+    }
+
+    /// Commits the nodes of the user at the paths of the change, or all nodes
+    /// for the first commit and bulk changes. Returns `None` if they did not
+    /// change since the last commit.
+    pub fn commit(
+        &self,
+        store: &dyn NodeStore,
+        user_id: UserId,
+        username: &str,
+        change: Change,
+    ) -> BackendResult<Option<Oid>> {
+        let lock = self.lock(user_id);
+        let _guard = lock.lock().unwrap_or_else(|err| err.into_inner());
+
+        let path = self.repository_path(user_id);
+        let repo = if path.exists() {
+            Repository::open_bare(&path)?
+        } else {
+            let repo = Repository::init_bare(&path)?;
+            repo.set_head(BRANCH)?;
+            repo
+        };
+
+        let parent = match repo.refname_to_id(BRANCH) {
+            Ok(oid) => Some(repo.find_commit(oid)?),
+            Err(err) if err.code() == ErrorCode::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        let tree_id = match &parent {
+            Some(parent) if !matches!(change, Change::Bulk(_)) => {
+                let mut tree = parent.tree()?;
+                for path in change.paths() {
+                    let node = fetch_entry(&repo, store, user_id, path)?;
+                    let tree_id =
+                        replace_entry(&repo, Some(tree), path, node, true)?;
+                    tree = repo.find_tree(tree_id)?;
+                }
+                tree.id()
+            }
+            _ => {
+                let mut by_parent: HashMap<Option<NodeId>, Vec<Node>> =
+                    HashMap::new();
+                for node in store.fetch_all(user_id)? {
+                    by_parent.entry(node.parent_id).or_default().push(node);
+                }
+                let mut children = |parent_id: Option<NodeId>| {
+                    Ok(by_parent.remove(&parent_id).unwrap_or_default())
+                };
+                write_tree(&repo, &mut children, None)?
+            }
+        };
+        if parent.as_ref().map(|commit| commit.tree_id()) == Some(tree_id) {
+            return Ok(None);
+        }
+
+        let author =
+            Signature::now(username, &format!("{}@{}", username, EMAIL_HOST))?;
+        let tree = repo.find_tree(tree_id)?;
+        let parents: Vec<_> = parent.iter().collect();
+        let oid = repo.commit(
+            Some(BRANCH),
+            &author,
+            &author,
+            &change.to_string(),
+            &tree,
+            &parents,
+        )?;
+        Ok(Some(oid))
+    }
+
+    /// Commits like `commit` but only logs errors, for changes that are
+    /// already stored and must not fail because of the history.
+    pub fn record(
+        &self,
+        store: &dyn NodeStore,
+        user_id: UserId,
+        username: &str,
+        change: Change,
+    ) {
+        if let Err(err) = self.commit(store, user_id, username, change) {
+            log::error!(
+                "Failed to commit \"{}\" of user {} to the history: {}",
+                change,
+                user_id,
+                err
+            );
+        }
+    }
+
+    /// Deletes the repository of the user, e.g. before the user is deleted, so
+    /// a new user that gets the same id does not get the history.
+    pub fn delete(&self, user_id: UserId) -> BackendResult<()> {
+        let lock = self.lock(user_id);
+        let _guard = lock.lock().unwrap_or_else(|err| err.into_inner());
+
+        let path = self.repository_path(user_id);
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the repository of the user as a git bundle, which can be
+    /// cloned with `git clone <file>`. Returns `BackendError::NotFound` if
+    /// nothing was committed for the user yet.
+    pub fn bundle(&self, user_id: UserId) -> BackendResult<Vec<u8>> {
+        let lock = self.lock(user_id);
+        let _guard = lock.lock().unwrap_or_else(|err| err.into_inner());
+
+        let path = self.repository_path(user_id);
+        if !path.exists() {
+            return Err(BackendError::NotFound);
+        }
+        let repo = Repository::open_bare(&path)?;
+        let head = match repo.refname_to_id(BRANCH) {
+            Ok(oid) => oid,
+            Err(err) if err.code() == ErrorCode::NotFound => {
+                return Err(BackendError::NotFound)
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut walk = repo.revwalk()?;
+        walk.push(head)?;
+        let mut builder = repo.packbuilder()?;
+        builder.insert_walk(&mut walk)?;
+        let mut pack = git2::Buf::new();
+        builder.write_buf(&mut pack)?;
+
+        let mut bundle =
+            format!("# v2 git bundle\n{} {}\n{} HEAD\n\n", head, BRANCH, head)
+                .into_bytes();
+        bundle.extend_from_slice(&pack);
+        Ok(bundle)
+    }
+}
+
+/// Returns the name of the entry of a node in its tree. Git rejects names
+/// like `.git` or `..`, and a file `x` is stored as `x.md`, so names starting
+/// with `.` and directories ending in `.md` are escaped like `%`: `.git`
+/// becomes `%2Egit` and a directory `x.md` becomes `x%2Emd`. Neither collides
+/// with another node or the `.keep` file. The empty name becomes `%`.
+fn entry_name(name: &str, is_directory: bool) -> String {
+    let mut escaped = name.replace('%', "%25").replace('\0', "%00");
+    if escaped.starts_with('.') {
+        escaped.replace_range(..1, "%2E");
+    }
+    if is_directory && escaped.ends_with(NOTE_EXTENSION) {
+        let dot = escaped.len() - NOTE_EXTENSION.len();
+        escaped.replace_range(dot..=dot, "%2E");
+    }
+    if escaped.is_empty() {
+        escaped.push('%');
+    }
+    if !is_directory {
+        escaped.push_str(NOTE_EXTENSION);
+    }
+    escaped
+}
+
+/// An entry of a tree: its name, object and mode.
+type Entry = (String, Oid, i32);
+
+/// Writes the node at the path with everything below it. Returns `None` if
+/// there is no such node, e.g. since it was deleted.
+fn fetch_entry(
+    repo: &Repository,
+    store: &dyn NodeStore,
+    user_id: UserId,
+    path: &Path,
+) -> BackendResult<Option<Entry>> {
+    let node = match store.fetch(user_id, NodeRef::Path(path)) {
+        Ok(node) => node,
+        // The database reports some of them as diesel errors.
+        Err(err) if err.code() == codes::NOT_FOUND => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let name = entry_name(&node.node_name, node.is_directory);
+    if node.is_directory {
+        let mut children = |parent_id: Option<NodeId>| match parent_id {
+            Some(id) => store.list(user_id, NodeRef::Id(id)),
+            None => store.list(user_id, NodeRef::ROOT),
+        };
+        let tree_id = write_tree(repo, &mut children, Some(node.node_id))?;
+        Ok(Some((name, tree_id, TREE_MODE)))
+    } else {
+        let content = node.content.as_deref().unwrap_or_default();
+        Ok(Some((name, repo.blob(content.as_bytes())?, FILE_MODE)))
+    }
+}
+
+/// Replaces the entry of the node at `path` below `tree` by `entry`, or
+/// removes it for `None`. Returns the id of the new tree.
+fn replace_entry(
+    repo: &Repository,
+    tree: Option<Tree>,
+    path: &Path,
+    entry: Option<Entry>,
+    is_root: bool,
+) -> BackendResult<Oid> {
+    let mut builder = repo.treebuilder(tree.as_ref())?;
+    match path {
+        [] => (),
+        [name] => {
+            // The node may have been a file or a directory.
+            for is_directory in &[false, true] {
+                let old_name = entry_name(name, *is_directory);
+                if builder.get(old_name.as_str())?.is_some() {
+                    builder.remove(old_name)?;
+                }
+            }
+            if let Some((name, id, mode)) = entry {
+                builder.insert(name, id, mode)?;
+            }
+        }
+        [directory, rest @ ..] => {
+            let name = entry_name(directory, true);
+            let subtree = match builder.get(name.as_str())? {
+                Some(old) if old.kind() == Some(ObjectType::Tree) => {
+                    Some(repo.find_tree(old.id())?)
+                }
+                _ => None,
+            };
+            // Nothing to remove in a directory that was never committed.
+            if subtree.is_some() || entry.is_some() {
+                let tree_id = replace_entry(repo, subtree, rest, entry, false)?;
+                builder.insert(name, tree_id, TREE_MODE)?;
+            }
+        }
+    }
+    write_builder(repo, builder, is_root)
+}
+
+/// Writes the tree of the children of `parent_id`, as returned by `children`,
+/// and returns its id.
+fn write_tree(
+    repo: &Repository,
+    children: &mut dyn FnMut(Option<NodeId>) -> BackendResult<Vec<Node>>,
+    parent_id: Option<NodeId>,
+) -> BackendResult<Oid> {
+    let mut builder = repo.treebuilder(None)?;
+    for node in children(parent_id)? {
+        let name = entry_name(&node.node_name, node.is_directory);
+        if node.is_directory {
+            let tree_id = write_tree(repo, children, Some(node.node_id))?;
+            builder.insert(name, tree_id, TREE_MODE)?;
+        } else {
+            let content = node.content.as_deref().unwrap_or_default();
+            let blob_id = repo.blob(content.as_bytes())?;
+            builder.insert(name, blob_id, FILE_MODE)?;
+        }
+    }
+    write_builder(repo, builder, parent_id.is_none())
+}
+
+/// Writes the tree, with the `.keep` file if it is an empty directory.
+fn write_builder(
+    repo: &Repository,
+    mut builder: TreeBuilder,
+    is_root: bool,
+) -> BackendResult<Oid> {
+    if builder.is_empty() && !is_root {
+        builder.insert(KEEP_FILE, repo.blob(&[])?, FILE_MODE)?;
+    } else if builder.len() > 1 && builder.get(KEEP_FILE)?.is_some() {
+        builder.remove(KEEP_FILE)?;
+    }
+    Ok(builder.write()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::NewNodePayload;
+    use crate::store::{MemoryNodeStore, NodeRef};
+
+    fn path(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|part| String::from(*part)).collect()
+    }
+
+    #[test]
+    fn it_commits_every_change_and_bundles_the_history() -> BackendResult<()> {
+        let store = MemoryNodeStore::new();
+        let user_id = 1;
+
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let history = History::new(dir)?;
+        assert!(matches!(
+            history.bundle(user_id),
+            Err(BackendError::NotFound)
+        ));
+
+        let insert = |parent: &[&str], name: &str, content: Option<&str>| {
+            store.insert(
+                user_id,
+                NodeRef::Path(&path(parent)),
+                &NewNodePayload {
+                    name: String::from(name),
+                    is_directory: content.is_none(),
+                    content: content.map(String::from),
+                },
+            )
+        };
+        insert(&[], "Projects", None)?;
+        let projects = path(&["Projects"]);
+        history.commit(&store, user_id, "alice", Change::Create(&projects))?;
+        insert(&["Projects"], "Todo", Some("- [ ] history"))?;
+        let todo = path(&["Projects", "Todo"]);
+        history.commit(&store, user_id, "alice", Change::Create(&todo))?;
+        store.change_content(user_id, NodeRef::Path(&todo), "- [x] history")?;
+        history.commit(&store, user_id, "alice", Change::Edit(&todo))?;
+        // Nothing changed.
+        let unchanged =
+            history.commit(&store, user_id, "alice", Change::Edit(&todo))?;
+        assert!(unchanged.is_none());
+
+        let repo = Repository::open_bare(dir.join(format!("{}.git", user_id)))?;
+        let head = repo.find_commit(repo.refname_to_id(BRANCH)?)?;
+        assert_eq!(head.message(), Some("Edit Projects/Todo"));
+        assert_eq!(head.author().name(), Some("alice"));
+        assert_eq!(head.parent_count(), 1);
+        let blob = head
+            .tree()?
+            .get_path(std::path::Path::new("Projects/Todo.md"))?
+            .to_object(&repo)?
+            .peel_to_blob()?;
+        assert_eq!(blob.content(), b"- [x] history");
+
+        let mut walk = repo.revwalk()?;
+        walk.push(head.id())?;
+        assert_eq!(walk.count(), 3);
+
+        let bundle = history.bundle(user_id)?;
+        assert!(bundle.starts_with(b"# v2 git bundle\n"));
+
+        history.delete(user_id)?;
+        assert!(matches!(
+            history.bundle(user_id),
+            Err(BackendError::NotFound)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_escapes_names_git_can_not_store() -> BackendResult<()> {
+        let store = MemoryNodeStore::new();
+        let user_id = 1;
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let history = History::new(dir)?;
+
+        let nodes = [("x", false), ("x.md", true), ("100%", false)];
+        for (name, is_directory) in &nodes {
+            store.insert(
+                user_id,
+                NodeRef::ROOT,
+                &NewNodePayload {
+                    name: String::from(*name),
+                    is_directory: *is_directory,
+                    content: if *is_directory {
+                        None
+                    } else {
+                        Some(String::from(*name))
+                    },
+                },
+            )?;
+            let path = path(&[*name]);
+            history.commit(&store, user_id, "alice", Change::Create(&path))?;
+        }
+
+        let repo = Repository::open_bare(dir.join(format!("{}.git", user_id)))?;
+        let tree = repo.find_commit(repo.refname_to_id(BRANCH)?)?.tree()?;
+        let names: Vec<_> = tree
+            .iter()
+            .filter_map(|e| e.name().map(String::from))
+            .collect();
+        assert_eq!(names, vec!["100%25.md", "x%2Emd", "x.md"]);
+        // Names starting with a `.` are rejected by the stores, but older
+        // databases may contain them.
+        assert_eq!(entry_name(".git", true), "%2Egit");
+        assert_eq!(entry_name("..", true), "%2E.");
+        assert_eq!(entry_name(".keep", false), "%2Ekeep.md");
+        assert_eq!(entry_name("", true), "%");
+        let blob = tree
+            .get_name("x.md")
+            .unwrap()
+            .to_object(&repo)?
+            .peel_to_blob()?;
+        assert_eq!(blob.content(), b"x");
+
+        Ok(())
+    }
+}
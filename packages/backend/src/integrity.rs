@@ -0,0 +1,655 @@
+//! Checks the database for corruption and for nodes that break the rules of
+//! the node tree, and repairs the latter by moving the affected nodes into a
+//! `lost+found` directory of their owner.
+//!
+//! The constraints of the nodes table prevent most of these problems, but
+//! they are not enforced while `foreign_keys` is off, as in the
+//! `fix_node_name_unique_per_user` migration, and the unique constraint does
+//! not cover root nodes.
+
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use crate::models::schema::nodes;
+use crate::models::{NodeId, NodeName, UserId};
+use crate::{BackendResult, DbConnection};
+
+/// The name of the root directory repaired nodes are moved into.
+pub const LOST_AND_FOUND: &str = "lost+found";
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Problem {
+    /// A line reported by `PRAGMA integrity_check`.
+    Corruption(String),
+    /// A row reported by `PRAGMA foreign_key_check`.
+    ForeignKeyViolation {
+        table: String,
+        row_id: Option<i64>,
+        parent: String,
+    },
+    /// The parent of the node is missing, not a directory or owned by another
+    /// user.
+    OrphanedNode {
+        node_id: NodeId,
+        owner_id: UserId,
+    },
+    /// The nodes are each others ancestors and not reachable from a root node.
+    ParentCycle {
+        node_ids: Vec<NodeId>,
+        owner_id: UserId,
+    },
+    /// The nodes have the same parent and the same name.
+    DuplicateSiblingName {
+        node_ids: Vec<NodeId>,
+        owner_id: UserId,
+        name: NodeName,
+    },
+    DirectoryWithContent {
+        node_id: NodeId,
+        owner_id: UserId,
+    },
+    FileWithoutContent {
+        node_id: NodeId,
+        owner_id: UserId,
+    },
+}
+
+impl Problem {
+    /// Whether `repair` fixes this problem. Only problems of the node tree are
+    /// repaired.
+    pub fn is_repairable(&self) -> bool {
+        match self {
+            Problem::Corruption(_) | Problem::ForeignKeyViolation { .. } => {
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Corruption(message) => {
+                write!(f, "integrity check: {}", message)
+            }
+            Problem::ForeignKeyViolation {
+                table,
+                row_id,
+                parent,
+            } => match row_id {
+                Some(row_id) => write!(
+                    f,
+                    "row {} of {} references a missing row of {}",
+                    row_id, table, parent
+                ),
+                None => write!(
+                    f,
+                    "a row of {} references a missing row of {}",
+                    table, parent
+                ),
+            },
+            Problem::OrphanedNode { node_id, owner_id } => write!(
+                f,
+                "node {} of user {} has an invalid parent",
+                node_id, owner_id
+            ),
+            Problem::ParentCycle { node_ids, owner_id } => write!(
+                f,
+                "nodes {:?} of user {} are their own ancestors",
+                node_ids, owner_id
+            ),
+            Problem::DuplicateSiblingName {
+                node_ids,
+                owner_id,
+                name,
+            } => write!(
+                f,
+                "nodes {:?} of user {} are siblings with the same name {:?}",
+                node_ids, owner_id, name
+            ),
+            Problem::DirectoryWithContent { node_id, owner_id } => write!(
+                f,
+                "directory {} of user {} has content",
+                node_id, owner_id
+            ),
+            Problem::FileWithoutContent { node_id, owner_id } => write!(
+                f,
+                "file {} of user {} has no content",
+                node_id, owner_id
+            ),
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct IntegrityCheckRow {
+    #[sql_type = "Text"]
+    integrity_check: String,
+}
+
+/// Runs `PRAGMA integrity_check` and returns its findings. The result is
+/// empty if the database is fine.
+pub fn integrity_check(conn: &DbConnection) -> BackendResult<Vec<String>> {
+    let rows: Vec<IntegrityCheckRow> =
+        diesel::sql_query("PRAGMA integrity_check").load(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(|row| row.integrity_check)
+        .filter(|message| message != "ok")
+        .collect())
+}
+
+#[derive(QueryableByName)]
+struct ForeignKeyCheckRow {
+    #[sql_type = "Text"]
+    table: String,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::BigInt>"]
+    rowid: Option<i64>,
+    #[sql_type = "Text"]
+    parent: String,
+}
+
+fn foreign_key_check(conn: &DbConnection) -> BackendResult<Vec<Problem>> {
+    let rows: Vec<ForeignKeyCheckRow> =
+        diesel::sql_query("PRAGMA foreign_key_check").load(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(|row| Problem::ForeignKeyViolation {
+            table: row.table,
+            row_id: row.rowid,
+            parent: row.parent,
+        })
+        .collect())
+}
+
+/// The columns of a node that are needed to check the tree.
+struct NodeInfo {
+    node_id: NodeId,
+    node_name: NodeName,
+    parent_id: Option<NodeId>,
+    owner_id: UserId,
+    is_directory: bool,
+    has_content: bool,
+}
+
+fn load_nodes(conn: &DbConnection) -> BackendResult<Vec<NodeInfo>> {
+    let rows = nodes::table
+        .select((
+            nodes::node_id,
+            nodes::node_name,
+            nodes::parent_id,
+            nodes::owner_id,
+            nodes::is_directory,
+            nodes::content.is_not_null(),
+        ))
+        .order(nodes::node_id)
+        .load::<(NodeId, NodeName, Option<NodeId>, UserId, bool, bool)>(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(
+                node_id,
+                node_name,
+                parent_id,
+                owner_id,
+                is_directory,
+                has_content,
+            )| NodeInfo {
+                node_id,
+                node_name,
+                parent_id,
+                owner_id,
+                is_directory,
+                has_content,
+            },
+        )
+        .collect())
+}
+
+/// Checks the whole database and returns every problem found. With postgres
+/// the constraints are always enforced, so only the node tree is checked.
+pub fn check(conn: &DbConnection) -> BackendResult<Vec<Problem>> {
+    let mut problems = Vec::new();
+    if cfg!(not(feature = "postgres")) {
+        problems.extend(
+            integrity_check(conn)?.into_iter().map(Problem::Corruption),
+        );
+        problems.extend(foreign_key_check(conn)?);
+    }
+    problems.extend(check_nodes(&load_nodes(conn)?));
+    Ok(problems)
+}
+
+fn check_nodes(nodes: &[NodeInfo]) -> Vec<Problem> {
+    let by_id: HashMap<NodeId, &NodeInfo> =
+        nodes.iter().map(|node| (node.node_id, node)).collect();
+    let mut problems = Vec::new();
+
+    for node in nodes {
+        if let Some(parent_id) = node.parent_id {
+            let valid_parent = match by_id.get(&parent_id) {
+                Some(parent) => {
+                    parent.is_directory && parent.owner_id == node.owner_id
+                }
+                None => false,
+            };
+            if !valid_parent {
+                problems.push(Problem::OrphanedNode {
+                    node_id: node.node_id,
+                    owner_id: node.owner_id,
+                });
+            }
+        }
+        if node.is_directory && node.has_content {
+            problems.push(Problem::DirectoryWithContent {
+                node_id: node.node_id,
+                owner_id: node.owner_id,
+            });
+        }
+        if !node.is_directory && !node.has_content {
+            problems.push(Problem::FileWithoutContent {
+                node_id: node.node_id,
+                owner_id: node.owner_id,
+            });
+        }
+    }
+
+    problems.extend(find_cycles(nodes, &by_id));
+
+    let mut siblings: BTreeMap<(UserId, Option<NodeId>, &str), Vec<NodeId>> =
+        BTreeMap::new();
+    for node in nodes {
+        siblings
+            .entry((node.owner_id, node.parent_id, node.node_name.as_str()))
+            .or_default()
+            .push(node.node_id);
+    }
+    for ((owner_id, _, name), node_ids) in siblings {
+        if node_ids.len() > 1 {
+            problems.push(Problem::DuplicateSiblingName {
+                node_ids,
+                owner_id,
+                name: String::from(name),
+            });
+        }
+    }
+
+    problems
+}
+
+/// Follows the parents of every node and returns the cycles found on the
+/// way. Every node is visited once.
+fn find_cycles(
+    nodes: &[NodeInfo],
+    by_id: &HashMap<NodeId, &NodeInfo>,
+) -> Vec<Problem> {
+    let mut done: BTreeSet<NodeId> = BTreeSet::new();
+    let mut problems = Vec::new();
+
+    for node in nodes {
+        let mut path: Vec<NodeId> = Vec::new();
+        let mut current = Some(node.node_id);
+        while let Some(node_id) = current {
+            if done.contains(&node_id) {
+                break;
+            }
+            if let Some(position) = path.iter().position(|id| *id == node_id) {
+                let mut node_ids = path[position..].to_vec();
+                node_ids.sort_unstable();
+                problems.push(Problem::ParentCycle {
+                    node_ids,
+                    owner_id: by_id[&node_id].owner_id,
+                });
+                break;
+            }
+            path.push(node_id);
+            current = by_id.get(&node_id).and_then(|node| node.parent_id);
+        }
+        done.extend(path);
+    }
+
+    problems
+}
+
+/// The number of nodes `repair` changed, by kind of repair.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Repairs {
+    /// Nodes moved into `lost+found`, including the files without content.
+    pub moved: usize,
+    /// Files without content that were given an empty content.
+    pub filled: usize,
+    /// Directories whose content was moved into a new file in `lost+found`.
+    pub extracted: usize,
+}
+
+/// Repairs the given problems of the node tree in a single transaction and
+/// returns the number of nodes that were changed by kind of repair.
+///
+/// Orphaned nodes, one node of every cycle and all but the oldest of the
+/// siblings with the same name are moved into the `lost+found` root directory
+/// of their owner. The content of a directory is moved into a new file in
+/// `lost+found` and files without content are moved there with an empty
+/// content. Nodes keep their name, unless it is already taken in
+/// `lost+found`, in which case the node id is appended.
+pub fn repair(
+    conn: &DbConnection,
+    problems: &[Problem],
+) -> BackendResult<Repairs> {
+    conn.transaction(|| {
+        let mut moves: BTreeMap<NodeId, UserId> = BTreeMap::new();
+        let mut directories_with_content = Vec::new();
+        let mut files_without_content = Vec::new();
+
+        for problem in problems {
+            match problem {
+                Problem::Corruption(_)
+                | Problem::ForeignKeyViolation { .. } => {}
+                Problem::OrphanedNode { node_id, owner_id } => {
+                    moves.insert(*node_id, *owner_id);
+                }
+                Problem::ParentCycle { node_ids, owner_id } => {
+                    moves.insert(node_ids[0], *owner_id);
+                }
+                Problem::DuplicateSiblingName {
+                    node_ids, owner_id, ..
+                } => {
+                    for node_id in &node_ids[1..] {
+                        moves.insert(*node_id, *owner_id);
+                    }
+                }
+                Problem::DirectoryWithContent { node_id, owner_id } => {
+                    directories_with_content.push((*node_id, *owner_id));
+                }
+                Problem::FileWithoutContent { node_id, owner_id } => {
+                    files_without_content.push(*node_id);
+                    moves.insert(*node_id, *owner_id);
+                }
+            }
+        }
+
+        // The nodes are moved first, because the triggers of the nodes table
+        // reject any update of a root node while another root node has the
+        // same name.
+        let mut repairs = Repairs::default();
+        for (node_id, owner_id) in moves {
+            let lost_and_found = lost_and_found_id(conn, owner_id)?;
+            // The lost+found directory may be one of the duplicate root
+            // nodes, but it is never moved into itself.
+            if node_id == lost_and_found {
+                continue;
+            }
+            let name: NodeName = nodes::table
+                .find(node_id)
+                .select(nodes::node_name)
+                .first(conn)?;
+            diesel::update(nodes::table.find(node_id))
+                .set((
+                    nodes::node_name.eq(free_name(
+                        conn,
+                        lost_and_found,
+                        &name,
+                        node_id,
+                    )?),
+                    nodes::parent_id.eq(lost_and_found),
+                    nodes::parent_is_directory.eq(true),
+                ))
+                .execute(conn)?;
+            repairs.moved += 1;
+        }
+
+        for node_id in files_without_content {
+            diesel::update(nodes::table.find(node_id))
+                .set(nodes::content.eq(""))
+                .execute(conn)?;
+            repairs.filled += 1;
+        }
+
+        for (node_id, owner_id) in directories_with_content {
+            let (name, content) = nodes::table
+                .find(node_id)
+                .select((nodes::node_name, nodes::content))
+                .first::<(NodeName, Option<String>)>(conn)?;
+            let lost_and_found = lost_and_found_id(conn, owner_id)?;
+            diesel::update(nodes::table.find(node_id))
+                .set(nodes::content.eq(None::<String>))
+                .execute(conn)?;
+            diesel::insert_into(nodes::table)
+                .values((
+                    nodes::node_name.eq(free_name(
+                        conn,
+                        lost_and_found,
+                        &name,
+                        node_id,
+                    )?),
+                    nodes::parent_id.eq(lost_and_found),
+                    nodes::parent_is_directory.eq(true),
+                    nodes::owner_id.eq(owner_id),
+                    nodes::is_directory.eq(false),
+                    nodes::content.eq(content),
+                ))
+                .execute(conn)?;
+            repairs.extracted += 1;
+        }
+
+        Ok(repairs)
+    })
+}
+
+/// Returns the id of the `lost+found` root directory of the user and creates
+/// it if it does not exist yet. If a root file has that name, the directory is
+/// named `lost+found (1)`, `lost+found (2)` and so on instead.
+fn lost_and_found_id(
+    conn: &DbConnection,
+    owner_id: UserId,
+) -> BackendResult<NodeId> {
+    let query = |name: &str| {
+        nodes::table
+            .select((nodes::node_id, nodes::is_directory))
+            .filter(nodes::owner_id.eq(owner_id))
+            .filter(nodes::parent_id.is_null())
+            .filter(nodes::node_name.eq(String::from(name)))
+            .order(nodes::node_id)
+    };
+
+    let mut name = String::from(LOST_AND_FOUND);
+    for number in 1.. {
+        match query(&name).first::<(NodeId, bool)>(conn).optional()? {
+            Some((node_id, true)) => return Ok(node_id),
+            Some((_, false)) => {
+                name = format!("{} ({})", LOST_AND_FOUND, number)
+            }
+            None => break,
+        }
+    }
+
+    diesel::insert_into(nodes::table)
+        .values((
+            nodes::node_name.eq(&name),
+            nodes::owner_id.eq(owner_id),
+            nodes::is_directory.eq(true),
+        ))
+        .execute(conn)?;
+    Ok(query(&name).first::<(NodeId, bool)>(conn)?.0)
+}
+
+/// Returns `name` if there is no node with that name in the given directory
+/// yet and otherwise the name with the id of the node appended.
+fn free_name(
+    conn: &DbConnection,
+    parent_id: NodeId,
+    name: &str,
+    node_id: NodeId,
+) -> BackendResult<NodeName> {
+    let taken: i64 = nodes::table
+        .filter(nodes::parent_id.eq(parent_id))
+        .filter(nodes::node_name.eq(name))
+        .count()
+        .get_result(conn)?;
+    if taken == 0 {
+        Ok(String::from(name))
+    } else {
+        Ok(format!("{} ({})", name, node_id))
+    }
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+mod tests {
+    use super::*;
+    use crate::database;
+    use crate::models::{NewNodePayload, Node, NodeQueries};
+    use crate::user_management;
+    use diesel::connection::SimpleConnection;
+
+    fn insert(
+        conn: &DbConnection,
+        owner_id: UserId,
+        parent_path: &[&str],
+        name: &str,
+        content: Option<&str>,
+    ) -> NodeId {
+        let parent_path: Vec<String> =
+            parent_path.iter().map(|part| String::from(*part)).collect();
+        Node::insert(
+            conn,
+            &owner_id,
+            &parent_path,
+            &NewNodePayload {
+                name: String::from(name),
+                is_directory: content.is_none(),
+                content: content.map(String::from),
+            },
+        )
+        .unwrap()
+        .node_id
+    }
+
+    #[test]
+    fn it_finds_and_repairs_broken_nodes() -> BackendResult<()> {
+        let pool = database::create_test_pool()?;
+        let conn = pool.get()?;
+        database::run_migrations(&conn)?;
+        let user = user_management::provision(&conn, "alice")?;
+
+        let a = insert(&conn, user.id, &[], "a", None);
+        let b = insert(&conn, user.id, &["a"], "b", None);
+        let notes = insert(&conn, user.id, &[], "notes", None);
+        let todo = insert(&conn, user.id, &["notes"], "todo", Some("- [ ]"));
+        assert!(check(&conn)?.is_empty());
+
+        // The test connection never commits, so the foreign keys are not
+        // checked until the end of the test.
+        conn.batch_execute(
+            "PRAGMA defer_foreign_keys = ON;
+             PRAGMA ignore_check_constraints = ON;
+             DROP TRIGGER CheckInsertUniqueRootName;",
+        )?;
+        diesel::update(nodes::table.find(a))
+            .set((nodes::parent_id.eq(b), nodes::parent_is_directory.eq(true)))
+            .execute(&conn)?;
+        diesel::update(nodes::table.find(todo))
+            .set(nodes::parent_id.eq(1000))
+            .execute(&conn)?;
+        diesel::update(nodes::table.find(notes))
+            .set(nodes::content.eq("text"))
+            .execute(&conn)?;
+        diesel::insert_into(nodes::table)
+            .values((
+                nodes::node_name.eq("notes"),
+                nodes::owner_id.eq(user.id),
+                nodes::is_directory.eq(false),
+                nodes::content.eq("x"),
+            ))
+            .execute(&conn)?;
+        let duplicate: NodeId = nodes::table
+            .select(nodes::node_id)
+            .order(nodes::node_id.desc())
+            .first(&conn)?;
+
+        let problems = check(&conn)?;
+        assert!(problems.contains(&Problem::ParentCycle {
+            node_ids: vec![a, b],
+            owner_id: user.id,
+        }));
+        assert!(problems.contains(&Problem::OrphanedNode {
+            node_id: todo,
+            owner_id: user.id,
+        }));
+        assert!(problems.contains(&Problem::DirectoryWithContent {
+            node_id: notes,
+            owner_id: user.id,
+        }));
+        assert!(problems.contains(&Problem::DuplicateSiblingName {
+            node_ids: vec![notes, duplicate],
+            owner_id: user.id,
+            name: String::from("notes"),
+        }));
+        assert!(problems.iter().any(|problem| match problem {
+            Problem::ForeignKeyViolation { table, .. } => table == "nodes",
+            _ => false,
+        }));
+
+        let repairs = repair(&conn, &problems)?;
+        assert_eq!(
+            repairs,
+            Repairs {
+                moved: 3,
+                filled: 0,
+                extracted: 1,
+            }
+        );
+        assert!(check(&conn)?.is_empty());
+
+        let lost_and_found = Node::fetch_by_path_for_user(
+            &conn,
+            &user.id,
+            &[String::from(LOST_AND_FOUND)],
+        )?;
+        let moved: Vec<(NodeName, Option<String>)> = nodes::table
+            .select((nodes::node_name, nodes::content))
+            .filter(nodes::parent_id.eq(lost_and_found.node_id))
+            .order(nodes::node_name)
+            .load(&conn)?;
+        assert_eq!(
+            moved,
+            vec![
+                (String::from("a"), None),
+                (String::from("notes"), Some(String::from("x"))),
+                (format!("notes ({})", notes), Some(String::from("text"))),
+                (String::from("todo"), Some(String::from("- [ ]"))),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_does_not_move_nodes_into_a_file_named_lost_and_found(
+    ) -> BackendResult<()> {
+        let pool = database::create_test_pool()?;
+        let conn = pool.get()?;
+        database::run_migrations(&conn)?;
+        let user = user_management::provision(&conn, "alice")?;
+
+        insert(&conn, user.id, &[], LOST_AND_FOUND, Some("not a directory"));
+        insert(&conn, user.id, &[], "notes", None);
+        let todo = insert(&conn, user.id, &["notes"], "todo", Some("- [ ]"));
+        conn.batch_execute("PRAGMA defer_foreign_keys = ON;")?;
+        diesel::update(nodes::table.find(todo))
+            .set(nodes::parent_id.eq(1000))
+            .execute(&conn)?;
+
+        assert_eq!(repair(&conn, &check(&conn)?)?.moved, 1);
+        assert!(check(&conn)?.is_empty());
+        let todo = Node::fetch_by_path_for_user(
+            &conn,
+            &user.id,
+            &[format!("{} (1)", LOST_AND_FOUND), String::from("todo")],
+        )?;
+        assert_eq!(todo.content.as_deref(), Some("- [ ]"));
+
+        Ok(())
+    }
+}
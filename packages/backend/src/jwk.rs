@@ -0,0 +1,136 @@
+//! Conversion of PEM encoded public keys into JSON Web Keys, so they can be
+//! published in a JWKS document.
+
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters,
+    RSAKeyType,
+};
+use jsonwebtoken::Algorithm;
+use simple_asn1::{oid, ASN1Block, BigInt, OID};
+
+use crate::{BackendError, BackendResult};
+
+fn invalid_key() -> BackendError {
+    BackendError::InvalidConfig(String::from("Unsupported public key"))
+}
+
+/// Parses the given DER and returns the contents of its outer sequence.
+fn read_sequence(der: &[u8]) -> BackendResult<Vec<ASN1Block>> {
+    let mut blocks = simple_asn1::from_der(der).map_err(|_| invalid_key())?;
+    match (blocks.pop(), blocks.is_empty()) {
+        (Some(ASN1Block::Sequence(_, blocks)), true) => Ok(blocks),
+        _ => Err(invalid_key()),
+    }
+}
+
+/// Splits a DER encoded SubjectPublicKeyInfo into the algorithm object
+/// identifier and the public key bits.
+fn read_subject_public_key_info(der: &[u8]) -> BackendResult<(OID, Vec<u8>)> {
+    match read_sequence(der)?.as_slice() {
+        [ASN1Block::Sequence(_, algorithm), ASN1Block::BitString(_, bits, key)]
+            if *bits == key.len() * 8 =>
+        {
+            match algorithm.first() {
+                Some(ASN1Block::ObjectIdentifier(_, oid)) => {
+                    Ok((oid.clone(), key.clone()))
+                }
+                _ => Err(invalid_key()),
+            }
+        }
+        _ => Err(invalid_key()),
+    }
+}
+
+fn base64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Converts the given PEM encoded public key into a JWK. Supports RSA keys in
+/// the SubjectPublicKeyInfo ("PUBLIC KEY") or PKCS#1 ("RSA PUBLIC KEY")
+/// format and Ed25519 keys in the SubjectPublicKeyInfo format.
+pub fn from_public_key_pem(
+    pem: &[u8],
+    algorithm: Algorithm,
+    kid: &str,
+) -> BackendResult<Jwk> {
+    let pem = pem::parse(pem).map_err(|_| invalid_key())?;
+    let algorithm_parameters = match pem.tag.as_str() {
+        "RSA PUBLIC KEY" => rsa_parameters(&pem.contents)?,
+        "PUBLIC KEY" => {
+            let (algorithm_oid, key) =
+                read_subject_public_key_info(&pem.contents)?;
+            let is_ed25519 = algorithm_oid == oid!(1, 3, 101, 112);
+            if algorithm_oid == oid!(1, 2, 840, 113549, 1, 1, 1) {
+                rsa_parameters(&key)?
+            } else if is_ed25519 && key.len() == 32 {
+                AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: base64url(&key),
+                })
+            } else {
+                return Err(invalid_key());
+            }
+        }
+        _ => return Err(invalid_key()),
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            algorithm: Some(algorithm),
+            key_id: Some(String::from(kid)),
+            ..Default::default()
+        },
+        algorithm: algorithm_parameters,
+    })
+}
+
+fn unsigned_bytes(integer: &BigInt) -> Vec<u8> {
+    integer.to_bytes_be().1
+}
+
+/// Reads the modulus and exponent of a PKCS#1 RSAPublicKey structure.
+fn rsa_parameters(der: &[u8]) -> BackendResult<AlgorithmParameters> {
+    match read_sequence(der)?.as_slice() {
+        [ASN1Block::Integer(_, modulus), ASN1Block::Integer(_, exponent)] => {
+            Ok(AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: base64url(&unsigned_bytes(modulus)),
+                e: base64url(&unsigned_bytes(exponent)),
+            }))
+        }
+        _ => Err(invalid_key()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::DecodingKey;
+
+    #[test]
+    fn it_converts_public_keys() -> BackendResult<()> {
+        let rsa = from_public_key_pem(
+            include_bytes!("../tests/keys/rsa_public.pem"),
+            Algorithm::RS256,
+            "rsa",
+        )?;
+        match &rsa.algorithm {
+            AlgorithmParameters::RSA(params) => assert_eq!(params.e, "AQAB"),
+            _ => panic!("Expected RSA parameters"),
+        }
+        assert!(DecodingKey::from_jwk(&rsa).is_ok());
+
+        let ed25519 = from_public_key_pem(
+            include_bytes!("../tests/keys/ed25519_public.pem"),
+            Algorithm::EdDSA,
+            "ed25519",
+        )?;
+        assert_eq!(ed25519.common.key_id.as_deref(), Some("ed25519"));
+        assert!(DecodingKey::from_jwk(&ed25519).is_ok());
+
+        Ok(())
+    }
+}
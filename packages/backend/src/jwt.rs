@@ -1,19 +1,57 @@
 use chrono::prelude::*;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
 use jsonwebtoken::{
-    decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header,
+    Validation,
 };
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome};
 use rocket::{Request, State};
 use serde::{Deserialize, Serialize};
+use std::fs;
+use std::ops::Deref;
 
-use crate::models::{User, UserId};
-use crate::BackendResult;
+use crate::config::JwtSection;
+use crate::database::DbReadPool;
+use crate::errors::codes;
+use crate::models::{
+    AccessToken, AccessTokenId, Scope, User, UserId, UserQueries,
+    ACCESS_TOKEN_PREFIX,
+};
+use crate::proxy_auth::ProxyAuthConfig;
+use crate::{
+    database, jwk, logging, user_management, BackendError, BackendResult,
+    DbConnectionPool,
+};
+
+/// The key new JWTs are signed with.
+#[derive(Clone)]
+pub struct SigningKey {
+    /// Written into the `kid` header of each JWT, so the matching verification
+    /// key can be found.
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub key: EncodingKey,
+}
+
+/// A key JWTs are accepted from. Keeping the key of a previous signing key
+/// around allows rotating keys without invalidating the issued JWTs.
+#[derive(Clone)]
+pub struct VerificationKey {
+    /// Matched against the `kid` header of a JWT. JWTs without a `kid` header
+    /// are verified with the key without a kid.
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub key: DecodingKey,
+    /// The public key as JWK. `None` for shared secrets, which must never be
+    /// published.
+    pub jwk: Option<Jwk>,
+}
 
 #[derive(Clone)]
 pub struct Config {
-    /// The secret to be used to sign JWTs.
-    pub secret: String,
+    pub signing_key: SigningKey,
+    pub verification_keys: Vec<VerificationKey>,
     /// The number of seconds a jwt is valid after being issued.
     pub expire_in: i64,
     /// Leeway in seconds for the validation of the claims `exp`, `iat` and
@@ -22,13 +60,195 @@ pub struct Config {
     pub validation_leeway: u64,
 }
 
+impl Config {
+    /// Creates a config that signs and verifies JWTs with HS256 using the
+    /// given shared secret.
+    pub fn with_secret(
+        secret: &str,
+        expire_in: i64,
+        validation_leeway: u64,
+    ) -> Config {
+        Config {
+            signing_key: SigningKey {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                key: EncodingKey::from_secret(secret.as_ref()),
+            },
+            verification_keys: vec![Self::secret_verification_key(secret)],
+            expire_in,
+            validation_leeway,
+        }
+    }
+
+    fn secret_verification_key(secret: &str) -> VerificationKey {
+        VerificationKey {
+            kid: None,
+            algorithm: Algorithm::HS256,
+            key: DecodingKey::from_secret(secret.as_ref()),
+            jwk: None,
+        }
+    }
+
+    /// Creates a config that signs JWTs with the PEM encoded private key and
+    /// verifies them with the given PEM encoded public keys, each with its
+    /// own algorithm. `public_keys` must contain the public key of the
+    /// signing key under `kid`, with the same algorithm.
+    pub fn with_key_pair(
+        algorithm: Algorithm,
+        kid: &str,
+        private_key_pem: &[u8],
+        public_keys: &[(String, Algorithm, Vec<u8>)],
+        expire_in: i64,
+        validation_leeway: u64,
+    ) -> BackendResult<Config> {
+        let unsupported = |algorithm| {
+            BackendError::InvalidConfig(format!(
+                "Unsupported jwt algorithm {:?}",
+                algorithm
+            ))
+        };
+        let key = match algorithm {
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(private_key_pem)?,
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(private_key_pem)?,
+            _ => return Err(unsupported(algorithm)),
+        };
+
+        let mut verification_keys = Vec::new();
+        for (public_kid, public_algorithm, public_key_pem) in public_keys {
+            let public_algorithm = *public_algorithm;
+            let key = match public_algorithm {
+                Algorithm::RS256 => DecodingKey::from_rsa_pem(public_key_pem)?,
+                Algorithm::EdDSA => DecodingKey::from_ed_pem(public_key_pem)?,
+                _ => return Err(unsupported(public_algorithm)),
+            };
+            verification_keys.push(VerificationKey {
+                kid: Some(public_kid.clone()),
+                algorithm: public_algorithm,
+                key,
+                jwk: Some(jwk::from_public_key_pem(
+                    public_key_pem,
+                    public_algorithm,
+                    public_kid,
+                )?),
+            });
+        }
+        let signing_public_key = public_keys.iter().find(|key| key.0 == kid);
+        match signing_public_key {
+            Some((_, public_algorithm, _))
+                if *public_algorithm == algorithm => {}
+            Some(_) => {
+                return Err(BackendError::InvalidConfig(format!(
+                    "The public key {} has another algorithm than the \
+                     signing key",
+                    kid
+                )))
+            }
+            None => {
+                return Err(BackendError::InvalidConfig(format!(
+                    "There is no public key for the signing key {}",
+                    kid
+                )))
+            }
+        }
+
+        Ok(Config {
+            signing_key: SigningKey {
+                kid: Some(String::from(kid)),
+                algorithm,
+                key,
+            },
+            verification_keys,
+            expire_in,
+            validation_leeway,
+        })
+    }
+
+    /// Creates a config from the `jwt` section of the configuration.
+    ///
+    /// If `private_key` is set, JWTs are signed with that key using
+    /// `algorithm` and `key_id` as kid, and accepted from the `public_keys`.
+    /// If `secret` is set as well, JWTs signed with that secret are still
+    /// accepted, which allows switching from a shared secret to a key pair
+    /// without logging everyone out.
+    ///
+    /// Otherwise JWTs are signed with `secret` using HS256.
+    pub fn from_config(cfg: &JwtSection) -> BackendResult<Config> {
+        let private_key_path = match &cfg.private_key {
+            Some(path) => path,
+            None => {
+                let secret = cfg.secret.as_ref().ok_or_else(|| {
+                    BackendError::InvalidConfig(String::from(
+                        "Either jwt.secret (MN_JWT_SECRET) or jwt.private_key \
+                         (MN_JWT_PRIVATE_KEY) is required",
+                    ))
+                })?;
+                return Ok(Self::with_secret(
+                    secret,
+                    cfg.token_lifetime_seconds,
+                    cfg.leeway_seconds,
+                ));
+            }
+        };
+
+        let kid = cfg.key_id.as_ref().ok_or_else(|| {
+            BackendError::InvalidConfig(String::from(
+                "jwt.key_id (MN_JWT_KEY_ID) is required with jwt.private_key",
+            ))
+        })?;
+        let mut public_keys = Vec::new();
+        for (public_kid, public_key) in &cfg.public_keys {
+            public_keys.push((
+                public_kid.clone(),
+                public_key.algorithm(cfg.algorithm),
+                fs::read(public_key.path())?,
+            ));
+        }
+
+        let mut config = Self::with_key_pair(
+            cfg.algorithm,
+            kid,
+            &fs::read(private_key_path)?,
+            &public_keys,
+            cfg.token_lifetime_seconds,
+            cfg.leeway_seconds,
+        )?;
+        if let Some(secret) = &cfg.secret {
+            config
+                .verification_keys
+                .push(Self::secret_verification_key(secret));
+        }
+        Ok(config)
+    }
+
+    /// Returns the JWK set of all public verification keys.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .verification_keys
+                .iter()
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
 /// Represents the data stored in each JWT. Can be used as a request guard to
-/// ensure that a jwt is present in the request.
+/// ensure that a jwt or a personal access token is present in the request.
+///
+/// The guard does not check any scopes. Use `ReadAccess`, `WriteAccess` or
+/// `AdminAccess` for routes that require a specific scope.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     sub: UserId,
     username: String,
     exp: i64,
+    /// The scopes granted to the request. Not part of the JWT, since logging
+    /// in with a password always grants `Scope::SESSION`.
+    #[serde(skip)]
+    scopes: Vec<Scope>,
+    /// The id of the personal access token used to authenticate, if any.
+    #[serde(skip)]
+    access_token_id: Option<AccessTokenId>,
 }
 
 impl Claims {
@@ -37,50 +257,201 @@ impl Claims {
         self.sub
     }
 
+    /// Returns the name of the user associated to the given jwt.
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Whether the request was authenticated with a token granting the given
+    /// scope.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// Returns the id of the personal access token used to authenticate or
+    /// `None` if the request was authenticated with a JWT.
+    pub fn access_token_id(&self) -> Option<AccessTokenId> {
+        self.access_token_id
+    }
+
     /// Constructs a Claims instance from a given user.
     pub fn from_user(user: User, cfg: &Config) -> Claims {
         Claims {
             sub: user.id,
             username: user.username,
             exp: Utc::now().timestamp() + cfg.expire_in,
+            scopes: Scope::SESSION.to_vec(),
+            access_token_id: None,
+        }
+    }
+
+    /// Constructs a Claims instance from a personal access token of the given
+    /// user. Such claims are never converted into a JWT.
+    pub fn from_access_token(user: User, access_token: &AccessToken) -> Claims {
+        Claims {
+            sub: user.id,
+            username: user.username,
+            exp: access_token.expires_at.unwrap_or(i64::max_value()),
+            scopes: access_token.scope_list(),
+            access_token_id: Some(access_token.id),
         }
     }
 
     /// Tries to extract claims from a given token string. Returns an Error
-    /// if the token string is not a JWT, does not match our secret or it is
-    /// expired.
+    /// if the token string is not a JWT, does not match one of our
+    /// verification keys or it is expired.
     pub fn from_token(token: &str, cfg: &Config) -> BackendResult<Claims> {
-        let validation = Validation {
-            leeway: cfg.validation_leeway,
-            algorithms: vec![Algorithm::HS256],
-            ..Default::default()
-        };
+        let header = decode_header(token)?;
+        let key = cfg
+            .verification_keys
+            .iter()
+            .find(|key| key.kid == header.kid)
+            .ok_or(BackendError::InvalidCredentials)?;
+        // Only accept the algorithm of the key, otherwise a public key could be
+        // abused as a HS256 secret.
+        let mut validation = Validation::new(key.algorithm);
+        validation.leeway = cfg.validation_leeway;
 
-        let result = decode::<Claims>(
-            &token,
-            &DecodingKey::from_secret(cfg.secret.as_ref()),
-            &validation,
-        );
-        Ok(result?.claims)
+        let result = decode::<Claims>(token, &key.key, &validation);
+        let mut claims = result?.claims;
+        claims.scopes = Scope::SESSION.to_vec();
+        Ok(claims)
+    }
+
+    /// Tries to authenticate using a personal access token. Returns an Error
+    /// if there is no such token or it is expired. The token is read from the
+    /// read pool, its use is only recorded if the writer is free.
+    pub fn from_access_token_str(
+        token: &str,
+        read_pool: &DbReadPool,
+        pool: &DbConnectionPool,
+    ) -> BackendResult<Claims> {
+        let conn = read_pool.get()?;
+        let access_token = AccessToken::authenticate(&conn, token)?;
+        let user = User::load_by_id(&conn, access_token.user_id)?;
+        drop(conn);
+        access_token.record_use(pool);
+        Ok(Self::from_access_token(user, &access_token))
+    }
+
+    /// Loads the user with the given username that was authenticated by a
+    /// trusted proxy. Creates the user if it does not exist yet.
+    pub fn from_proxy_user(
+        username: &str,
+        pool: &DbConnectionPool,
+        cfg: &Config,
+    ) -> BackendResult<Claims> {
+        let conn = pool.get()?;
+        let user = database::retry_on_busy(|| {
+            user_management::provision(&conn, username)
+        })?;
+        Ok(Self::from_user(user, cfg))
     }
 
     /// Converts the claims into a JWT. Should not fail.
     pub fn to_token(&self, cfg: &Config) -> BackendResult<String> {
-        let token = encode(
-            &Header::default(),
-            self,
-            &EncodingKey::from_secret(cfg.secret.as_ref()),
-        )?;
+        let mut header = Header::new(cfg.signing_key.algorithm);
+        header.kid = cfg.signing_key.kid.clone();
+        let token = encode(&header, self, &cfg.signing_key.key)?;
         Ok(token)
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum AuthTokenError {
     BadCount,
     Internal,
     Invalid,
     Missing,
+    /// The token was valid but is expired, the user has to log in again.
+    Expired,
+    /// The `Authorization` header does not use the `Bearer` scheme.
+    WrongScheme,
+    /// The token is valid but does not grant the scope required by the route.
+    InsufficientScope,
+    /// The route can only be used with a password login, not with a personal
+    /// access token.
+    SessionRequired,
+}
+
+impl AuthTokenError {
+    pub fn status(self) -> Status {
+        match self {
+            AuthTokenError::BadCount => Status::BadRequest,
+            AuthTokenError::Internal => Status::InternalServerError,
+            AuthTokenError::Invalid
+            | AuthTokenError::Missing
+            | AuthTokenError::Expired
+            | AuthTokenError::WrongScheme => Status::Unauthorized,
+            AuthTokenError::InsufficientScope
+            | AuthTokenError::SessionRequired => Status::Forbidden,
+        }
+    }
+
+    /// A stable, machine-readable code like the ones of `BackendError`.
+    pub fn code(self) -> &'static str {
+        match self {
+            AuthTokenError::BadCount => codes::TOKEN_BAD_COUNT,
+            AuthTokenError::Internal => codes::INTERNAL_ERROR,
+            AuthTokenError::Invalid => codes::TOKEN_INVALID,
+            AuthTokenError::Missing => codes::TOKEN_MISSING,
+            AuthTokenError::Expired => codes::TOKEN_EXPIRED,
+            AuthTokenError::WrongScheme => codes::TOKEN_WRONG_SCHEME,
+            AuthTokenError::InsufficientScope => codes::INSUFFICIENT_SCOPE,
+            AuthTokenError::SessionRequired => codes::SESSION_REQUIRED,
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            AuthTokenError::BadCount => "More than one token given",
+            AuthTokenError::Internal => "Internal server error",
+            AuthTokenError::Invalid => "Invalid token",
+            AuthTokenError::Missing => "Token missing",
+            AuthTokenError::Expired => "Token expired",
+            AuthTokenError::WrongScheme => {
+                "Invalid authorization scheme, expected Bearer"
+            }
+            AuthTokenError::InsufficientScope => {
+                "The token does not grant the required scope"
+            }
+            AuthTokenError::SessionRequired => {
+                "Personal access tokens can not be used for this route"
+            }
+        }
+    }
+
+    /// Maps the error of a failed token validation.
+    fn from_backend_error(err: &BackendError) -> AuthTokenError {
+        match err {
+            BackendError::TokenExpired => AuthTokenError::Expired,
+            BackendError::JwtError(err) => match err.kind() {
+                jsonwebtoken::errors::ErrorKind::ExpiredSignature => {
+                    AuthTokenError::Expired
+                }
+                _ => AuthTokenError::Invalid,
+            },
+            BackendError::InvalidCredentials => AuthTokenError::Invalid,
+            _ => AuthTokenError::Internal,
+        }
+    }
+}
+
+/// Cached in the request if an authentication guard fails, so the error
+/// catchers can tell the client why. Contains the first failure.
+pub struct AuthFailure(pub Option<AuthTokenError>);
+
+/// Records the given error in the request and returns the failure outcome.
+fn fail<S>(req: &Request, err: AuthTokenError) -> Outcome<S, AuthTokenError> {
+    req.local_cache(|| AuthFailure(Some(err)));
+    Outcome::Failure((err.status(), err))
+}
+
+/// Attaches the user to the log lines of the request and returns the success
+/// outcome.
+fn succeed(claims: Claims) -> Outcome<Claims, AuthTokenError> {
+    logging::set_user_id(claims.id());
+    Outcome::Success(claims)
 }
 
 /// The JWT header schema including the whitespace separating the schema and the
@@ -91,65 +462,265 @@ impl<'a, 'r> FromRequest<'a, 'r> for Claims {
     type Error = AuthTokenError;
 
     fn from_request(req: &'a Request<'r>) -> Outcome<Self, Self::Error> {
-        let cfg: State<Config> = req
-            .guard::<State<Config>>()
-            .map_failure(|_| (Status::BadRequest, AuthTokenError::Internal))?;
+        let cfg = match req.guard::<State<Config>>() {
+            Outcome::Success(cfg) => cfg,
+            _ => return fail(req, AuthTokenError::Internal),
+        };
+
+        if let Outcome::Success(proxy_cfg) =
+            req.guard::<State<ProxyAuthConfig>>()
+        {
+            if let Some(username) = proxy_cfg.username(req) {
+                let pool = match req.guard::<State<DbConnectionPool>>() {
+                    Outcome::Success(pool) => pool,
+                    _ => return fail(req, AuthTokenError::Internal),
+                };
+                return match Self::from_proxy_user(username, &pool, &cfg) {
+                    Ok(claims) => succeed(claims),
+                    Err(err) => {
+                        log::error!("Proxy authentication failed: {}", err);
+                        fail(req, AuthTokenError::Internal)
+                    }
+                };
+            }
+        }
+
         let keys: Vec<_> = req.headers().get("Authorization").collect();
         match keys.len() {
             0 => {
-                println!("Token missing for {}", req);
-                Outcome::Failure((Status::BadRequest, AuthTokenError::Missing))
+                log::info!("Token missing for {}", req);
+                fail(req, AuthTokenError::Missing)
             }
             1 => {
                 let token = keys[0];
                 if !token.starts_with(JWT_HEADER_SCHEMA) {
-                    println!("Invalid jwt schema for {}, expected Bearer", req);
-                    return Outcome::Failure((
-                        Status::BadRequest,
-                        AuthTokenError::Missing,
-                    ));
+                    log::info!(
+                        "Invalid jwt schema for {}, expected Bearer",
+                        req
+                    );
+                    return fail(req, AuthTokenError::WrongScheme);
                 }
 
                 let token = &token[JWT_HEADER_SCHEMA.len()..];
-                match Self::from_token(&token, &cfg) {
-                    Ok(claims) => Outcome::Success(claims),
+                let result = if token.starts_with(ACCESS_TOKEN_PREFIX) {
+                    let read_pool = match req.guard::<State<DbReadPool>>() {
+                        Outcome::Success(read_pool) => read_pool,
+                        _ => return fail(req, AuthTokenError::Internal),
+                    };
+                    let pool = match req.guard::<State<DbConnectionPool>>() {
+                        Outcome::Success(pool) => pool,
+                        _ => return fail(req, AuthTokenError::Internal),
+                    };
+                    Self::from_access_token_str(token, &read_pool, &pool)
+                } else {
+                    Self::from_token(token, &cfg)
+                };
+                match result {
+                    Ok(claims) => succeed(claims),
                     Err(err) => {
-                        println!("Invalid token for {}: {}", req, err);
-                        Outcome::Failure((
-                            Status::Unauthorized,
-                            AuthTokenError::Invalid,
-                        ))
+                        log::info!("Invalid token for {}: {}", req, err);
+                        fail(req, AuthTokenError::from_backend_error(&err))
                     }
                 }
             }
             _ => {
-                println!("More than 1 token given for {}", req);
-                Outcome::Failure((Status::BadRequest, AuthTokenError::BadCount))
+                log::info!("More than 1 token given for {}", req);
+                fail(req, AuthTokenError::BadCount)
             }
         }
     }
 }
 
+/// Generates a request guard wrapping `Claims` that only succeeds if the claims
+/// grant the given scope.
+macro_rules! scope_guard {
+    ($(#[$attr: meta])* $name: ident, $scope: expr) => {
+        $(#[$attr])*
+        #[derive(Debug)]
+        pub struct $name(pub Claims);
+
+        impl Deref for $name {
+            type Target = Claims;
+
+            fn deref(&self) -> &Claims {
+                &self.0
+            }
+        }
+
+        impl<'a, 'r> FromRequest<'a, 'r> for $name {
+            type Error = AuthTokenError;
+
+            fn from_request(
+                req: &'a Request<'r>,
+            ) -> Outcome<Self, Self::Error> {
+                let claims = req.guard::<Claims>()?;
+                if claims.has_scope($scope) {
+                    Outcome::Success($name(claims))
+                } else {
+                    log::info!("Missing scope {:?} for {}", $scope, req);
+                    fail(req, AuthTokenError::InsufficientScope)
+                }
+            }
+        }
+    };
+}
+
+scope_guard!(
+    /// Request guard for routes that only read data of the user.
+    ReadAccess,
+    Scope::Read
+);
+scope_guard!(
+    /// Request guard for routes that change data of the user.
+    WriteAccess,
+    Scope::Write
+);
+scope_guard!(
+    /// Request guard for administrative routes.
+    AdminAccess,
+    Scope::Admin
+);
+
+/// Request guard for routes that must not be accessible with a personal access
+/// token, like managing the tokens themselves. This way a leaked token cannot
+/// be used to create new ones.
+#[derive(Debug)]
+pub struct Session(pub Claims);
+
+impl Deref for Session {
+    type Target = Claims;
+
+    fn deref(&self) -> &Claims {
+        &self.0
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Session {
+    type Error = AuthTokenError;
+
+    fn from_request(req: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let claims = req.guard::<Claims>()?;
+        if claims.access_token_id().is_none() {
+            Outcome::Success(Session(claims))
+        } else {
+            log::info!("Access token used for session-only route {}", req);
+            fail(req, AuthTokenError::SessionRequired)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn it_validates_a_token() -> Result<(), Box<dyn std::error::Error>> {
-        let cfg = Config {
-            secret: String::from("my awesome secret"),
-            expire_in: 100,
-            validation_leeway: 60,
-        };
-        let user = User {
+        let cfg = Config::with_secret("my awesome secret", 100, 60);
+        let user = test_user();
+        let token = Claims::from_user(user, &cfg).to_token(&cfg)?;
+
+        let claims = Claims::from_token(&token, &cfg)?;
+        assert!(claims.has_scope(Scope::Write));
+        assert!(!claims.has_scope(Scope::Admin));
+        assert!(claims.access_token_id().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_expired_tokens() -> BackendResult<()> {
+        // Expired 100 seconds ago, longer than the leeway.
+        let cfg = Config::with_secret("my awesome secret", -100, 0);
+        let token = Claims::from_user(test_user(), &cfg).to_token(&cfg)?;
+
+        match Claims::from_token(&token, &cfg) {
+            Err(err) => assert_eq!(
+                AuthTokenError::from_backend_error(&err),
+                AuthTokenError::Expired
+            ),
+            Ok(_) => panic!("Expected the token to be expired"),
+        }
+
+        Ok(())
+    }
+
+    fn test_user() -> User {
+        User {
             id: 1,
             username: String::from("foobar"),
             password_hash: String::from("some hash"),
+        }
+    }
+
+    fn ed25519_config(kid: &str) -> BackendResult<Config> {
+        Config::with_key_pair(
+            Algorithm::EdDSA,
+            kid,
+            include_bytes!("../tests/keys/ed25519_private.pem"),
+            &[(
+                String::from(kid),
+                Algorithm::EdDSA,
+                include_bytes!("../tests/keys/ed25519_public.pem").to_vec(),
+            )],
+            100,
+            60,
+        )
+    }
+
+    #[test]
+    fn it_accepts_tokens_of_rotated_keys() -> BackendResult<()> {
+        let old_cfg = ed25519_config("old")?;
+        let old_token =
+            Claims::from_user(test_user(), &old_cfg).to_token(&old_cfg)?;
+
+        let rsa_public_key = (
+            String::from("new"),
+            Algorithm::RS256,
+            include_bytes!("../tests/keys/rsa_public.pem").to_vec(),
+        );
+        let new_cfg = |public_keys: &[(String, Algorithm, Vec<u8>)]| {
+            Config::with_key_pair(
+                Algorithm::RS256,
+                "new",
+                include_bytes!("../tests/keys/rsa_private.pem"),
+                public_keys,
+                100,
+                60,
+            )
         };
-        let token = Claims::from_user(user, &cfg).into_token(&cfg)?;
+        let rotated_cfg = new_cfg(&[rsa_public_key.clone()])?;
+        let new_token = Claims::from_user(test_user(), &rotated_cfg)
+            .to_token(&rotated_cfg)?;
+        assert_eq!(decode_header(&new_token)?.kid.as_deref(), Some("new"));
+        assert!(Claims::from_token(&old_token, &rotated_cfg).is_err());
 
-        assert!(Claims::from_token(&token, &cfg).is_ok());
+        // The EdDSA key of the previous signing key is kept.
+        let rotated_cfg = new_cfg(&[
+            rsa_public_key,
+            (
+                String::from("old"),
+                Algorithm::EdDSA,
+                include_bytes!("../tests/keys/ed25519_public.pem").to_vec(),
+            ),
+        ])?;
+        assert!(Claims::from_token(&old_token, &rotated_cfg).is_ok());
+        assert!(Claims::from_token(&new_token, &rotated_cfg).is_ok());
+        assert_eq!(rotated_cfg.jwks().keys.len(), 2);
+
+        // The public key of the signing key must have its algorithm.
+        assert!(new_cfg(&[(
+            String::from("new"),
+            Algorithm::EdDSA,
+            include_bytes!("../tests/keys/ed25519_public.pem").to_vec(),
+        )])
+        .is_err());
 
         Ok(())
     }
+
+    #[test]
+    fn it_does_not_publish_secrets() {
+        let cfg = Config::with_secret("my awesome secret", 100, 60);
+        assert!(cfg.jwks().keys.is_empty());
+    }
 }
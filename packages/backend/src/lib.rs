@@ -6,11 +6,24 @@ extern crate diesel;
 extern crate diesel_migrations;
 
 pub mod api;
+pub mod backup;
+pub mod config;
 pub mod database;
 pub mod errors;
+pub mod export;
+pub mod health;
+pub mod history;
+pub mod integrity;
+pub mod jwk;
 pub mod jwt;
+pub mod logging;
+pub mod metrics;
 pub mod models;
+pub mod passwords;
+pub mod proxy_auth;
+pub mod store;
 pub mod user_management;
+pub mod webapp;
 
 pub use crate::database::{DbConnection, DbConnectionPool};
 pub use crate::errors::{BackendError, BackendResult};
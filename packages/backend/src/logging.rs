@@ -0,0 +1,349 @@
+//! Leveled logging in a human readable or a json format. Every line logged
+//! while handling a request contains the id of the request and, once the
+//! request is authenticated, the id of the user.
+//!
+//! Rocket handles a request on a single thread, from the request fairings to
+//! the response fairings, so the request context is kept in a thread local.
+
+use chrono::prelude::*;
+use diesel::r2d2::event::{
+    CheckinEvent, CheckoutEvent, HandleEvent, TimeoutEvent,
+};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::config::LogSection;
+use crate::models::UserId;
+use crate::{BackendError, BackendResult};
+
+/// The header containing the id of a request. An id set by the load balancer
+/// is kept, otherwise a new one is generated.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// The maximum length of a request id given by the client or load balancer.
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One human readable line per record.
+    Text,
+    /// One json object per line, for log aggregation.
+    Json,
+}
+
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    pub format: LogFormat,
+    /// Database connections that are held or waited for longer than this are
+    /// logged as warning. Single queries are not timed.
+    pub slow_connection_threshold: Duration,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: LevelFilter::Info,
+            format: LogFormat::Text,
+            slow_connection_threshold: Duration::from_millis(200),
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = BackendError;
+
+    fn from_str(format: &str) -> BackendResult<LogFormat> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(BackendError::InvalidConfig(format!(
+                "Invalid log format {}, expected text or json",
+                format
+            ))),
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn from_config(cfg: &LogSection) -> LogConfig {
+        LogConfig {
+            level: cfg.level,
+            format: cfg.format,
+            slow_connection_threshold: Duration::from_millis(
+                cfg.slow_connection_ms,
+            ),
+        }
+    }
+}
+
+/// Installs the logger for the whole process. Must be called before rocket is
+/// launched, otherwise rocket installs its own logger.
+pub fn init(cfg: &LogConfig) -> BackendResult<()> {
+    log::set_boxed_logger(Box::new(Logger {
+        level: cfg.level,
+        format: cfg.format,
+    }))
+    .map_err(|_| {
+        BackendError::InvalidConfig(String::from("Logger already initialized"))
+    })?;
+    log::set_max_level(cfg.level);
+    Ok(())
+}
+
+struct RequestContext {
+    request_id: String,
+    user_id: Option<UserId>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Option<RequestContext>> = RefCell::new(None);
+}
+
+/// Attaches the given user id to all following log lines of the current
+/// request.
+pub fn set_user_id(user_id: UserId) {
+    CONTEXT.with(|context| {
+        if let Some(context) = context.borrow_mut().as_mut() {
+            context.user_id = Some(user_id);
+        }
+    });
+}
+
+struct Logger {
+    level: LevelFilter,
+    format: LogFormat,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if metadata.level() > self.level {
+            return false;
+        }
+
+        // Rocket logs every request on its own, which is replaced by the
+        // single line of the `RequestLogger`. Keep its launch messages and
+        // warnings though.
+        let target = metadata.target();
+        metadata.level() <= Level::Warn
+            || self.level >= LevelFilter::Debug
+            || target.starts_with("backend")
+            || target.starts_with("launch")
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            write_line(
+                self.format,
+                record.level(),
+                record.target(),
+                record.args(),
+                &[],
+            );
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+    }
+}
+
+fn write_line(
+    format: LogFormat,
+    level: Level,
+    target: &str,
+    message: &fmt::Arguments,
+    fields: &[(&str, Value)],
+) {
+    let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    let line = CONTEXT.with(|context| {
+        let context = context.borrow();
+        let request_id = context.as_ref().map(|c| c.request_id.as_str());
+        let user_id = context.as_ref().and_then(|c| c.user_id);
+
+        match format {
+            LogFormat::Text => {
+                let mut line = format!("{} {:<5} {}", time, level, target);
+                if let Some(request_id) = request_id {
+                    line.push_str(&format!(" request_id={}", request_id));
+                }
+                if let Some(user_id) = user_id {
+                    line.push_str(&format!(" user_id={}", user_id));
+                }
+                for (key, value) in fields {
+                    line.push_str(&format!(" {}={}", key, value));
+                }
+                format!("{}: {}", line, message)
+            }
+            LogFormat::Json => {
+                let mut object = Map::new();
+                object.insert("time".into(), json!(time));
+                object.insert("level".into(), json!(level.to_string()));
+                object.insert("target".into(), json!(target));
+                object.insert("message".into(), json!(message.to_string()));
+                if let Some(request_id) = request_id {
+                    object.insert("requestId".into(), json!(request_id));
+                }
+                if let Some(user_id) = user_id {
+                    object.insert("userId".into(), json!(user_id));
+                }
+                for (key, value) in fields {
+                    object.insert((*key).into(), value.clone());
+                }
+                Value::Object(object).to_string()
+            }
+        }
+    });
+
+    let stdout = std::io::stdout();
+    let _ = writeln!(stdout.lock(), "{}", line);
+}
+
+/// The id and start time of a request, cached in the request.
+struct RequestStart {
+    request_id: String,
+    started_at: Instant,
+}
+
+/// Assigns an id to every request, returns it in the `X-Request-Id` header and
+/// logs a line for every response with the route, status and latency.
+pub struct RequestLogger {
+    format: LogFormat,
+}
+
+impl RequestLogger {
+    pub fn new(cfg: &LogConfig) -> RequestLogger {
+        RequestLogger { format: cfg.format }
+    }
+}
+
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request logger",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, req: &mut Request, _: &Data) {
+        let request_id = match req.headers().get_one(REQUEST_ID_HEADER) {
+            Some(id) if is_valid_request_id(id) => String::from(id),
+            _ => generate_request_id(),
+        };
+        CONTEXT.with(|context| {
+            *context.borrow_mut() = Some(RequestContext {
+                request_id: request_id.clone(),
+                user_id: None,
+            });
+        });
+        req.local_cache(|| RequestStart {
+            request_id,
+            started_at: Instant::now(),
+        });
+    }
+
+    fn on_response(&self, req: &Request, res: &mut Response) {
+        let start = req.local_cache(|| RequestStart {
+            request_id: generate_request_id(),
+            started_at: Instant::now(),
+        });
+        res.set_raw_header(REQUEST_ID_HEADER, start.request_id.clone());
+
+        if log::log_enabled!(Level::Info) {
+            let route = req.route().map(|route| route.uri.to_string());
+            write_line(
+                self.format,
+                Level::Info,
+                module_path!(),
+                &format_args!("{} {}", req.method(), req.uri()),
+                &[
+                    ("method", json!(req.method().as_str())),
+                    ("route", json!(route)),
+                    ("status", json!(res.status().code)),
+                    (
+                        "latencyMs",
+                        json!(start.started_at.elapsed().as_millis() as u64),
+                    ),
+                ],
+            );
+        }
+
+        CONTEXT.with(|context| *context.borrow_mut() = None);
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn generate_request_id() -> String {
+    let mut bytes = [0u8; 12];
+    // A predictable id is still better than none.
+    let _ = SystemRandom::new().fill(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Logs database connections that are checked out for longer than the
+/// threshold and slow checkouts, which happen if the pool is exhausted.
+///
+/// Only the time between checkout and checkin is known here, so a connection
+/// held across several quick queries is logged just like one slow query.
+#[derive(Debug)]
+pub struct SlowConnectionLogger {
+    pub threshold: Duration,
+}
+
+impl HandleEvent for SlowConnectionLogger {
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        if event.duration() > self.threshold {
+            log::warn!(
+                "Waited {}ms for a database connection",
+                event.duration().as_millis()
+            );
+        }
+    }
+
+    fn handle_timeout(&self, event: TimeoutEvent) {
+        log::warn!(
+            "Timed out after {}ms waiting for a database connection",
+            event.timeout().as_millis()
+        );
+    }
+
+    fn handle_checkin(&self, event: CheckinEvent) {
+        if event.duration() > self.threshold {
+            log::warn!(
+                "Database connection was in use for {}ms",
+                event.duration().as_millis()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_only_accepts_simple_request_ids() {
+        assert!(is_valid_request_id("7f3a-b2_c9"));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("a\nb"));
+        assert!(!is_valid_request_id(&"a".repeat(65)));
+        assert_eq!(generate_request_id().len(), 24);
+    }
+}
@@ -0,0 +1,316 @@
+//! Metrics in the Prometheus text format. Request and error metrics are
+//! recorded while handling requests, the database metrics are collected when
+//! the metrics are scraped.
+
+use diesel::prelude::*;
+use diesel::r2d2::event::{
+    CheckinEvent, CheckoutEvent, HandleEvent, TimeoutEvent,
+};
+use diesel::sql_types::BigInt;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::database::DbReadPool;
+use crate::store::StorageBackend;
+use crate::{BackendError, BackendResult, DbConnection, DbConnectionPool};
+
+/// The `pool` label of the metrics of the pool of the writer connection.
+pub const WRITE_POOL: &str = "write";
+/// The `pool` label of the metrics of the read pool.
+pub const READ_POOL: &str = "read";
+
+/// Holds all metrics of the backend. Cloning is cheap and all clones record
+/// into the same metrics.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    errors: IntCounterVec,
+    pool_wait_duration: HistogramVec,
+    pool_hold_duration: HistogramVec,
+    pool_timeouts: IntCounterVec,
+    pool_connections: IntGaugeVec,
+    pool_idle_connections: IntGaugeVec,
+    database_size: IntGauge,
+    users: IntGauge,
+    /// Not registered with the filesystem backend, which does not store the
+    /// nodes in the database.
+    nodes: Option<IntGauge>,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish()
+    }
+}
+
+impl Metrics {
+    pub fn new(storage: StorageBackend) -> BackendResult<Metrics> {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("mn_http_requests_total", "Number of handled requests"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "mn_http_request_duration_seconds",
+                "Time it took to handle a request",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "mn_errors_total",
+                "Number of error responses by BackendError variant",
+            ),
+            &["variant"],
+        )?;
+        let pool_wait_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "mn_db_pool_wait_seconds",
+                "Time spent waiting for a database connection",
+            ),
+            &["pool"],
+        )?;
+        let pool_hold_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "mn_db_pool_hold_seconds",
+                "Time a database connection was checked out",
+            ),
+            &["pool"],
+        )?;
+        let pool_timeouts = IntCounterVec::new(
+            Opts::new(
+                "mn_db_pool_timeouts_total",
+                "Number of timeouts waiting for a database connection",
+            ),
+            &["pool"],
+        )?;
+        let pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "mn_db_pool_connections",
+                "Number of open database connections",
+            ),
+            &["pool"],
+        )?;
+        let pool_idle_connections = IntGaugeVec::new(
+            Opts::new(
+                "mn_db_pool_idle_connections",
+                "Number of open database connections that are not in use",
+            ),
+            &["pool"],
+        )?;
+        let database_size = IntGauge::new(
+            "mn_database_size_bytes",
+            "Size of the database in bytes",
+        )?;
+        let users = IntGauge::new("mn_users", "Number of users")?;
+        let nodes = match storage {
+            StorageBackend::Database => Some(IntGauge::new(
+                "mn_nodes",
+                "Number of files and directories",
+            )?),
+            StorageBackend::Filesystem => None,
+        };
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(pool_wait_duration.clone()))?;
+        registry.register(Box::new(pool_hold_duration.clone()))?;
+        registry.register(Box::new(pool_timeouts.clone()))?;
+        registry.register(Box::new(pool_connections.clone()))?;
+        registry.register(Box::new(pool_idle_connections.clone()))?;
+        registry.register(Box::new(database_size.clone()))?;
+        registry.register(Box::new(users.clone()))?;
+        if let Some(nodes) = &nodes {
+            registry.register(Box::new(nodes.clone()))?;
+        }
+
+        Ok(Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            errors,
+            pool_wait_duration,
+            pool_hold_duration,
+            pool_timeouts,
+            pool_connections,
+            pool_idle_connections,
+            database_size,
+            users,
+            nodes,
+        })
+    }
+
+    /// Counts an error that is responded to a client.
+    pub fn observe_error(&self, err: &BackendError) {
+        self.errors.with_label_values(&[err.variant_name()]).inc();
+    }
+
+    /// Collects the database metrics and returns all metrics in the Prometheus
+    /// text format. The queries use the read pool, so scrapes never wait for
+    /// the writer connection.
+    pub fn gather(
+        &self,
+        pool: &DbConnectionPool,
+        read_pool: &DbReadPool,
+    ) -> BackendResult<String> {
+        // Read the pool states before checking out a connection for the
+        // queries below.
+        let pools: [(&str, &DbConnectionPool); 2] =
+            [(WRITE_POOL, pool), (READ_POOL, read_pool)];
+        for &(label, pool) in &pools {
+            let state = pool.state();
+            self.pool_connections
+                .with_label_values(&[label])
+                .set(i64::from(state.connections));
+            self.pool_idle_connections
+                .with_label_values(&[label])
+                .set(i64::from(state.idle_connections));
+        }
+
+        {
+            use crate::models::schema::{nodes, users};
+
+            let conn = read_pool.get()?;
+            self.database_size.set(database_size(&conn)?);
+            self.users.set(users::table.count().get_result(&conn)?);
+            if let Some(gauge) = &self.nodes {
+                gauge.set(nodes::table.count().get_result(&conn)?);
+            }
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        // The text encoder only writes valid utf-8.
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+#[derive(QueryableByName)]
+struct DatabaseSize {
+    #[sql_type = "BigInt"]
+    size: i64,
+}
+
+#[cfg(not(feature = "postgres"))]
+const DATABASE_SIZE_QUERY: &str = "SELECT page_count * page_size AS size \
+     FROM pragma_page_count(), pragma_page_size()";
+#[cfg(feature = "postgres")]
+const DATABASE_SIZE_QUERY: &str =
+    "SELECT pg_database_size(current_database()) AS size";
+
+fn database_size(conn: &DbConnection) -> BackendResult<i64> {
+    let size: DatabaseSize =
+        diesel::sql_query(DATABASE_SIZE_QUERY).get_result(conn)?;
+    Ok(size.size)
+}
+
+/// The start time of a request, cached in the request.
+struct RequestTimer(Instant);
+
+impl Fairing for Metrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, req: &mut Request, _: &Data) {
+        req.local_cache(|| RequestTimer(Instant::now()));
+    }
+
+    fn on_response(&self, req: &Request, res: &mut Response) {
+        let RequestTimer(started_at) =
+            req.local_cache(|| RequestTimer(Instant::now()));
+        // Use the route instead of the path to keep the number of label
+        // values bounded. Unmatched requests are counted together.
+        let route = req
+            .route()
+            .map(|route| route.uri.to_string())
+            .unwrap_or_else(|| String::from("unmatched"));
+        let status = res.status().code.to_string();
+        let labels = [req.method().as_str(), route.as_str(), status.as_str()];
+
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration
+            .with_label_values(&labels)
+            .observe(seconds(started_at.elapsed()));
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+/// Records the pool metrics and forwards every event to the given handler.
+#[derive(Debug)]
+pub struct PoolMetrics<H> {
+    pub metrics: Metrics,
+    /// The `pool` label, `WRITE_POOL` or `READ_POOL`.
+    pub pool: &'static str,
+    pub inner: H,
+}
+
+impl<H: HandleEvent> HandleEvent for PoolMetrics<H> {
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        self.metrics
+            .pool_wait_duration
+            .with_label_values(&[self.pool])
+            .observe(seconds(event.duration()));
+        self.inner.handle_checkout(event);
+    }
+
+    fn handle_timeout(&self, event: TimeoutEvent) {
+        self.metrics
+            .pool_timeouts
+            .with_label_values(&[self.pool])
+            .inc();
+        self.inner.handle_timeout(event);
+    }
+
+    fn handle_checkin(&self, event: CheckinEvent) {
+        self.metrics
+            .pool_hold_duration
+            .with_label_values(&[self.pool])
+            .observe(seconds(event.duration()));
+        self.inner.handle_checkin(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+
+    #[test]
+    fn it_exposes_the_metrics() -> BackendResult<()> {
+        let pool = database::create_test_pool()?;
+        database::run_migrations(&*pool.get()?)?;
+        let metrics = Metrics::new(StorageBackend::Database)?;
+        metrics.observe_error(&BackendError::PathNotFound(vec![]));
+
+        let text = metrics.gather(&pool, &DbReadPool::shared(&pool))?;
+        assert!(text.contains("mn_errors_total{variant=\"PathNotFound\"} 1"));
+        assert!(text.contains("mn_users 0"));
+        assert!(text.contains("mn_nodes 0"));
+        assert!(text.contains("mn_db_pool_connections{pool=\"write\"} 1"));
+        assert!(text.contains("mn_db_pool_connections{pool=\"read\"} 1"));
+
+        let metrics = Metrics::new(StorageBackend::Filesystem)?;
+        let text = metrics.gather(&pool, &DbReadPool::shared(&pool))?;
+        assert!(text.contains("mn_users 0"));
+        assert!(!text.contains("mn_nodes"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use chrono::prelude::*;
+use diesel::prelude::*;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, Serializer};
+
+use super::schema::access_tokens;
+use crate::errors::{BackendError, BackendResult};
+use crate::models::users::UserId;
+use crate::{DbConnection, DbConnectionPool};
+
+pub type AccessTokenId = i32;
+
+/// Every personal access token starts with this prefix. It allows the request
+/// guard to tell access tokens and JWTs apart without trying to decode them.
+pub const ACCESS_TOKEN_PREFIX: &str = "mnpat_";
+
+/// The number of random bytes a personal access token consists of.
+const ACCESS_TOKEN_BYTES: usize = 32;
+
+/// `last_used_at` is only updated if it is older than this many seconds, so
+/// that not every request authenticated with a token writes to the database.
+const LAST_USED_INTERVAL: i64 = 60;
+
+/// A permission that can be granted to a personal access token.
+#[derive(
+    Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Debug,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Allows reading nodes and the profile of the user.
+    Read,
+    /// Allows creating, changing and deleting nodes.
+    Write,
+    /// Allows using the administrative routes.
+    Admin,
+}
+
+impl Scope {
+    /// The scopes granted to a user that logged in with their password.
+    pub const SESSION: &'static [Scope] = &[Scope::Read, Scope::Write];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+
+    /// Parses a single scope. Returns `BackendError::InvalidValue` if the
+    /// given string is not a known scope.
+    pub fn parse(value: &str) -> BackendResult<Scope> {
+        match value.trim() {
+            "read" => Ok(Scope::Read),
+            "write" => Ok(Scope::Write),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(BackendError::InvalidValue),
+        }
+    }
+
+    /// Parses a comma separated list of scopes as it is stored in the
+    /// database.
+    pub fn parse_list(value: &str) -> BackendResult<Vec<Scope>> {
+        value
+            .split(',')
+            .filter(|part| !part.trim().is_empty())
+            .map(Scope::parse)
+            .collect()
+    }
+
+    /// Joins the given scopes into a comma separated list as it is stored in
+    /// the database.
+    pub fn join_list(scopes: &[Scope]) -> String {
+        scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "access_tokens"]
+struct NewAccessToken<'a> {
+    user_id: UserId,
+    token_name: &'a str,
+    token_hash: &'a str,
+    scopes: &'a str,
+    created_at: i64,
+    expires_at: Option<i64>,
+}
+
+/// A personal access token of a user. The token itself is never stored, only
+/// its hash, so it can only be shown once right after it was created.
+#[derive(Identifiable, Queryable, Serialize, JsonSchema, Debug)]
+#[table_name = "access_tokens"]
+#[serde(rename_all = "camelCase")]
+pub struct AccessToken {
+    pub id: AccessTokenId,
+    #[serde(skip_serializing)]
+    #[schemars(skip)]
+    pub user_id: UserId,
+    #[serde(rename = "name")]
+    pub token_name: String,
+    #[serde(skip_serializing)]
+    #[schemars(skip)]
+    pub token_hash: String,
+    #[serde(serialize_with = "serialize_scopes")]
+    #[schemars(with = "Vec<Scope>")]
+    pub scopes: String,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub expires_at: Option<i64>,
+}
+
+/// Serializes the comma separated scopes column as a list of strings.
+fn serialize_scopes<S>(scopes: &str, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(scopes.split(',').filter(|scope| !scope.is_empty()))
+}
+
+/// Generates a new random access token including the `ACCESS_TOKEN_PREFIX`.
+fn generate_token() -> BackendResult<String> {
+    let mut bytes = [0u8; ACCESS_TOKEN_BYTES];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| BackendError::TokenGeneration)?;
+    Ok(format!("{}{}", ACCESS_TOKEN_PREFIX, to_hex(&bytes)))
+}
+
+/// Hashes the given token for storage. Since access tokens are long random
+/// strings a fast hash is sufficient, in contrast to passwords.
+fn hash_token(token: &str) -> String {
+    to_hex(digest(&SHA256, token.as_bytes()).as_ref())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl AccessToken {
+    /// Returns the scopes granted to this token. Unknown scopes in the
+    /// database are ignored.
+    pub fn scope_list(&self) -> Vec<Scope> {
+        self.scopes
+            .split(',')
+            .filter_map(|scope| Scope::parse(scope).ok())
+            .collect()
+    }
+
+    /// Whether this token has an expiry date that lies in the past.
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= Utc::now().timestamp(),
+            None => false,
+        }
+    }
+
+    /// Creates a new access token for the given user. Returns the stored token
+    /// together with the actual token string, which is not recoverable
+    /// afterwards.
+    ///
+    /// Returns `BackendError::InvalidValue` if the name is empty, no scope is
+    /// given or `expires_at` lies in the past.
+    pub fn create(
+        conn: &DbConnection,
+        user_id: UserId,
+        name: &str,
+        scopes: &[Scope],
+        expires_at: Option<i64>,
+    ) -> BackendResult<(AccessToken, String)> {
+        let name = name.trim();
+        if name.is_empty() || scopes.is_empty() {
+            return Err(BackendError::InvalidValue);
+        }
+        let now = Utc::now().timestamp();
+        if expires_at.map_or(false, |expires_at| expires_at <= now) {
+            return Err(BackendError::InvalidValue);
+        }
+
+        let token = generate_token()?;
+        let token_hash = hash_token(&token);
+        let scopes = Scope::join_list(scopes);
+        let new_token = NewAccessToken {
+            user_id,
+            token_name: name,
+            token_hash: &token_hash,
+            scopes: &scopes,
+            created_at: now,
+            expires_at,
+        };
+
+        conn.transaction(|| {
+            diesel::insert_into(access_tokens::table)
+                .values(&new_token)
+                .execute(conn)?;
+            let access_token = access_tokens::table
+                .filter(access_tokens::token_hash.eq(&token_hash))
+                .first::<AccessToken>(conn)?;
+            Ok((access_token, token))
+        })
+    }
+
+    /// Fetches all access tokens of the given user.
+    pub fn fetch_all_for_user(
+        conn: &DbConnection,
+        user_id: UserId,
+    ) -> BackendResult<Vec<AccessToken>> {
+        let tokens = access_tokens::table
+            .filter(access_tokens::user_id.eq(user_id))
+            .order(access_tokens::id)
+            .get_results::<AccessToken>(conn)?;
+        Ok(tokens)
+    }
+
+    /// Revokes the access token with the given id. Returns
+    /// `BackendError::NotFound` if the user has no such token.
+    pub fn revoke(
+        conn: &DbConnection,
+        user_id: UserId,
+        id: AccessTokenId,
+    ) -> BackendResult<()> {
+        let count = diesel::delete(
+            access_tokens::table
+                .filter(access_tokens::user_id.eq(user_id))
+                .filter(access_tokens::id.eq(id)),
+        )
+        .execute(conn)?;
+        if count == 0 {
+            return Err(BackendError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the access token matching the given token string. Returns
+    /// `BackendError::InvalidCredentials` if there is no such token and
+    /// `BackendError::TokenExpired` if it is expired.
+    pub fn authenticate(
+        conn: &DbConnection,
+        token: &str,
+    ) -> BackendResult<AccessToken> {
+        let token_hash = hash_token(token);
+        let access_token = match access_tokens::table
+            .filter(access_tokens::token_hash.eq(&token_hash))
+            .first::<AccessToken>(conn)
+            .optional()?
+        {
+            Some(access_token) => access_token,
+            None => return Err(BackendError::InvalidCredentials),
+        };
+        if access_token.is_expired() {
+            return Err(BackendError::TokenExpired);
+        }
+
+        Ok(access_token)
+    }
+
+    /// Records that the token was used, at most once per
+    /// `LAST_USED_INTERVAL`. This is best-effort: it is skipped while the
+    /// writer connection is in use and failures are only logged, so that
+    /// requests authenticated with a token never wait for the writer.
+    pub fn record_use(&self, pool: &DbConnectionPool) {
+        let now = Utc::now().timestamp();
+        let recent = self.last_used_at.map_or(false, |last_used_at| {
+            now - last_used_at < LAST_USED_INTERVAL
+        });
+        if recent {
+            return;
+        }
+        let conn = match pool.try_get() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let result = diesel::update(self)
+            .set(access_tokens::last_used_at.eq(Some(now)))
+            .execute(&conn);
+        if let Err(err) = result {
+            log::warn!(
+                "Failed to record the use of access token {}: {}",
+                self.id,
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_scope_lists() -> BackendResult<()> {
+        let scopes = vec![Scope::Read, Scope::Admin];
+        let joined = Scope::join_list(&scopes);
+
+        assert_eq!(joined, "read,admin");
+        assert_eq!(Scope::parse_list(&joined)?, scopes);
+        assert!(Scope::parse_list("read,delete").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_only_stores_a_hash() -> BackendResult<()> {
+        let token = generate_token()?;
+
+        assert!(token.starts_with(ACCESS_TOKEN_PREFIX));
+        assert_ne!(hash_token(&token), token);
+        assert_eq!(hash_token(&token), hash_token(&token));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+use chrono::prelude::*;
+use diesel::prelude::*;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use super::schema::login_lockouts;
+use crate::errors::{BackendError, BackendResult};
+use crate::DbConnection;
+
+pub type LoginLockoutId = i32;
+
+/// Configures after how many failed login attempts and for how long logins are
+/// blocked. All durations are in seconds.
+#[derive(Clone, Debug)]
+pub struct LockoutConfig {
+    /// The number of failed attempts that are allowed before logins are
+    /// blocked.
+    pub free_attempts: i32,
+    /// The duration of the first lockout. Each further failed attempt doubles
+    /// the duration.
+    pub base_lockout: i64,
+    /// The maximum duration of a lockout.
+    pub max_lockout: i64,
+    /// The failed attempts are forgotten if there was no failed attempt for
+    /// this long.
+    pub reset_after: i64,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        LockoutConfig {
+            free_attempts: 5,
+            base_lockout: 30,
+            max_lockout: chrono::Duration::hours(1).num_seconds(),
+            reset_after: chrono::Duration::days(1).num_seconds(),
+        }
+    }
+}
+
+impl LockoutConfig {
+    /// Returns for how long logins are blocked after the given number of
+    /// consecutive failed attempts or `None` if they are not blocked at all.
+    pub fn lockout_duration(&self, failed_attempts: i32) -> Option<i64> {
+        let exceeding = failed_attempts - self.free_attempts;
+        if exceeding <= 0 {
+            return None;
+        }
+
+        // Prevent an overflow, the duration is capped by max_lockout anyway.
+        let shift = (exceeding - 1).min(62) as u32;
+        let duration = self.base_lockout.saturating_mul(1 << shift);
+        Some(duration.min(self.max_lockout))
+    }
+}
+
+/// What the failed attempts of a `LoginLockout` are counted for.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LockoutKind {
+    Username,
+    Ip,
+}
+
+impl LockoutKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LockoutKind::Username => "username",
+            LockoutKind::Ip => "ip",
+        }
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "login_lockouts"]
+struct NewLoginLockout<'a> {
+    lockout_kind: &'a str,
+    lockout_key: &'a str,
+    failed_attempts: i32,
+    last_failed_at: i64,
+    locked_until: Option<i64>,
+}
+
+/// The failed login attempts for either a username or a client ip address.
+#[derive(Identifiable, Queryable, Serialize, JsonSchema, Debug)]
+#[table_name = "login_lockouts"]
+#[serde(rename_all = "camelCase")]
+pub struct LoginLockout {
+    pub id: LoginLockoutId,
+    #[serde(rename = "kind")]
+    pub lockout_kind: String,
+    #[serde(rename = "key")]
+    pub lockout_key: String,
+    pub failed_attempts: i32,
+    pub last_failed_at: i64,
+    pub locked_until: Option<i64>,
+}
+
+impl LoginLockout {
+    fn fetch(
+        conn: &DbConnection,
+        kind: LockoutKind,
+        key: &str,
+    ) -> BackendResult<Option<LoginLockout>> {
+        let lockout = login_lockouts::table
+            .filter(login_lockouts::lockout_kind.eq(kind.as_str()))
+            .filter(login_lockouts::lockout_key.eq(key))
+            .first::<LoginLockout>(conn)
+            .optional()?;
+        Ok(lockout)
+    }
+
+    /// Returns `BackendError::TooManyAttempts` if logins for any of the given
+    /// keys are currently blocked.
+    pub fn check(
+        conn: &DbConnection,
+        keys: &[(LockoutKind, &str)],
+    ) -> BackendResult<()> {
+        let now = Utc::now().timestamp();
+        let mut retry_after = 0;
+        for (kind, key) in keys {
+            if let Some(lockout) = Self::fetch(conn, *kind, key)? {
+                match lockout.locked_until {
+                    Some(locked_until) if locked_until > now => {
+                        retry_after = retry_after.max(locked_until - now);
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        if retry_after > 0 {
+            Err(BackendError::TooManyAttempts(retry_after))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Counts a failed login attempt for the given key and blocks further
+    /// logins if there were too many. Also removes outdated entries.
+    pub fn record_failure(
+        conn: &DbConnection,
+        cfg: &LockoutConfig,
+        kind: LockoutKind,
+        key: &str,
+    ) -> BackendResult<()> {
+        let now = Utc::now().timestamp();
+        let outdated = now - cfg.reset_after;
+
+        conn.transaction(|| {
+            diesel::delete(
+                login_lockouts::table
+                    .filter(login_lockouts::last_failed_at.lt(outdated))
+                    .filter(
+                        login_lockouts::locked_until
+                            .is_null()
+                            .or(login_lockouts::locked_until.lt(now)),
+                    ),
+            )
+            .execute(conn)?;
+
+            match Self::fetch(conn, kind, key)? {
+                Some(lockout) => {
+                    let failed_attempts = lockout.failed_attempts + 1;
+                    let locked_until = cfg
+                        .lockout_duration(failed_attempts)
+                        .map(|duration| now + duration);
+                    diesel::update(&lockout)
+                        .set((
+                            login_lockouts::failed_attempts.eq(failed_attempts),
+                            login_lockouts::last_failed_at.eq(now),
+                            login_lockouts::locked_until.eq(locked_until),
+                        ))
+                        .execute(conn)?;
+                }
+                None => {
+                    diesel::insert_into(login_lockouts::table)
+                        .values(NewLoginLockout {
+                            lockout_kind: kind.as_str(),
+                            lockout_key: key,
+                            failed_attempts: 1,
+                            last_failed_at: now,
+                            locked_until: cfg
+                                .lockout_duration(1)
+                                .map(|duration| now + duration),
+                        })
+                        .execute(conn)?;
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Forgets all failed attempts for the given key, e.g. after a successful
+    /// login.
+    pub fn clear(
+        conn: &DbConnection,
+        kind: LockoutKind,
+        key: &str,
+    ) -> BackendResult<()> {
+        diesel::delete(
+            login_lockouts::table
+                .filter(login_lockouts::lockout_kind.eq(kind.as_str()))
+                .filter(login_lockouts::lockout_key.eq(key)),
+        )
+        .execute(conn)?;
+        Ok(())
+    }
+
+    /// Fetches all keys with failed attempts, most recent first.
+    pub fn fetch_all(conn: &DbConnection) -> BackendResult<Vec<LoginLockout>> {
+        let lockouts = login_lockouts::table
+            .order(login_lockouts::last_failed_at.desc())
+            .get_results::<LoginLockout>(conn)?;
+        Ok(lockouts)
+    }
+
+    /// Deletes the entry with the given id, which lifts the lockout. Returns
+    /// `BackendError::NotFound` if there is no such entry.
+    pub fn delete_by_id(
+        conn: &DbConnection,
+        id: LoginLockoutId,
+    ) -> BackendResult<()> {
+        let count = diesel::delete(
+            login_lockouts::table.filter(login_lockouts::id.eq(id)),
+        )
+        .execute(conn)?;
+        if count == 0 {
+            return Err(BackendError::NotFound);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_backs_off_exponentially() {
+        let cfg = LockoutConfig {
+            free_attempts: 3,
+            base_lockout: 10,
+            max_lockout: 100,
+            reset_after: 1000,
+        };
+
+        assert_eq!(cfg.lockout_duration(1), None);
+        assert_eq!(cfg.lockout_duration(3), None);
+        assert_eq!(cfg.lockout_duration(4), Some(10));
+        assert_eq!(cfg.lockout_duration(5), Some(20));
+        assert_eq!(cfg.lockout_duration(6), Some(40));
+        assert_eq!(cfg.lockout_duration(8), Some(100));
+        assert_eq!(cfg.lockout_duration(500), Some(100));
+    }
+}
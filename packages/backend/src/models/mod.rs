@@ -1,9 +1,18 @@
 pub mod schema;
 
+mod access_tokens;
+mod login_lockouts;
 mod nodes;
 mod users;
 
+pub use access_tokens::{
+    AccessToken, AccessTokenId, Scope, ACCESS_TOKEN_PREFIX,
+};
+pub use login_lockouts::{
+    LockoutConfig, LockoutKind, LoginLockout, LoginLockoutId,
+};
 pub use nodes::{
-    NewNode, NewNodePayload, Node, NodeId, NodeName, OwnedPath, Path,
+    NewNode, NewNodePayload, Node, NodeId, NodeName, NodeQueries, OwnedPath,
+    Path,
 };
-pub use users::{NewUser, User, UserId};
+pub use users::{NewUser, User, UserId, UserQueries};
@@ -1,5 +1,4 @@
 use diesel::prelude::*;
-use serde::{Deserialize, Serialize};
 
 use crate::database::DbConnection;
 use crate::errors::{BackendError, BackendResult};
@@ -7,19 +6,7 @@ use crate::models::users::UserId;
 
 use super::schema::nodes;
 
-pub type NodeName = String;
-pub type NodeId = i32;
-
-pub type Path = [NodeName];
-pub type OwnedPath = Vec<NodeName>;
-
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct NewNodePayload {
-    pub name: String,
-    pub is_directory: bool,
-    pub content: Option<String>,
-}
+pub use api_types::{NewNodePayload, Node, NodeId, NodeName, OwnedPath, Path};
 
 #[derive(Insertable, Debug)]
 #[table_name = "nodes"]
@@ -42,10 +29,7 @@ impl NewNode {
         let node = if payload.is_directory {
             NewNode {
                 node_name: payload.name.clone(),
-                parent_id: match parent_id {
-                    Some(id) => Some(*id),
-                    None => None,
-                },
+                parent_id: parent_id.copied(),
                 parent_is_directory: if parent_id.is_some() {
                     Some(true)
                 } else {
@@ -62,10 +46,7 @@ impl NewNode {
 
             NewNode {
                 node_name: payload.name.clone(),
-                parent_id: match parent_id {
-                    Some(id) => Some(*id),
-                    None => None,
-                },
+                parent_id: parent_id.copied(),
                 parent_is_directory: if parent_id.is_some() {
                     Some(true)
                 } else {
@@ -81,112 +62,96 @@ impl NewNode {
     }
 }
 
-#[derive(Identifiable, Queryable, Associations, Serialize, PartialEq, Debug)]
-#[table_name = "nodes"]
-#[primary_key(node_id)]
-#[belongs_to(Node)]
-#[serde(rename_all = "camelCase")]
-pub struct Node {
-    pub node_id: NodeId,
-    pub node_name: NodeName,
+/// The queries of the nodes table. `Node` is shared with the clients in the
+/// api-types crate, so they are implemented through this trait.
+pub trait NodeQueries {
+    /// Fetches a single node represented by the given path. The given `user_id`
+    /// must be the id of the owner of that node.
+    fn fetch_by_path_for_user(
+        conn: &DbConnection,
+        user_id: &UserId,
+        path: &Path,
+    ) -> BackendResult<Node>;
 
-    pub parent_id: Option<NodeId>,
-    #[serde(skip_serializing)]
-    pub parent_is_directory: Option<bool>,
-    pub owner_id: UserId,
+    /// Fetches all nodes that are owned by the user associated to the given
+    /// `user_id`.
+    fn fetch_all_for_user(
+        conn: &DbConnection,
+        user_id: &UserId,
+    ) -> BackendResult<Vec<Node>>;
 
-    pub is_directory: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub content: Option<String>,
-}
+    /// Fetches the nodes in the directory with the given id, or the root nodes
+    /// if `parent_id` is `None`, of the user associated to the given
+    /// `user_id`, ordered by name.
+    fn fetch_children_for_user(
+        conn: &DbConnection,
+        user_id: &UserId,
+        parent_id: Option<NodeId>,
+    ) -> BackendResult<Vec<Node>>;
 
-impl Node {
-    fn fetch_id_by_path_for_user(
+    /// Fetches the node with the given id. The given `user_id` must be the id
+    /// of the owner of that node.
+    fn fetch_by_id_for_user(
         conn: &DbConnection,
         user_id: &UserId,
-        path: &Path,
-    ) -> BackendResult<NodeId> {
-        // A CTE like this would allow for this to be a one query operation:
-        //   with recursive node_tree (
-        //       node_id, node_name, parent_id, parent_is_directory, owner_id,
-        //       is_directory, content, depth
-        //   ) as (
-        //       select *, 0 as depth
-        //       from nodes
-        //       where parent_id is null and owner_id = 1
-        //       union all
-        //       select nodes.*, node_tree.depth + 1 as depth
-        //       from node_tree
-        //           join nodes on (node_tree.node_id = nodes.parent_id)
-        //       where (
-        //               (depth = 0 and node_tree.node_name = 'Allgemein')
-        //               or depth is not 0
-        //           )
-        //           and (
-        //               (depth = 1 and node_tree.node_name = 'Projekte')
-        //               or depth is not 1
-        //           )
-        //           and depth < 2 - 1
-        //   )
-        //   select node_id, node_name
-        //   from node_tree
-        //   order by depth DESC
-        //   limit 1;
-        //
-        // Sadly CTEs or at least recursive ones are not supported by diesels
-        // query builder yet: https://github.com/diesel-rs/diesel/issues/356
-        // Also non fixed amount of bind values is not supported yet:
-        // https://github.com/diesel-rs/diesel/issues/2103
-        // Since nothing is supported to execute this query in a proper way,
-        // just load each node one after another along the path.
+        node_id: NodeId,
+    ) -> BackendResult<Node>;
 
-        conn.transaction(|| {
-            let mut node_id: Option<NodeId> = None;
-            for part in path.iter() {
-                let mut query = nodes::table
-                    .select(nodes::node_id)
-                    .filter(nodes::owner_id.eq(user_id))
-                    .filter(nodes::node_name.eq(part))
-                    .into_boxed();
-
-                // Add the node if there is one from a previous iteration step
-                // as the parent to the query.
-                query = match node_id {
-                    Some(parent_id) => {
-                        query.filter(nodes::parent_id.eq(parent_id))
-                    }
-                    None => query,
-                };
-
-                node_id = Some(query.first(conn)?);
-            }
+    /// Inserts a new node into the database. An empty `parent_path` means the
+    /// node will be added as a root node. Returns the new node.
+    fn insert(
+        conn: &DbConnection,
+        owner_id: &UserId,
+        parent_path: &Path,
+        payload: &NewNodePayload,
+    ) -> BackendResult<Node>;
 
-            match node_id {
-                Some(node_id) => Ok(node_id),
-                None => Err(BackendError::NotFound),
-            }
-        })
-    }
+    /// Inserts a new node into the directory with the given id, or as a root
+    /// node if `parent_id` is `None`. Returns the new node.
+    fn insert_into(
+        conn: &DbConnection,
+        owner_id: &UserId,
+        parent_id: Option<NodeId>,
+        payload: &NewNodePayload,
+    ) -> BackendResult<Node>;
 
-    /// Checks whether the given string is a valid node name.
-    fn is_name_valid(name: &str) -> bool {
-        // Based on https://stackoverflow.com/a/35352640 prevent several
-        // charactes to ensure compatibility with usual file systems.
-        name.chars().all(|c| match c {
-            '\\' | '/' | ':' | '*' | '"' | '<' | '>' | '|' => false,
-            _ => true,
-        })
-    }
+    /// Changes the content of this node. Returns a new node instance with the
+    /// updated content value. Returns `BackendError::InvalidValue` error if
+    /// this node is a directory.
+    fn change_content(
+        self,
+        conn: &DbConnection,
+        new_content: &str,
+    ) -> BackendResult<Node>;
 
-    /// Fetches a single node represented by the given path. The given `user_id`
-    /// must be the id of the owner of that node.
-    pub fn fetch_by_path_for_user(
+    /// Changes the name of this node. Returns a new node instance with the
+    /// updated name value.
+    fn change_name(
+        self,
+        conn: &DbConnection,
+        new_name: &str,
+    ) -> BackendResult<Node>;
+
+    /// Deletes this node from the database.
+    fn delete(self, conn: &DbConnection) -> BackendResult<()>;
+
+    /// Changes the parent of this node. If `new_parent` is `None` this node
+    /// will be attached to the root.
+    fn change_parent(
+        self,
+        conn: &DbConnection,
+        new_parent: Option<&Self>,
+    ) -> BackendResult<Node>;
+}
+
+impl NodeQueries for Node {
+    fn fetch_by_path_for_user(
         conn: &DbConnection,
         user_id: &UserId,
         path: &Path,
     ) -> BackendResult<Node> {
         conn.transaction(|| {
-            let node_id = Self::fetch_id_by_path_for_user(conn, user_id, path)?;
+            let node_id = fetch_id_by_path_for_user(conn, user_id, path)?;
             let node = nodes::table
                 .filter(nodes::owner_id.eq(user_id))
                 .filter(nodes::node_id.eq(node_id))
@@ -195,9 +160,7 @@ impl Node {
         })
     }
 
-    /// Fetches all nodes that are owned by the user associated to the given
-    /// `user_id`.
-    pub fn fetch_all_for_user(
+    fn fetch_all_for_user(
         conn: &DbConnection,
         user_id: &UserId,
     ) -> BackendResult<Vec<Node>> {
@@ -207,49 +170,85 @@ impl Node {
         Ok(nodes)
     }
 
-    /// Inserts a new node into the database. An empty `parent_path` means the
-    /// node will be added as a root node. Returns the new node.
-    pub fn insert(
+    fn fetch_children_for_user(
+        conn: &DbConnection,
+        user_id: &UserId,
+        parent_id: Option<NodeId>,
+    ) -> BackendResult<Vec<Node>> {
+        let query = nodes::table
+            .filter(nodes::owner_id.eq(user_id))
+            .order(nodes::node_name)
+            .into_boxed();
+        let query = match parent_id {
+            Some(parent_id) => query.filter(nodes::parent_id.eq(parent_id)),
+            None => query.filter(nodes::parent_id.is_null()),
+        };
+        Ok(query.get_results::<Node>(conn)?)
+    }
+
+    fn fetch_by_id_for_user(
+        conn: &DbConnection,
+        user_id: &UserId,
+        node_id: NodeId,
+    ) -> BackendResult<Node> {
+        let node = nodes::table
+            .filter(nodes::owner_id.eq(user_id))
+            .filter(nodes::node_id.eq(node_id))
+            .first::<Node>(conn)?;
+        Ok(node)
+    }
+
+    fn insert(
         conn: &DbConnection,
         owner_id: &UserId,
         parent_path: &Path,
         payload: &NewNodePayload,
     ) -> BackendResult<Node> {
-        if !Self::is_name_valid(&payload.name) {
-            return Err(BackendError::InvalidNodeName(payload.name.clone()));
-        }
-
-        conn.transaction::<_, BackendError, _>(|| {
+        conn.transaction(|| {
             let parent_id = if parent_path.is_empty() {
                 // New root node.
                 None
             } else {
                 // Load parent node id.
-                let parent_id = Node::fetch_id_by_path_for_user(
-                    &conn,
-                    owner_id,
-                    &parent_path,
-                )?;
+                let parent_id =
+                    fetch_id_by_path_for_user(conn, owner_id, parent_path)?;
                 Some(parent_id)
             };
+            Self::insert_into(conn, owner_id, parent_id, payload)
+        })
+    }
+
+    fn insert_into(
+        conn: &DbConnection,
+        owner_id: &UserId,
+        parent_id: Option<NodeId>,
+        payload: &NewNodePayload,
+    ) -> BackendResult<Node> {
+        if !Self::is_name_valid(&payload.name) {
+            return Err(BackendError::InvalidNodeName(payload.name.clone()));
+        }
+
+        conn.transaction::<_, BackendError, _>(|| {
             let new_node_data =
                 NewNode::new(payload, parent_id.as_ref(), owner_id)?;
             diesel::insert_into(nodes::table)
                 .values(new_node_data)
                 .execute(conn)?;
-            let mut new_node_path = parent_path.to_vec();
-            new_node_path.push(payload.name.clone());
-            let new_node =
-                Self::fetch_by_path_for_user(conn, owner_id, &new_node_path)?;
+            let query = nodes::table
+                .filter(nodes::owner_id.eq(owner_id))
+                .filter(nodes::node_name.eq(&payload.name))
+                .into_boxed();
+            let query = match parent_id {
+                Some(parent_id) => query.filter(nodes::parent_id.eq(parent_id)),
+                None => query.filter(nodes::parent_id.is_null()),
+            };
+            let new_node = query.first::<Node>(conn)?;
 
             Ok(new_node)
         })
     }
 
-    /// Changes the content of this node. Returns a new node instance with the
-    /// updated content value. Returns `BackendError::InvalidValue` error if
-    /// this node is a directory.
-    pub fn change_content(
+    fn change_content(
         self,
         conn: &DbConnection,
         new_content: &str,
@@ -258,7 +257,7 @@ impl Node {
             return Err(BackendError::InvalidValue);
         }
 
-        let count = diesel::update(&self)
+        let count = diesel::update(nodes::table.find(self.node_id))
             .set(nodes::content.eq(new_content))
             .execute(conn)?;
         if count == 0 {
@@ -271,9 +270,7 @@ impl Node {
         })
     }
 
-    /// Changes the name of this node. Returns a new node instance with the
-    /// updated name value.
-    pub fn change_name(
+    fn change_name(
         self,
         conn: &DbConnection,
         new_name: &str,
@@ -282,7 +279,7 @@ impl Node {
             return Err(BackendError::InvalidNodeName(String::from(new_name)));
         }
 
-        let count = diesel::update(&self)
+        let count = diesel::update(nodes::table.find(self.node_id))
             .set(nodes::node_name.eq(new_name))
             .execute(conn)?;
         if count == 0 {
@@ -295,9 +292,9 @@ impl Node {
         })
     }
 
-    /// Deletes this node from the database.
-    pub fn delete(self, conn: &DbConnection) -> BackendResult<()> {
-        let count = diesel::delete(&self).execute(conn)?;
+    fn delete(self, conn: &DbConnection) -> BackendResult<()> {
+        let count =
+            diesel::delete(nodes::table.find(self.node_id)).execute(conn)?;
         if count == 0 {
             return Err(BackendError::NotFound);
         }
@@ -305,9 +302,7 @@ impl Node {
         Ok(())
     }
 
-    /// Changes the parent of this node. If `new_parent` is `None` this node
-    /// will be attached to the root.
-    pub fn change_parent(
+    fn change_parent(
         self,
         conn: &DbConnection,
         new_parent: Option<&Self>,
@@ -315,11 +310,13 @@ impl Node {
         let maybe_new_parent_id = new_parent.map(|node| node.node_id);
 
         let query = match maybe_new_parent_id {
-            Some(new_parent_id) => diesel::update(&self).set((
-                nodes::parent_id.eq(Some(new_parent_id)),
-                nodes::parent_is_directory.eq(Some(true)),
-            )),
-            None => diesel::update(&self).set((
+            Some(new_parent_id) => {
+                diesel::update(nodes::table.find(self.node_id)).set((
+                    nodes::parent_id.eq(Some(new_parent_id)),
+                    nodes::parent_is_directory.eq(Some(true)),
+                ))
+            }
+            None => diesel::update(nodes::table.find(self.node_id)).set((
                 nodes::parent_id.eq(None),
                 nodes::parent_is_directory.eq(None),
             )),
@@ -336,3 +333,113 @@ impl Node {
         })
     }
 }
+
+fn fetch_id_by_path_for_user(
+    conn: &DbConnection,
+    user_id: &UserId,
+    path: &Path,
+) -> BackendResult<NodeId> {
+    // A CTE like this would allow for this to be a one query operation:
+    //   with recursive node_tree (
+    //       node_id, node_name, parent_id, parent_is_directory, owner_id,
+    //       is_directory, content, depth
+    //   ) as (
+    //       select *, 0 as depth
+    //       from nodes
+    //       where parent_id is null and owner_id = 1
+    //       union all
+    //       select nodes.*, node_tree.depth + 1 as depth
+    //       from node_tree
+    //           join nodes on (node_tree.node_id = nodes.parent_id)
+    //       where (
+    //               (depth = 0 and node_tree.node_name = 'Allgemein')
+    //               or depth is not 0
+    //           )
+    //           and (
+    //               (depth = 1 and node_tree.node_name = 'Projekte')
+    //               or depth is not 1
+    //           )
+    //           and depth < 2 - 1
+    //   )
+    //   select node_id, node_name
+    //   from node_tree
+    //   order by depth DESC
+    //   limit 1;
+    //
+    // Sadly CTEs or at least recursive ones are not supported by diesels
+    // query builder yet: https://github.com/diesel-rs/diesel/issues/356
+    // Also non fixed amount of bind values is not supported yet:
+    // https://github.com/diesel-rs/diesel/issues/2103
+    // Since nothing is supported to execute this query in a proper way,
+    // just load each node one after another along the path.
+
+    conn.transaction(|| {
+        let mut node_id: Option<NodeId> = None;
+        for part in path.iter() {
+            let mut query = nodes::table
+                .select(nodes::node_id)
+                .filter(nodes::owner_id.eq(user_id))
+                .filter(nodes::node_name.eq(part))
+                .into_boxed();
+
+            // Add the node if there is one from a previous iteration
+            // step as the parent to the query. The first part of the path
+            // is a root node.
+            query = match node_id {
+                Some(parent_id) => query.filter(nodes::parent_id.eq(parent_id)),
+                None => query.filter(nodes::parent_id.is_null()),
+            };
+
+            node_id = match query.first(conn).optional()? {
+                Some(node_id) => Some(node_id),
+                None => return Err(BackendError::PathNotFound(path.to_vec())),
+            };
+        }
+
+        match node_id {
+            Some(node_id) => Ok(node_id),
+            None => Err(BackendError::NotFound),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+    use crate::user_management;
+
+    fn directory(name: &str) -> NewNodePayload {
+        NewNodePayload {
+            name: String::from(name),
+            is_directory: true,
+            content: None,
+        }
+    }
+
+    #[test]
+    fn it_resolves_the_first_part_of_a_path_among_the_root_nodes(
+    ) -> BackendResult<()> {
+        let pool = database::create_test_pool()?;
+        let conn = pool.get()?;
+        database::run_migrations(&conn)?;
+        let alice = user_management::provision(&conn, "alice")?;
+        let projects = vec![String::from("Projects")];
+        let archive = vec![String::from("Archive")];
+
+        Node::insert(&conn, &alice.id, &[], &directory("Projects"))?;
+        Node::insert(&conn, &alice.id, &projects, &directory("Archive"))?;
+        assert!(matches!(
+            Node::fetch_by_path_for_user(&conn, &alice.id, &archive),
+            Err(BackendError::PathNotFound(_))
+        ));
+
+        // A root node with the name of a nested one is inserted at the root.
+        let root = Node::insert(&conn, &alice.id, &[], &directory("Archive"))?;
+        assert_eq!(root.parent_id, None);
+        let node = Node::fetch_by_path_for_user(&conn, &alice.id, &archive)?;
+        assert_eq!(node.node_id, root.node_id);
+
+        Ok(())
+    }
+}
@@ -1,3 +1,27 @@
+table! {
+    access_tokens (id) {
+        id -> Integer,
+        user_id -> Integer,
+        token_name -> Text,
+        token_hash -> Text,
+        scopes -> Text,
+        created_at -> BigInt,
+        last_used_at -> Nullable<BigInt>,
+        expires_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    login_lockouts (id) {
+        id -> Integer,
+        lockout_kind -> Text,
+        lockout_key -> Text,
+        failed_attempts -> Integer,
+        last_failed_at -> BigInt,
+        locked_until -> Nullable<BigInt>,
+    }
+}
+
 table! {
     nodes (node_id) {
         node_id -> Integer,
@@ -18,7 +42,11 @@ table! {
     }
 }
 
+joinable!(access_tokens -> users (user_id));
+
 allow_tables_to_appear_in_same_query!(
+    access_tokens,
+    login_lockouts,
     nodes,
     users,
 );
@@ -1,10 +1,9 @@
 use diesel::prelude::*;
-use serde::Serialize;
 
 use super::schema::users;
 use crate::{BackendResult, DbConnection};
 
-pub type UserId = i32;
+pub use api_types::{User, UserId};
 
 #[derive(Insertable)]
 #[table_name = "users"]
@@ -13,18 +12,23 @@ pub struct NewUser<'a> {
     pub password_hash: &'a str,
 }
 
-#[derive(Queryable, Serialize)]
-pub struct User {
-    pub id: UserId,
-    pub username: String,
-    #[serde(skip)]
-    pub password_hash: String,
+/// The queries of the users table. `User` is shared with the clients in the
+/// api-types crate, so they are implemented through this trait.
+pub trait UserQueries {
+    /// Loads a user by the associated user id. Returns
+    /// `BackendError::NotFound` if the user does not exist.
+    fn load_by_id(conn: &DbConnection, id: UserId) -> BackendResult<User>;
+
+    /// Loads a user by the associated username. Returns
+    /// `BackendError::NotFound` if the user does not exist.
+    fn load_by_username(
+        conn: &DbConnection,
+        username: &str,
+    ) -> BackendResult<User>;
 }
 
-impl User {
-    // Loads a user by the associated user id. Returns `BackendError::NotFound`
-    // if the user does not exist.
-    pub fn load_by_id(conn: &DbConnection, id: UserId) -> BackendResult<User> {
+impl UserQueries for User {
+    fn load_by_id(conn: &DbConnection, id: UserId) -> BackendResult<User> {
         // diesel::result::Error::NotFound will be converted into
         // BackendError::NotFound through the From trait implementation
         // on BackendError.
@@ -32,9 +36,7 @@ impl User {
         Ok(user)
     }
 
-    // Loads a user by the associated username. Returns `BackendError::NotFound`
-    // if the user does not exist.
-    pub fn load_by_username(
+    fn load_by_username(
         conn: &DbConnection,
         username: &str,
     ) -> BackendResult<User> {
@@ -0,0 +1,249 @@
+//! Hashing and verification of user passwords as well as the password policy.
+//!
+//! New hashes are created with Argon2id by default. Bcrypt hashes created by
+//! earlier versions are still verified and upgraded by
+//! `user_management::check_user` once the user logs in.
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::{BackendError, BackendResult};
+
+/// Lowercased list of commonly used passwords, one per line, that are rejected
+/// by the password policy.
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// The length in bytes of the random salt used for Argon2id hashes.
+const SALT_LENGTH: usize = 16;
+
+/// The length in bytes of Argon2id hashes.
+const ARGON2_HASH_LENGTH: u32 = 32;
+
+/// Stored instead of a hash for users that can not log in with a password,
+/// like users provisioned by a trusted proxy. No password matches it.
+pub const UNUSABLE_HASH: &str = "!";
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Argon2id,
+    Bcrypt,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordConfig {
+    /// The algorithm used for new password hashes.
+    pub algorithm: HashAlgorithm,
+    /// Memory usage of Argon2id in KiB.
+    pub argon2_memory_kib: u32,
+    /// Number of Argon2id iterations.
+    pub argon2_iterations: u32,
+    /// Degree of parallelism of Argon2id.
+    pub argon2_parallelism: u32,
+    /// The cost of bcrypt, only used if `algorithm` is `Bcrypt`.
+    pub bcrypt_cost: u32,
+    /// Passwords shorter than this many characters are rejected.
+    pub min_length: usize,
+    /// Whether passwords from the bundled list of common passwords are
+    /// rejected.
+    pub reject_common: bool,
+}
+
+impl Default for PasswordConfig {
+    /// The Argon2id parameters follow the recommendation of the OWASP
+    /// password storage cheat sheet.
+    fn default() -> Self {
+        PasswordConfig {
+            algorithm: HashAlgorithm::Argon2id,
+            argon2_memory_kib: 19 * 1024,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+            bcrypt_cost: bcrypt::DEFAULT_COST,
+            min_length: 8,
+            reject_common: true,
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = BackendError;
+
+    fn from_str(algorithm: &str) -> BackendResult<HashAlgorithm> {
+        match algorithm {
+            "argon2id" => Ok(HashAlgorithm::Argon2id),
+            "bcrypt" => Ok(HashAlgorithm::Bcrypt),
+            _ => Err(BackendError::InvalidConfig(format!(
+                "Invalid password algorithm {}, expected argon2id or bcrypt",
+                algorithm
+            ))),
+        }
+    }
+}
+
+impl PasswordConfig {
+    fn argon2_config(&self) -> argon2::Config<'static> {
+        argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+            mem_cost: self.argon2_memory_kib,
+            time_cost: self.argon2_iterations,
+            lanes: self.argon2_parallelism,
+            thread_mode: argon2::ThreadMode::from_threads(
+                self.argon2_parallelism,
+            ),
+            secret: &[],
+            ad: &[],
+            hash_length: ARGON2_HASH_LENGTH,
+        }
+    }
+}
+
+/// Checks the given password against the password policy. Returns
+/// `BackendError::WeakPassword` with the reason if the password is rejected.
+pub fn check_policy(password: &str, cfg: &PasswordConfig) -> BackendResult<()> {
+    if password.chars().count() < cfg.min_length {
+        return Err(BackendError::WeakPassword(format!(
+            "The password must have at least {} characters",
+            cfg.min_length
+        )));
+    }
+
+    if cfg.reject_common {
+        let lowercase = password.to_lowercase();
+        if COMMON_PASSWORDS.lines().any(|common| common == lowercase) {
+            return Err(BackendError::WeakPassword(String::from(
+                "The password is too common",
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Hashes the given password with the configured algorithm.
+pub fn hash(password: &str, cfg: &PasswordConfig) -> BackendResult<String> {
+    match cfg.algorithm {
+        HashAlgorithm::Argon2id => {
+            let mut salt = [0u8; SALT_LENGTH];
+            SystemRandom::new()
+                .fill(&mut salt)
+                .map_err(|_| BackendError::SaltGeneration)?;
+            let hash = argon2::hash_encoded(
+                password.as_bytes(),
+                &salt,
+                &cfg.argon2_config(),
+            )?;
+            Ok(hash)
+        }
+        HashAlgorithm::Bcrypt => Ok(bcrypt::hash(password, cfg.bcrypt_cost)?),
+    }
+}
+
+/// Verifies the password against the given hash, which can be either an
+/// Argon2id or a bcrypt hash. `UNUSABLE_HASH` takes as long as a hash with the
+/// configured parameters, so that the response time does not reveal which
+/// users can not log in with a password.
+pub fn verify(
+    password: &str,
+    hash: &str,
+    cfg: &PasswordConfig,
+) -> BackendResult<bool> {
+    if hash == UNUSABLE_HASH {
+        dummy_verify(password, cfg);
+        Ok(false)
+    } else if hash.starts_with("$argon2") {
+        Ok(argon2::verify_encoded(hash, password.as_bytes())?)
+    } else {
+        Ok(bcrypt::verify(password, hash)?)
+    }
+}
+
+/// Does the same work as verifying a password against a hash created with the
+/// configured algorithm and parameters, without returning anything. Used if a
+/// user does not exist, so that the response time does not reveal which
+/// usernames exist.
+pub fn dummy_verify(password: &str, cfg: &PasswordConfig) {
+    // Hashing costs as much as verifying against a hash with the same
+    // parameters.
+    let _ = hash(password, cfg);
+}
+
+/// Whether the given hash was created with another algorithm or other
+/// parameters than configured and should be replaced with a new hash.
+/// `UNUSABLE_HASH` is never replaced.
+pub fn needs_rehash(hash: &str, cfg: &PasswordConfig) -> bool {
+    if hash == UNUSABLE_HASH {
+        return false;
+    }
+    match cfg.algorithm {
+        HashAlgorithm::Argon2id => {
+            let expected = format!(
+                "$argon2id$v=19$m={},t={},p={}$",
+                cfg.argon2_memory_kib,
+                cfg.argon2_iterations,
+                cfg.argon2_parallelism
+            );
+            !hash.starts_with(&expected)
+        }
+        HashAlgorithm::Bcrypt => {
+            // Bcrypt hashes look like $2b$12$..., where 12 is the cost.
+            let cost = hash.split('$').nth(2).and_then(|c| c.parse().ok());
+            !hash.starts_with("$2") || cost != Some(cfg.bcrypt_cost)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cheap_config() -> PasswordConfig {
+        PasswordConfig {
+            argon2_memory_kib: 64,
+            argon2_iterations: 1,
+            bcrypt_cost: 4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_upgrades_bcrypt_hashes() -> BackendResult<()> {
+        let cfg = cheap_config();
+        let bcrypt_hash = hash(
+            "correct horse battery staple",
+            &PasswordConfig {
+                algorithm: HashAlgorithm::Bcrypt,
+                ..cheap_config()
+            },
+        )?;
+        assert!(verify("correct horse battery staple", &bcrypt_hash, &cfg)?);
+        assert!(needs_rehash(&bcrypt_hash, &cfg));
+
+        let argon2_hash = hash("correct horse battery staple", &cfg)?;
+        assert!(verify("correct horse battery staple", &argon2_hash, &cfg)?);
+        assert!(!verify("wrong", &argon2_hash, &cfg)?);
+        assert!(!verify("", UNUSABLE_HASH, &cfg)?);
+        assert!(!needs_rehash(UNUSABLE_HASH, &cfg));
+        assert!(!needs_rehash(&argon2_hash, &cfg));
+        assert!(needs_rehash(
+            &argon2_hash,
+            &PasswordConfig {
+                argon2_iterations: 2,
+                ..cfg
+            }
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_enforces_the_policy() {
+        let cfg = PasswordConfig::default();
+
+        assert!(check_policy("short", &cfg).is_err());
+        assert!(check_policy("Password123", &cfg).is_err());
+        assert!(check_policy("correct horse battery staple", &cfg).is_ok());
+    }
+}
@@ -0,0 +1,46 @@
+//! Authentication by a trusted reverse proxy, e.g. a single sign-on proxy in
+//! front of the load balancer, that passes the name of the authenticated user
+//! in a header.
+
+use rocket::Request;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyAuthConfig {
+    /// The header containing the username, e.g. `X-Remote-User`. Proxy
+    /// authentication is disabled if this is `None`.
+    pub header: Option<String>,
+    /// The header is only trusted in requests from these addresses. Must be
+    /// the address of the proxy directly connecting to the backend.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl ProxyAuthConfig {
+    /// Returns the username passed by a trusted proxy or `None` if proxy
+    /// authentication is disabled, the request does not come from a trusted
+    /// proxy or the header is not set exactly once.
+    pub fn username<'r>(&self, req: &'r Request) -> Option<&'r str> {
+        let header = self.header.as_ref()?;
+        let values: Vec<_> = req.headers().get(header).collect();
+        if values.is_empty() {
+            return None;
+        }
+
+        // Use the address of the connection. In contrast to client_ip() this
+        // can not be forged with a X-Real-IP header.
+        match req.remote() {
+            Some(remote) if self.trusted_proxies.contains(&remote.ip()) => (),
+            _ => {
+                log::warn!("Ignoring {} from untrusted {}", header, req);
+                return None;
+            }
+        }
+
+        match values.as_slice() {
+            [username] if !username.trim().is_empty() => Some(username.trim()),
+            _ => None,
+        }
+    }
+}
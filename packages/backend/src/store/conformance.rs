@@ -0,0 +1,718 @@
+//! The tests every `NodeStore` has to pass. Errors are compared by their code,
+//! since the database reports some of them as diesel errors.
+
+use super::{NodeChange, NodeRef, NodeStore};
+use crate::models::{NewNodePayload, Node, OwnedPath, UserId};
+use crate::BackendResult;
+
+fn path(parts: &[&str]) -> OwnedPath {
+    parts.iter().map(|part| String::from(*part)).collect()
+}
+
+fn directory(name: &str) -> NewNodePayload {
+    NewNodePayload {
+        name: String::from(name),
+        is_directory: true,
+        content: None,
+    }
+}
+
+fn file(name: &str, content: &str) -> NewNodePayload {
+    NewNodePayload {
+        name: String::from(name),
+        is_directory: false,
+        content: Some(String::from(content)),
+    }
+}
+
+fn names(nodes: &[Node]) -> Vec<&str> {
+    nodes.iter().map(|node| node.node_name.as_str()).collect()
+}
+
+fn assert_code<T: std::fmt::Debug>(result: BackendResult<T>, code: &str) {
+    match result {
+        Ok(value) => panic!("expected {}, got {:?}", code, value),
+        Err(err) => assert_eq!(err.code(), code, "{}", err),
+    }
+}
+
+/// Runs every test against a new store returned by `new_store`, together with
+/// the ids of two users that exist in the store.
+pub fn run<S, F>(new_store: F)
+where
+    S: NodeStore,
+    F: Fn() -> (S, UserId, UserId),
+{
+    let tests: &[fn(&dyn NodeStore, UserId, UserId)] = &[
+        inserts_and_fetches_nodes,
+        lists_nodes_by_name,
+        rejects_invalid_inserts,
+        changes_the_content,
+        renames_nodes,
+        moves_nodes,
+        rejects_invalid_moves,
+        changes_nodes_at_once,
+        deletes_recursively,
+        separates_users,
+        deletes_all_nodes_of_a_user,
+        addresses_nodes_by_id,
+    ];
+    for test in tests {
+        let (store, alice, bob) = new_store();
+        test(&store, alice, bob);
+    }
+}
+
+fn inserts_and_fetches_nodes(store: &dyn NodeStore, alice: UserId, _: UserId) {
+    let projects = store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+    assert!(projects.is_directory);
+    assert_eq!(projects.parent_id, None);
+    assert_eq!(projects.owner_id, alice);
+    assert_eq!(projects.content, None);
+
+    let todo = store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            &file("Todo", "- [ ] store"),
+        )
+        .unwrap();
+    assert!(!todo.is_directory);
+    assert_eq!(todo.parent_id, Some(projects.node_id));
+    assert_eq!(todo.parent_is_directory, Some(true));
+    assert_eq!(todo.content.as_deref(), Some("- [ ] store"));
+
+    let fetched = store
+        .fetch(alice, NodeRef::Path(&path(&["Projects", "Todo"])))
+        .unwrap();
+    assert_eq!(fetched, todo);
+    assert_eq!(
+        store
+            .fetch(alice, NodeRef::Path(&path(&["Projects"])))
+            .unwrap(),
+        projects
+    );
+
+    let mut all = store.fetch_all(alice).unwrap();
+    all.sort_by_key(|node| node.node_id);
+    assert_eq!(all, vec![projects, todo]);
+
+    assert_code(
+        store.fetch(alice, NodeRef::Path(&path(&["Todo"]))),
+        "not_found",
+    );
+    assert_code(
+        store.fetch(alice, NodeRef::Path(&path(&["Projects", "Ideas"]))),
+        "not_found",
+    );
+    assert_code(store.fetch(alice, NodeRef::ROOT), "not_found");
+}
+
+fn lists_nodes_by_name(store: &dyn NodeStore, alice: UserId, _: UserId) {
+    assert!(store.list(alice, NodeRef::ROOT).unwrap().is_empty());
+    store.insert(alice, NodeRef::ROOT, &directory("b")).unwrap();
+    store.insert(alice, NodeRef::ROOT, &file("c", "")).unwrap();
+    store.insert(alice, NodeRef::ROOT, &directory("a")).unwrap();
+    store
+        .insert(alice, NodeRef::Path(&path(&["b"])), &file("y", ""))
+        .unwrap();
+    store
+        .insert(alice, NodeRef::Path(&path(&["b"])), &file("x", ""))
+        .unwrap();
+
+    assert_eq!(
+        names(&store.list(alice, NodeRef::ROOT).unwrap()),
+        vec!["a", "b", "c"]
+    );
+    assert_eq!(
+        names(&store.list(alice, NodeRef::Path(&path(&["b"]))).unwrap()),
+        vec!["x", "y"]
+    );
+    assert!(store
+        .list(alice, NodeRef::Path(&path(&["a"])))
+        .unwrap()
+        .is_empty());
+
+    assert_code(
+        store.list(alice, NodeRef::Path(&path(&["c"]))),
+        "invalid_value",
+    );
+    assert_code(store.list(alice, NodeRef::Path(&path(&["d"]))), "not_found");
+}
+
+fn rejects_invalid_inserts(store: &dyn NodeStore, alice: UserId, _: UserId) {
+    store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+    store
+        .insert(alice, NodeRef::ROOT, &file("Todo", ""))
+        .unwrap();
+
+    assert_code(
+        store.insert(alice, NodeRef::ROOT, &directory("Projects")),
+        "name_conflict",
+    );
+    assert_code(
+        store.insert(alice, NodeRef::ROOT, &file("Projects", "")),
+        "name_conflict",
+    );
+    assert_code(
+        store.insert(alice, NodeRef::ROOT, &directory("a/b")),
+        "node_name_invalid",
+    );
+    // Names of hidden entries on disk are rejected by every store.
+    assert_code(
+        store.insert(alice, NodeRef::ROOT, &file(".notes", "")),
+        "node_name_invalid",
+    );
+    assert_code(
+        store.insert(alice, NodeRef::ROOT, &directory("")),
+        "node_name_invalid",
+    );
+    let no_content = NewNodePayload {
+        name: String::from("Ideas"),
+        is_directory: false,
+        content: None,
+    };
+    assert_code(
+        store.insert(alice, NodeRef::ROOT, &no_content),
+        "invalid_value",
+    );
+    assert_code(
+        store.insert(
+            alice,
+            NodeRef::Path(&path(&["Todo"])),
+            &file("Ideas", ""),
+        ),
+        "invalid_value",
+    );
+    assert_code(
+        store.insert(
+            alice,
+            NodeRef::Path(&path(&["Missing"])),
+            &file("Ideas", ""),
+        ),
+        "not_found",
+    );
+
+    // The same name is fine in another directory.
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            &directory("Projects"),
+        )
+        .unwrap();
+    assert_eq!(store.fetch_all(alice).unwrap().len(), 3);
+}
+
+fn changes_the_content(store: &dyn NodeStore, alice: UserId, _: UserId) {
+    store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            &file("Todo", "old"),
+        )
+        .unwrap();
+    let todo = path(&["Projects", "Todo"]);
+
+    let changed = store
+        .change_content(alice, NodeRef::Path(&todo), "new")
+        .unwrap();
+    assert_eq!(changed.content.as_deref(), Some("new"));
+    assert_eq!(store.fetch(alice, NodeRef::Path(&todo)).unwrap(), changed);
+
+    assert_code(
+        store.change_content(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            "text",
+        ),
+        "invalid_value",
+    );
+    assert_code(
+        store.change_content(alice, NodeRef::Path(&path(&["Ideas"])), "text"),
+        "not_found",
+    );
+}
+
+fn renames_nodes(store: &dyn NodeStore, alice: UserId, _: UserId) {
+    store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            &file("Todo", ""),
+        )
+        .unwrap();
+    store
+        .insert(alice, NodeRef::ROOT, &file("Ideas", ""))
+        .unwrap();
+
+    let renamed = store
+        .rename(alice, NodeRef::Path(&path(&["Projects"])), "Work")
+        .unwrap();
+    assert_eq!(renamed.node_name, "Work");
+    store
+        .fetch(alice, NodeRef::Path(&path(&["Work", "Todo"])))
+        .unwrap();
+    assert_code(
+        store.fetch(alice, NodeRef::Path(&path(&["Projects"]))),
+        "not_found",
+    );
+
+    // Keeping the name is not a conflict.
+    store
+        .rename(alice, NodeRef::Path(&path(&["Ideas"])), "Ideas")
+        .unwrap();
+    assert_code(
+        store.rename(alice, NodeRef::Path(&path(&["Ideas"])), "Work"),
+        "name_conflict",
+    );
+    assert_code(
+        store.rename(alice, NodeRef::Path(&path(&["Ideas"])), "a:b"),
+        "node_name_invalid",
+    );
+    assert_code(
+        store.rename(alice, NodeRef::Path(&path(&["Ideas"])), ".."),
+        "node_name_invalid",
+    );
+    assert_code(
+        store.rename(alice, NodeRef::Path(&path(&["Missing"])), "New"),
+        "not_found",
+    );
+}
+
+fn moves_nodes(store: &dyn NodeStore, alice: UserId, _: UserId) {
+    let projects = store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+    store
+        .insert(alice, NodeRef::ROOT, &directory("Archive"))
+        .unwrap();
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            &directory("Old"),
+        )
+        .unwrap();
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects", "Old"])),
+            &file("Todo", ""),
+        )
+        .unwrap();
+
+    let moved = store
+        .move_node(
+            alice,
+            NodeRef::Path(&path(&["Projects", "Old"])),
+            NodeRef::Path(&path(&["Archive"])),
+        )
+        .unwrap();
+    assert_eq!(moved.node_name, "Old");
+    store
+        .fetch(alice, NodeRef::Path(&path(&["Archive", "Old", "Todo"])))
+        .unwrap();
+    assert!(store
+        .list(alice, NodeRef::Path(&path(&["Projects"])))
+        .unwrap()
+        .is_empty());
+
+    let moved = store
+        .move_node(
+            alice,
+            NodeRef::Path(&path(&["Archive", "Old"])),
+            NodeRef::ROOT,
+        )
+        .unwrap();
+    assert_eq!(moved.parent_id, None);
+    assert_eq!(
+        names(&store.list(alice, NodeRef::ROOT).unwrap()),
+        vec!["Archive", "Old", "Projects"]
+    );
+
+    let moved = store
+        .move_node(
+            alice,
+            NodeRef::Path(&path(&["Old"])),
+            NodeRef::Path(&path(&["Projects"])),
+        )
+        .unwrap();
+    assert_eq!(moved.parent_id, Some(projects.node_id));
+}
+
+fn rejects_invalid_moves(store: &dyn NodeStore, alice: UserId, _: UserId) {
+    store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            &directory("Old"),
+        )
+        .unwrap();
+    store
+        .insert(alice, NodeRef::ROOT, &file("Todo", ""))
+        .unwrap();
+    store
+        .insert(alice, NodeRef::ROOT, &directory("Old"))
+        .unwrap();
+
+    assert_code(
+        store.move_node(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            NodeRef::Path(&path(&["Projects"])),
+        ),
+        "invalid_value",
+    );
+    assert_code(
+        store.move_node(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            NodeRef::Path(&path(&["Projects", "Old"])),
+        ),
+        "invalid_value",
+    );
+    assert_code(
+        store.move_node(
+            alice,
+            NodeRef::Path(&path(&["Old"])),
+            NodeRef::Path(&path(&["Todo"])),
+        ),
+        "invalid_value",
+    );
+    assert_code(
+        store.move_node(
+            alice,
+            NodeRef::Path(&path(&["Old"])),
+            NodeRef::Path(&path(&["Projects"])),
+        ),
+        "name_conflict",
+    );
+    assert_code(
+        store.move_node(
+            alice,
+            NodeRef::Path(&path(&["Old"])),
+            NodeRef::Path(&path(&["Missing"])),
+        ),
+        "not_found",
+    );
+    assert_code(
+        store.move_node(
+            alice,
+            NodeRef::Path(&path(&["Missing"])),
+            NodeRef::ROOT,
+        ),
+        "not_found",
+    );
+    assert_eq!(store.fetch_all(alice).unwrap().len(), 4);
+}
+
+fn changes_nodes_at_once(store: &dyn NodeStore, alice: UserId, _: UserId) {
+    for name in &["Projects", "Archive"] {
+        store
+            .insert(alice, NodeRef::ROOT, &directory(name))
+            .unwrap();
+    }
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            &file("Todo", "- [ ]"),
+        )
+        .unwrap();
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            &file("Notes", ""),
+        )
+        .unwrap();
+
+    let changed = store
+        .change(
+            alice,
+            NodeRef::Path(&path(&["Projects", "Todo"])),
+            &NodeChange {
+                name: Some("Done"),
+                parent: Some(NodeRef::Path(&path(&["Archive"]))),
+                content: Some("- [x]"),
+            },
+        )
+        .unwrap();
+    assert_eq!(changed.node_name, "Done");
+    assert_eq!(changed.content.as_deref(), Some("- [x]"));
+    assert_eq!(
+        store
+            .fetch(alice, NodeRef::Path(&path(&["Archive", "Done"])))
+            .unwrap(),
+        changed
+    );
+
+    // The move fails after the rename, so the rename is not applied either.
+    let done = NodeRef::Id(changed.node_id);
+    assert_code(
+        store.change(
+            alice,
+            done,
+            &NodeChange {
+                name: Some("Notes"),
+                parent: Some(NodeRef::Path(&path(&["Projects"]))),
+                ..NodeChange::default()
+            },
+        ),
+        "name_conflict",
+    );
+    assert_code(
+        store.change(
+            alice,
+            done,
+            &NodeChange {
+                name: Some("Todo"),
+                parent: Some(NodeRef::Path(&path(&["Projects", "Notes"]))),
+                content: Some("- [ ]"),
+            },
+        ),
+        "invalid_value",
+    );
+    // Directories have no content.
+    assert_code(
+        store.change(
+            alice,
+            NodeRef::Path(&path(&["Archive"])),
+            &NodeChange {
+                name: Some("Old"),
+                content: Some(""),
+                ..NodeChange::default()
+            },
+        ),
+        "invalid_value",
+    );
+    assert_eq!(store.fetch(alice, done).unwrap(), changed);
+    assert_eq!(
+        names(&store.list(alice, NodeRef::ROOT).unwrap()),
+        vec!["Archive", "Projects"]
+    );
+}
+
+fn deletes_recursively(store: &dyn NodeStore, alice: UserId, _: UserId) {
+    store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            &directory("Old"),
+        )
+        .unwrap();
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects", "Old"])),
+            &file("Todo", ""),
+        )
+        .unwrap();
+    store
+        .insert(alice, NodeRef::ROOT, &file("Ideas", ""))
+        .unwrap();
+
+    store
+        .delete(alice, NodeRef::Path(&path(&["Projects"])))
+        .unwrap();
+    assert_eq!(names(&store.fetch_all(alice).unwrap()), vec!["Ideas"]);
+    assert_code(
+        store.delete(alice, NodeRef::Path(&path(&["Projects"]))),
+        "not_found",
+    );
+
+    // The name can be used again.
+    store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+}
+
+fn separates_users(store: &dyn NodeStore, alice: UserId, bob: UserId) {
+    store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            &file("Todo", "alice"),
+        )
+        .unwrap();
+
+    assert!(store.fetch_all(bob).unwrap().is_empty());
+    assert!(store.list(bob, NodeRef::ROOT).unwrap().is_empty());
+    assert_code(
+        store.fetch(bob, NodeRef::Path(&path(&["Projects"]))),
+        "not_found",
+    );
+    assert_code(
+        store.insert(
+            bob,
+            NodeRef::Path(&path(&["Projects"])),
+            &file("Todo", "bob"),
+        ),
+        "not_found",
+    );
+    assert_code(
+        store.change_content(
+            bob,
+            NodeRef::Path(&path(&["Projects", "Todo"])),
+            "bob",
+        ),
+        "not_found",
+    );
+    assert_code(
+        store.delete(bob, NodeRef::Path(&path(&["Projects"]))),
+        "not_found",
+    );
+
+    // Both users can have root nodes with the same name.
+    store
+        .insert(bob, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+    store
+        .insert(
+            bob,
+            NodeRef::Path(&path(&["Projects"])),
+            &file("Todo", "bob"),
+        )
+        .unwrap();
+    let todo = path(&["Projects", "Todo"]);
+    assert_eq!(
+        store
+            .fetch(alice, NodeRef::Path(&todo))
+            .unwrap()
+            .content
+            .as_deref(),
+        Some("alice")
+    );
+    assert_eq!(
+        store
+            .fetch(bob, NodeRef::Path(&todo))
+            .unwrap()
+            .content
+            .as_deref(),
+        Some("bob")
+    );
+}
+
+fn deletes_all_nodes_of_a_user(
+    store: &dyn NodeStore,
+    alice: UserId,
+    bob: UserId,
+) {
+    store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+    store
+        .insert(
+            alice,
+            NodeRef::Path(&path(&["Projects"])),
+            &file("Todo", ""),
+        )
+        .unwrap();
+    store
+        .insert(bob, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+
+    store.delete_all(alice).unwrap();
+    assert!(store.fetch_all(alice).unwrap().is_empty());
+    assert_eq!(names(&store.fetch_all(bob).unwrap()), vec!["Projects"]);
+    // Users without nodes can be deleted too.
+    store.delete_all(alice).unwrap();
+
+    store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+}
+
+fn addresses_nodes_by_id(store: &dyn NodeStore, alice: UserId, bob: UserId) {
+    let projects = store
+        .insert(alice, NodeRef::ROOT, &directory("Projects"))
+        .unwrap();
+    let todo = store
+        .insert(alice, NodeRef::Id(projects.node_id), &file("Todo", "old"))
+        .unwrap();
+    let archive = store
+        .insert(alice, NodeRef::ROOT, &directory("Archive"))
+        .unwrap();
+    assert_eq!(todo.parent_id, Some(projects.node_id));
+
+    assert_eq!(store.fetch(alice, NodeRef::Id(todo.node_id)).unwrap(), todo);
+    assert_eq!(
+        store.path(alice, todo.node_id).unwrap(),
+        path(&["Projects", "Todo"])
+    );
+    assert_eq!(
+        names(&store.list(alice, NodeRef::Id(projects.node_id)).unwrap()),
+        vec!["Todo"]
+    );
+
+    let changed = store
+        .change_content(alice, NodeRef::Id(todo.node_id), "new")
+        .unwrap();
+    assert_eq!(changed.content.as_deref(), Some("new"));
+    store
+        .rename(alice, NodeRef::Id(projects.node_id), "Work")
+        .unwrap();
+    let moved = store
+        .move_node(
+            alice,
+            NodeRef::Id(todo.node_id),
+            NodeRef::Id(archive.node_id),
+        )
+        .unwrap();
+    assert_eq!(moved.node_id, todo.node_id);
+    assert_eq!(moved.parent_id, Some(archive.node_id));
+    assert_eq!(
+        store.path(alice, todo.node_id).unwrap(),
+        path(&["Archive", "Todo"])
+    );
+
+    assert_code(
+        store.insert(alice, NodeRef::Id(todo.node_id), &file("Ideas", "")),
+        "invalid_value",
+    );
+    assert_code(
+        store.list(alice, NodeRef::Id(todo.node_id)),
+        "invalid_value",
+    );
+    assert_code(
+        store.move_node(
+            alice,
+            NodeRef::Id(archive.node_id),
+            NodeRef::Id(archive.node_id),
+        ),
+        "invalid_value",
+    );
+
+    // The nodes of other users are not found by their ids.
+    assert_code(store.fetch(bob, NodeRef::Id(todo.node_id)), "not_found");
+    assert_code(store.path(bob, todo.node_id), "not_found");
+    assert_code(
+        store.insert(bob, NodeRef::Id(archive.node_id), &file("Ideas", "")),
+        "not_found",
+    );
+    assert_code(store.delete(bob, NodeRef::Id(todo.node_id)), "not_found");
+
+    store.delete(alice, NodeRef::Id(archive.node_id)).unwrap();
+    assert_code(store.fetch(alice, NodeRef::Id(todo.node_id)), "not_found");
+    assert_code(store.path(alice, todo.node_id), "not_found");
+}
@@ -0,0 +1,210 @@
+use diesel::prelude::*;
+
+use super::{NodeChange, NodeRef, NodeStore};
+use crate::database::{retry_on_busy, DbReadPool};
+use crate::models::schema::nodes;
+use crate::models::{
+    NewNodePayload, Node, NodeId, NodeQueries, OwnedPath, UserId,
+};
+use crate::{BackendError, BackendResult, DbConnection, DbConnectionPool};
+
+/// Stores the nodes in the database. Reads use the read pool, writes the pool
+/// of the single writer connection and are retried while sqlite is busy.
+pub struct DieselNodeStore {
+    pool: DbConnectionPool,
+    read_pool: DbReadPool,
+}
+
+impl DieselNodeStore {
+    pub fn new(pool: DbConnectionPool, read_pool: DbReadPool) -> Self {
+        DieselNodeStore { pool, read_pool }
+    }
+}
+
+fn fetch(
+    conn: &DbConnection,
+    owner_id: UserId,
+    node: NodeRef,
+) -> BackendResult<Node> {
+    match node {
+        NodeRef::Path(path) => {
+            Node::fetch_by_path_for_user(conn, &owner_id, path)
+        }
+        NodeRef::Id(node_id) => {
+            Node::fetch_by_id_for_user(conn, &owner_id, node_id)
+        }
+    }
+}
+
+/// Fetches the directory, or returns `None` for `NodeRef::ROOT`. Returns
+/// `BackendError::InvalidValue` if the node is a file.
+fn fetch_directory(
+    conn: &DbConnection,
+    owner_id: UserId,
+    directory: NodeRef,
+) -> BackendResult<Option<Node>> {
+    if let NodeRef::Path([]) = directory {
+        return Ok(None);
+    }
+    let node = fetch(conn, owner_id, directory)?;
+    if !node.is_directory {
+        return Err(BackendError::InvalidValue);
+    }
+    Ok(Some(node))
+}
+
+/// Whether the node with the id `ancestor_id` is the node with the id
+/// `node_id` or one of its ancestors.
+fn is_ancestor(
+    conn: &DbConnection,
+    ancestor_id: NodeId,
+    node_id: NodeId,
+) -> BackendResult<bool> {
+    let mut current = Some(node_id);
+    while let Some(id) = current {
+        if id == ancestor_id {
+            return Ok(true);
+        }
+        current = nodes::table
+            .find(id)
+            .select(nodes::parent_id)
+            .first::<Option<NodeId>>(conn)?;
+    }
+    Ok(false)
+}
+
+impl NodeStore for DieselNodeStore {
+    fn fetch_all(&self, owner_id: UserId) -> BackendResult<Vec<Node>> {
+        let conn = self.read_pool.get()?;
+        Node::fetch_all_for_user(&conn, &owner_id)
+    }
+
+    fn list(
+        &self,
+        owner_id: UserId,
+        directory: NodeRef,
+    ) -> BackendResult<Vec<Node>> {
+        let conn = self.read_pool.get()?;
+        conn.transaction(|| {
+            let parent = fetch_directory(&conn, owner_id, directory)?;
+            let parent_id = parent.map(|parent| parent.node_id);
+            Node::fetch_children_for_user(&conn, &owner_id, parent_id)
+        })
+    }
+
+    fn fetch(&self, owner_id: UserId, node: NodeRef) -> BackendResult<Node> {
+        let conn = self.read_pool.get()?;
+        fetch(&conn, owner_id, node)
+    }
+
+    fn path(
+        &self,
+        owner_id: UserId,
+        node_id: NodeId,
+    ) -> BackendResult<OwnedPath> {
+        let conn = self.read_pool.get()?;
+        conn.transaction(|| {
+            let mut path = Vec::new();
+            let mut current = Some(node_id);
+            while let Some(id) = current {
+                let node = Node::fetch_by_id_for_user(&conn, &owner_id, id)?;
+                current = node.parent_id;
+                path.push(node.node_name);
+            }
+            path.reverse();
+            Ok(path)
+        })
+    }
+
+    fn insert(
+        &self,
+        owner_id: UserId,
+        parent: NodeRef,
+        payload: &NewNodePayload,
+    ) -> BackendResult<Node> {
+        let conn = &self.pool.get()?;
+        retry_on_busy(|| {
+            conn.transaction(|| {
+                // The foreign key would fail with an internal error for a
+                // file.
+                let parent = fetch_directory(conn, owner_id, parent)?;
+                let parent_id = parent.map(|parent| parent.node_id);
+                Node::insert_into(conn, &owner_id, parent_id, payload)
+            })
+        })
+    }
+
+    fn change(
+        &self,
+        owner_id: UserId,
+        node: NodeRef,
+        change: &NodeChange,
+    ) -> BackendResult<Node> {
+        let conn = &self.pool.get()?;
+        retry_on_busy(|| {
+            // The transaction rolls back the changes before a failed one.
+            conn.transaction(|| {
+                let mut result = fetch(conn, owner_id, node)?;
+                if let Some(name) = change.name {
+                    result = result.change_name(conn, name)?;
+                }
+                if let Some(new_parent) = change.parent {
+                    let new_parent =
+                        fetch_directory(conn, owner_id, new_parent)?;
+                    if let Some(parent) = &new_parent {
+                        // The node would no longer be reachable from a root
+                        // node.
+                        if is_ancestor(conn, result.node_id, parent.node_id)? {
+                            return Err(BackendError::InvalidValue);
+                        }
+                    }
+                    result = result.change_parent(conn, new_parent.as_ref())?;
+                }
+                if let Some(content) = change.content {
+                    result = result.change_content(conn, content)?;
+                }
+                Ok(result)
+            })
+        })
+    }
+
+    fn delete(&self, owner_id: UserId, node: NodeRef) -> BackendResult<()> {
+        let conn = &self.pool.get()?;
+        retry_on_busy(|| {
+            conn.transaction(|| fetch(conn, owner_id, node)?.delete(conn))
+        })
+    }
+
+    fn delete_all(&self, owner_id: UserId) -> BackendResult<()> {
+        let conn = &self.pool.get()?;
+        retry_on_busy(|| {
+            diesel::delete(nodes::table.filter(nodes::owner_id.eq(owner_id)))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+    use crate::store::conformance;
+    use crate::user_management;
+
+    #[test]
+    fn it_passes_the_conformance_suite() {
+        conformance::run(|| {
+            let pool = database::create_test_pool().unwrap();
+            let conn = pool.get().unwrap();
+            database::run_migrations(&conn).unwrap();
+            let alice = user_management::provision(&conn, "alice").unwrap();
+            let bob = user_management::provision(&conn, "bob").unwrap();
+            drop(conn);
+
+            let read_pool = DbReadPool::shared(&pool);
+            let store = DieselNodeStore::new(pool, read_pool);
+            (store, alice.id, bob.id)
+        });
+    }
+}
@@ -0,0 +1,674 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use super::{NodeChange, NodeRef, NodeStore};
+use crate::export::NOTE_EXTENSION;
+use crate::models::{NewNodePayload, Node, NodeId, OwnedPath, Path, UserId};
+use crate::{BackendError, BackendResult};
+
+/// The file in the directory of a user that maps the paths of the nodes to
+/// their ids.
+const INDEX_FILE: &str = ".mnstore.json";
+
+/// Stores the nodes as plain files in the layout of `export`, so they can be
+/// read without the backend: the nodes of a user are in `<dir>/<user id>`,
+/// directories are folders and files are `.md` files.
+///
+/// Files are replaced atomically by renaming a written temporary file, and
+/// directories are moved out of the way before they are deleted. The ids of
+/// the nodes are kept in an index per user. Nodes created by other programs
+/// get a new id when they are first read, and ids only survive changes made
+/// through the store.
+///
+/// Hidden entries and files without the `.md` extension are ignored, node
+/// names never start with a `.`. A directory named like a note file, e.g.
+/// `Todo.md`, and the file `Todo` can not be siblings.
+pub struct FsNodeStore {
+    dir: PathBuf,
+    /// Operations run one after another, so no change happens between the
+    /// checks of an operation and its change.
+    lock: Mutex<()>,
+}
+
+impl FsNodeStore {
+    pub fn new(dir: &FsPath) -> BackendResult<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(FsNodeStore {
+            dir: dir.to_path_buf(),
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<()> {
+        self.lock.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn user_dir(&self, owner_id: UserId) -> PathBuf {
+        self.dir.join(owner_id.to_string())
+    }
+
+    fn open(&self, owner_id: UserId) -> BackendResult<Tree> {
+        let dir = self.user_dir(owner_id);
+        let index = match fs::read(dir.join(INDEX_FILE)) {
+            Ok(json) => {
+                serde_json::from_slice(&json).map_err(std::io::Error::from)?
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Index::default(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Tree {
+            owner_id,
+            dir,
+            index,
+            changed: false,
+        })
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Index {
+    next_id: NodeId,
+    /// The ids by the paths of the nodes joined with `/`.
+    ids: BTreeMap<String, NodeId>,
+}
+
+/// The nodes of a user while an operation runs.
+struct Tree {
+    owner_id: UserId,
+    dir: PathBuf,
+    index: Index,
+    /// Whether the index has to be saved.
+    changed: bool,
+}
+
+impl Tree {
+    /// Returns the id of the node at `path` and assigns a new one if the node
+    /// has none yet.
+    fn id(&mut self, path: &Path) -> NodeId {
+        let key = path.join("/");
+        if let Some(id) = self.index.ids.get(&key) {
+            return *id;
+        }
+        self.index.next_id += 1;
+        self.index.ids.insert(key, self.index.next_id);
+        self.changed = true;
+        self.index.next_id
+    }
+
+    /// Moves the ids of the node at `from` and of the nodes below it to `to`.
+    fn move_ids(&mut self, from: &Path, to: &Path) {
+        let moved = self.remove_ids(from);
+        self.remove_ids(to);
+        let (from, to) = (from.join("/"), to.join("/"));
+        for (key, id) in moved {
+            self.index
+                .ids
+                .insert(format!("{}{}", to, &key[from.len()..]), id);
+        }
+    }
+
+    /// Removes and returns the ids of the node at `path` and of the nodes
+    /// below it.
+    fn remove_ids(&mut self, path: &Path) -> Vec<(String, NodeId)> {
+        let key = path.join("/");
+        let prefix = format!("{}/", key);
+        let removed: Vec<(String, NodeId)> = self
+            .index
+            .ids
+            .iter()
+            .filter(|(k, _)| **k == key || k.starts_with(&prefix))
+            .map(|(k, id)| (k.clone(), *id))
+            .collect();
+        for (k, _) in &removed {
+            self.index.ids.remove(k);
+        }
+        self.changed |= !removed.is_empty();
+        removed
+    }
+
+    /// Returns the path of the node. Ids are looked up in the index and fail
+    /// with `BackendError::NotFound` if their node was removed by another
+    /// program.
+    fn resolve(&self, node: NodeRef) -> BackendResult<OwnedPath> {
+        let node_id = match node {
+            NodeRef::Path(path) => return Ok(path.to_vec()),
+            NodeRef::Id(node_id) => node_id,
+        };
+        let key = self
+            .index
+            .ids
+            .iter()
+            .find(|(_, id)| **id == node_id)
+            .map(|(key, _)| key)
+            .ok_or(BackendError::NotFound)?;
+        let path: OwnedPath = key.split('/').map(String::from).collect();
+        self.locate(&path).map_err(|_| BackendError::NotFound)?;
+        Ok(path)
+    }
+
+    /// Returns the location of the node at `path` and whether it is a
+    /// directory.
+    fn locate(&self, path: &Path) -> BackendResult<(PathBuf, bool)> {
+        let not_found = || BackendError::PathNotFound(path.to_vec());
+        let (name, parents) =
+            path.split_last().ok_or(BackendError::NotFound)?;
+        let mut location = self.dir.clone();
+        for parent in parents {
+            location.push(parent);
+            if !Node::is_name_valid(parent) || !location.is_dir() {
+                return Err(not_found());
+            }
+        }
+        if !Node::is_name_valid(name) {
+            return Err(not_found());
+        }
+
+        let directory = location.join(name);
+        if directory.is_dir() {
+            return Ok((directory, true));
+        }
+        let file = location.join(file_name(name));
+        if file.is_file() {
+            return Ok((file, false));
+        }
+        Err(not_found())
+    }
+
+    /// Returns the location of the directory at `path`, or of the root if
+    /// `path` is empty.
+    fn locate_directory(&self, path: &Path) -> BackendResult<PathBuf> {
+        if path.is_empty() {
+            return Ok(self.dir.clone());
+        }
+        match self.locate(path)? {
+            (location, true) => Ok(location),
+            (_, false) => Err(BackendError::InvalidValue),
+        }
+    }
+
+    fn node(
+        &mut self,
+        path: &Path,
+        location: &FsPath,
+        is_directory: bool,
+    ) -> BackendResult<Node> {
+        let parent_id = match path.split_last() {
+            Some((_, parent)) if !parent.is_empty() => Some(self.id(parent)),
+            _ => None,
+        };
+        Ok(Node {
+            node_id: self.id(path),
+            node_name: path.last().cloned().unwrap_or_default(),
+            parent_id,
+            parent_is_directory: parent_id.map(|_| true),
+            owner_id: self.owner_id,
+            is_directory,
+            content: if is_directory {
+                None
+            } else {
+                Some(fs::read_to_string(location)?)
+            },
+        })
+    }
+
+    /// Returns the nodes in the directory at `location`, ordered by name.
+    fn children(
+        &mut self,
+        path: &Path,
+        location: &FsPath,
+    ) -> BackendResult<Vec<Node>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(location)? {
+            let entry = entry?;
+            let entry_name = match entry.file_name().into_string() {
+                Ok(entry_name) => entry_name,
+                Err(_) => continue,
+            };
+            let is_directory = entry.file_type()?.is_dir();
+            let name = if is_directory {
+                entry_name
+            } else if entry_name.ends_with(NOTE_EXTENSION) {
+                let len = entry_name.len() - NOTE_EXTENSION.len();
+                String::from(&entry_name[..len])
+            } else {
+                continue;
+            };
+            if Node::is_name_valid(&name) {
+                entries.push((name, entry.path(), is_directory));
+            }
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut nodes = Vec::with_capacity(entries.len());
+        let mut child_path = path.to_vec();
+        for (name, location, is_directory) in entries {
+            child_path.push(name);
+            nodes.push(self.node(&child_path, &location, is_directory)?);
+            child_path.pop();
+        }
+        Ok(nodes)
+    }
+
+    /// Adds the nodes below the directory at `path` to `nodes`.
+    fn collect(
+        &mut self,
+        path: &mut OwnedPath,
+        location: &FsPath,
+        nodes: &mut Vec<Node>,
+    ) -> BackendResult<()> {
+        for node in self.children(path, location)? {
+            if node.is_directory {
+                path.push(node.node_name.clone());
+                self.collect(path, &location.join(&node.node_name), nodes)?;
+                path.pop();
+            }
+            nodes.push(node);
+        }
+        Ok(())
+    }
+
+    /// Saves the index if it changed.
+    fn save(self) -> BackendResult<()> {
+        if self.changed {
+            fs::create_dir_all(&self.dir)?;
+            let json = serde_json::to_vec_pretty(&self.index)
+                .map_err(std::io::Error::from)?;
+            write_atomically(&self.dir.join(INDEX_FILE), &json)?;
+        }
+        Ok(())
+    }
+}
+
+fn file_name(name: &str) -> String {
+    format!("{}{}", name, NOTE_EXTENSION)
+}
+
+/// Returns where a node named `name` is stored in the directory at `parent`.
+fn entry_location(parent: &FsPath, name: &str, is_directory: bool) -> PathBuf {
+    if is_directory {
+        parent.join(name)
+    } else {
+        parent.join(file_name(name))
+    }
+}
+
+/// Fails with `BackendError::Conflict` if a node named `name` could not be
+/// stored in the directory at `parent`, since another entry than `own` has
+/// the name of its folder or file.
+fn check_free(
+    parent: &FsPath,
+    name: &str,
+    own: Option<&FsPath>,
+) -> BackendResult<()> {
+    let taken = [parent.join(name), parent.join(file_name(name))]
+        .iter()
+        .any(|entry| {
+            Some(entry.as_path()) != own && fs::symlink_metadata(entry).is_ok()
+        });
+    if taken {
+        return Err(BackendError::Conflict);
+    }
+    Ok(())
+}
+
+/// Writes a hidden file next to `file` first, so `file` is never partially
+/// written. The file and the rename are synced to disk before it returns.
+fn write_atomically(file: &FsPath, content: &[u8]) -> BackendResult<()> {
+    let tmp_file = write_temporarily(file, content)?;
+    replace_with(file, &tmp_file)
+}
+
+/// Writes the content into a hidden file next to `file` and returns its path.
+fn write_temporarily(file: &FsPath, content: &[u8]) -> BackendResult<PathBuf> {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_file = file.with_file_name(format!(".{}.mnstore-tmp", name));
+    let mut tmp = fs::File::create(&tmp_file)?;
+    tmp.write_all(content)?;
+    tmp.sync_all()?;
+    Ok(tmp_file)
+}
+
+/// Moves the file written by `write_temporarily` to `file`.
+fn replace_with(file: &FsPath, tmp_file: &FsPath) -> BackendResult<()> {
+    fs::rename(tmp_file, file)?;
+    if let Some(parent) = file.parent() {
+        fs::File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Removes the directory at `location`. Renaming hides the whole directory at
+/// once, even if removing its content fails halfway. What is left over then is
+/// removed before the next directory of the same name is hidden.
+fn remove_directory(location: &FsPath) -> BackendResult<()> {
+    let name = location.file_name().unwrap_or_default().to_string_lossy();
+    let hidden = location.with_file_name(format!(".{}.mnstore-deleted", name));
+    match fs::remove_dir_all(&hidden) {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+        _ => (),
+    }
+    fs::rename(location, &hidden)?;
+    if let Err(err) = fs::remove_dir_all(&hidden) {
+        log::warn!("Failed to remove {}: {}", hidden.display(), err);
+    }
+    Ok(())
+}
+
+fn validate_name(name: &str) -> BackendResult<()> {
+    if !Node::is_name_valid(name) {
+        return Err(BackendError::InvalidNodeName(String::from(name)));
+    }
+    Ok(())
+}
+
+impl NodeStore for FsNodeStore {
+    fn fetch_all(&self, owner_id: UserId) -> BackendResult<Vec<Node>> {
+        let _guard = self.lock();
+        let mut tree = self.open(owner_id)?;
+        let mut nodes = Vec::new();
+        if tree.dir.is_dir() {
+            let dir = tree.dir.clone();
+            tree.collect(&mut Vec::new(), &dir, &mut nodes)?;
+        }
+        tree.save()?;
+        Ok(nodes)
+    }
+
+    fn list(
+        &self,
+        owner_id: UserId,
+        directory: NodeRef,
+    ) -> BackendResult<Vec<Node>> {
+        let _guard = self.lock();
+        let mut tree = self.open(owner_id)?;
+        let path = tree.resolve(directory)?;
+        let location = tree.locate_directory(&path)?;
+        // The directory of a user is created with the first node.
+        if !location.is_dir() {
+            return Ok(Vec::new());
+        }
+        let nodes = tree.children(&path, &location)?;
+        tree.save()?;
+        Ok(nodes)
+    }
+
+    fn fetch(&self, owner_id: UserId, node: NodeRef) -> BackendResult<Node> {
+        let _guard = self.lock();
+        let mut tree = self.open(owner_id)?;
+        let path = tree.resolve(node)?;
+        let (location, is_directory) = tree.locate(&path)?;
+        let node = tree.node(&path, &location, is_directory)?;
+        tree.save()?;
+        Ok(node)
+    }
+
+    fn path(
+        &self,
+        owner_id: UserId,
+        node_id: NodeId,
+    ) -> BackendResult<OwnedPath> {
+        let _guard = self.lock();
+        self.open(owner_id)?.resolve(NodeRef::Id(node_id))
+    }
+
+    fn insert(
+        &self,
+        owner_id: UserId,
+        parent: NodeRef,
+        payload: &NewNodePayload,
+    ) -> BackendResult<Node> {
+        validate_name(&payload.name)?;
+        let _guard = self.lock();
+        let mut tree = self.open(owner_id)?;
+        let parent_path = tree.resolve(parent)?;
+        let parent = tree.locate_directory(&parent_path)?;
+        let content = if payload.is_directory {
+            None
+        } else {
+            Some(payload.content.as_ref().ok_or(BackendError::InvalidValue)?)
+        };
+        fs::create_dir_all(&tree.dir)?;
+        check_free(&parent, &payload.name, None)?;
+
+        let location =
+            entry_location(&parent, &payload.name, payload.is_directory);
+        match content {
+            None => fs::create_dir(&location)?,
+            Some(content) => write_atomically(&location, content.as_bytes())?,
+        }
+        let mut path = parent_path.to_vec();
+        path.push(payload.name.clone());
+        // Ids of nodes that were deleted by other programs are not reused.
+        tree.remove_ids(&path);
+        let node = tree.node(&path, &location, payload.is_directory)?;
+        tree.save()?;
+        Ok(node)
+    }
+
+    fn change(
+        &self,
+        owner_id: UserId,
+        node: NodeRef,
+        change: &NodeChange,
+    ) -> BackendResult<Node> {
+        let _guard = self.lock();
+        let mut tree = self.open(owner_id)?;
+        let path = tree.resolve(node)?;
+        let (location, is_directory) = tree.locate(&path)?;
+
+        // Everything is checked before the files change.
+        let (name, parents) =
+            path.split_last().ok_or(BackendError::NotFound)?;
+        let name = match change.name {
+            Some(new_name) => {
+                validate_name(new_name)?;
+                String::from(new_name)
+            }
+            None => name.clone(),
+        };
+        let (mut new_path, new_parent) = match change.parent {
+            Some(new_parent) => {
+                let new_parent_path = tree.resolve(new_parent)?;
+                let new_parent = tree.locate_directory(&new_parent_path)?;
+                // The directory can not be moved into itself.
+                if new_parent_path.starts_with(&path) {
+                    return Err(BackendError::InvalidValue);
+                }
+                (new_parent_path, new_parent)
+            }
+            None => {
+                let parent = location.parent().unwrap_or(&tree.dir);
+                (parents.to_vec(), parent.to_path_buf())
+            }
+        };
+        check_free(&new_parent, &name, Some(&location))?;
+        if change.content.is_some() && is_directory {
+            return Err(BackendError::InvalidValue);
+        }
+
+        let new_location = entry_location(&new_parent, &name, is_directory);
+        new_path.push(name);
+        // The content is written before the node is moved and the move is
+        // undone if the content can not be put in place, so a failed change
+        // changes nothing.
+        let tmp_file = match change.content {
+            Some(content) => {
+                Some(write_temporarily(&new_location, content.as_bytes())?)
+            }
+            None => None,
+        };
+        if new_location != location {
+            tree.id(&path);
+            if let Err(err) = fs::rename(&location, &new_location) {
+                if let Some(tmp_file) = &tmp_file {
+                    let _ = fs::remove_file(tmp_file);
+                }
+                return Err(err.into());
+            }
+            tree.move_ids(&path, &new_path);
+        }
+        if let Some(tmp_file) = tmp_file {
+            if let Err(err) = replace_with(&new_location, &tmp_file) {
+                let _ = fs::remove_file(&tmp_file);
+                if new_location != location {
+                    let _ = fs::rename(&new_location, &location);
+                }
+                return Err(err);
+            }
+        }
+        let node = tree.node(&new_path, &new_location, is_directory)?;
+        tree.save()?;
+        Ok(node)
+    }
+
+    fn delete(&self, owner_id: UserId, node: NodeRef) -> BackendResult<()> {
+        let _guard = self.lock();
+        let mut tree = self.open(owner_id)?;
+        let path = tree.resolve(node)?;
+        let (location, is_directory) = tree.locate(&path)?;
+        if is_directory {
+            remove_directory(&location)?;
+        } else {
+            fs::remove_file(&location)?;
+        }
+        tree.remove_ids(&path);
+        tree.save()
+    }
+
+    fn delete_all(&self, owner_id: UserId) -> BackendResult<()> {
+        let _guard = self.lock();
+        let dir = self.user_dir(owner_id);
+        // The directory of a user is created with the first node.
+        if dir.is_dir() {
+            remove_directory(&dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance;
+    use std::cell::Cell;
+
+    #[test]
+    fn it_passes_the_conformance_suite() -> BackendResult<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let count = Cell::new(0);
+        conformance::run(|| {
+            count.set(count.get() + 1);
+            let store = FsNodeStore::new(&dir.join(count.get().to_string()));
+            (store.unwrap(), 1, 2)
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn it_stores_plain_files_and_keeps_ids() -> BackendResult<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let store = FsNodeStore::new(dir)?;
+        let path = |parts: &[&str]| -> OwnedPath {
+            parts.iter().map(|part| String::from(*part)).collect()
+        };
+        let (projects_path, todo_path) =
+            (path(&["Projects"]), path(&["Work", "Todo"]));
+
+        let projects = store.insert(
+            1,
+            NodeRef::ROOT,
+            &NewNodePayload {
+                name: String::from("Projects"),
+                is_directory: true,
+                content: None,
+            },
+        )?;
+        let todo = store.insert(
+            1,
+            NodeRef::Path(&projects_path),
+            &NewNodePayload {
+                name: String::from("Todo"),
+                is_directory: false,
+                content: Some(String::from("- [ ] fs")),
+            },
+        )?;
+        assert_eq!(
+            fs::read_to_string(dir.join("1/Projects/Todo.md"))?,
+            "- [ ] fs"
+        );
+
+        // Ids survive renames of ancestors and restarts.
+        store.rename(1, NodeRef::Id(projects.node_id), "Work")?;
+        let store = FsNodeStore::new(dir)?;
+        let moved = store.fetch(1, NodeRef::Path(&todo_path))?;
+        assert_eq!(moved.node_id, todo.node_id);
+        assert_eq!(moved.parent_id, Some(projects.node_id));
+        assert_eq!(store.path(1, todo.node_id)?, todo_path);
+
+        // Notes written by other programs are picked up, other files are not.
+        fs::write(dir.join("1/Work/Ideas.md"), "idea")?;
+        fs::write(dir.join("1/Work/notes.txt"), "not a note")?;
+        let names: Vec<_> = store
+            .list(1, NodeRef::Id(projects.node_id))?
+            .into_iter()
+            .map(|node| node.node_name)
+            .collect();
+        assert_eq!(names, vec!["Ideas", "Todo"]);
+
+        // The note `Todo` would be stored as `Todo.md`.
+        assert!(matches!(
+            store.insert(
+                1,
+                NodeRef::Id(projects.node_id),
+                &NewNodePayload {
+                    name: String::from("Todo.md"),
+                    is_directory: true,
+                    content: None,
+                },
+            ),
+            Err(BackendError::Conflict)
+        ));
+
+        // Ids of notes removed by other programs are no longer found.
+        fs::remove_file(dir.join("1/Work/Todo.md"))?;
+        assert!(matches!(
+            store.fetch(1, NodeRef::Id(todo.node_id)),
+            Err(BackendError::NotFound)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_removes_leftovers_of_failed_deletes() -> BackendResult<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let store = FsNodeStore::new(dir)?;
+        let projects = NewNodePayload {
+            name: String::from("Projects"),
+            is_directory: true,
+            content: None,
+        };
+
+        // A delete that failed to remove the hidden directory.
+        let node = store.insert(1, NodeRef::ROOT, &projects)?;
+        fs::create_dir_all(dir.join("1/.Projects.mnstore-deleted/Old"))?;
+        store.delete(1, NodeRef::Id(node.node_id))?;
+        assert!(!dir.join("1/.Projects.mnstore-deleted").exists());
+        assert!(!dir.join("1/Projects").exists());
+
+        store.insert(1, NodeRef::ROOT, &projects)?;
+        store.delete_all(1)?;
+        assert!(!dir.join("1").exists());
+        assert!(store.fetch_all(1)?.is_empty());
+
+        Ok(())
+    }
+}
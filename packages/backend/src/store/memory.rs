@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+use super::{NodeChange, NodeRef, NodeStore};
+use crate::models::{NewNodePayload, Node, NodeId, OwnedPath, Path, UserId};
+use crate::{BackendError, BackendResult};
+
+/// Stores the nodes in memory, e.g. for tests that do not need a database.
+/// Enforces the same constraints as the database schema.
+#[derive(Default)]
+pub struct MemoryNodeStore {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: NodeId,
+    nodes: BTreeMap<NodeId, Node>,
+}
+
+impl State {
+    fn children(
+        &self,
+        owner_id: UserId,
+        parent_id: Option<NodeId>,
+    ) -> impl Iterator<Item = &Node> {
+        self.nodes.values().filter(move |node| {
+            node.owner_id == owner_id && node.parent_id == parent_id
+        })
+    }
+
+    fn find_id(&self, owner_id: UserId, path: &Path) -> BackendResult<NodeId> {
+        let mut node_id = None;
+        for part in path {
+            node_id = match self
+                .children(owner_id, node_id)
+                .find(|node| &node.node_name == part)
+            {
+                Some(node) => Some(node.node_id),
+                None => return Err(BackendError::PathNotFound(path.to_vec())),
+            };
+        }
+        node_id.ok_or(BackendError::NotFound)
+    }
+
+    /// Fails with `BackendError::NotFound` if the node does not exist or
+    /// belongs to another user.
+    fn find(&self, owner_id: UserId, node: NodeRef) -> BackendResult<NodeId> {
+        match node {
+            NodeRef::Path(path) => self.find_id(owner_id, path),
+            NodeRef::Id(node_id) => match self.nodes.get(&node_id) {
+                Some(node) if node.owner_id == owner_id => Ok(node_id),
+                _ => Err(BackendError::NotFound),
+            },
+        }
+    }
+
+    /// Returns the id of the directory, or `None` for the root.
+    fn find_directory(
+        &self,
+        owner_id: UserId,
+        directory: NodeRef,
+    ) -> BackendResult<Option<NodeId>> {
+        if let NodeRef::Path([]) = directory {
+            return Ok(None);
+        }
+        let node_id = self.find(owner_id, directory)?;
+        if !self.nodes[&node_id].is_directory {
+            return Err(BackendError::InvalidValue);
+        }
+        Ok(Some(node_id))
+    }
+
+    /// Fails with `BackendError::Conflict` if another node than `node_id` in
+    /// the directory is named `name`.
+    fn check_unique(
+        &self,
+        owner_id: UserId,
+        parent_id: Option<NodeId>,
+        name: &str,
+        node_id: Option<NodeId>,
+    ) -> BackendResult<()> {
+        let taken = self.children(owner_id, parent_id).any(|node| {
+            node.node_name == name && Some(node.node_id) != node_id
+        });
+        if taken {
+            return Err(BackendError::Conflict);
+        }
+        Ok(())
+    }
+
+    fn remove_recursively(&mut self, node_id: NodeId) {
+        let children: Vec<NodeId> = self
+            .nodes
+            .values()
+            .filter(|node| node.parent_id == Some(node_id))
+            .map(|node| node.node_id)
+            .collect();
+        for child_id in children {
+            self.remove_recursively(child_id);
+        }
+        self.nodes.remove(&node_id);
+    }
+}
+
+impl MemoryNodeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl NodeStore for MemoryNodeStore {
+    fn fetch_all(&self, owner_id: UserId) -> BackendResult<Vec<Node>> {
+        let state = self.state();
+        Ok(state
+            .nodes
+            .values()
+            .filter(|node| node.owner_id == owner_id)
+            .cloned()
+            .collect())
+    }
+
+    fn list(
+        &self,
+        owner_id: UserId,
+        directory: NodeRef,
+    ) -> BackendResult<Vec<Node>> {
+        let state = self.state();
+        let parent_id = state.find_directory(owner_id, directory)?;
+        let mut nodes: Vec<Node> =
+            state.children(owner_id, parent_id).cloned().collect();
+        nodes.sort_by(|a, b| a.node_name.cmp(&b.node_name));
+        Ok(nodes)
+    }
+
+    fn fetch(&self, owner_id: UserId, node: NodeRef) -> BackendResult<Node> {
+        let state = self.state();
+        let node_id = state.find(owner_id, node)?;
+        Ok(state.nodes[&node_id].clone())
+    }
+
+    fn path(
+        &self,
+        owner_id: UserId,
+        node_id: NodeId,
+    ) -> BackendResult<OwnedPath> {
+        let state = self.state();
+        let mut path = OwnedPath::new();
+        let mut current = Some(state.find(owner_id, NodeRef::Id(node_id))?);
+        while let Some(node_id) = current {
+            let node = &state.nodes[&node_id];
+            path.push(node.node_name.clone());
+            current = node.parent_id;
+        }
+        path.reverse();
+        Ok(path)
+    }
+
+    fn insert(
+        &self,
+        owner_id: UserId,
+        parent: NodeRef,
+        payload: &NewNodePayload,
+    ) -> BackendResult<Node> {
+        if !Node::is_name_valid(&payload.name) {
+            return Err(BackendError::InvalidNodeName(payload.name.clone()));
+        }
+        let mut state = self.state();
+        let parent_id = state.find_directory(owner_id, parent)?;
+        let content = if payload.is_directory {
+            None
+        } else {
+            match &payload.content {
+                Some(content) => Some(content.clone()),
+                None => return Err(BackendError::InvalidValue),
+            }
+        };
+        state.check_unique(owner_id, parent_id, &payload.name, None)?;
+
+        state.next_id += 1;
+        let node = Node {
+            node_id: state.next_id,
+            node_name: payload.name.clone(),
+            parent_id,
+            parent_is_directory: parent_id.map(|_| true),
+            owner_id,
+            is_directory: payload.is_directory,
+            content,
+        };
+        state.nodes.insert(node.node_id, node.clone());
+        Ok(node)
+    }
+
+    fn change(
+        &self,
+        owner_id: UserId,
+        node: NodeRef,
+        change: &NodeChange,
+    ) -> BackendResult<Node> {
+        let mut state = self.state();
+        let node_id = state.find(owner_id, node)?;
+        let current = &state.nodes[&node_id];
+        let is_directory = current.is_directory;
+        let name = String::from(change.name.unwrap_or(&current.node_name));
+        let mut parent_id = current.parent_id;
+
+        // Everything is checked before the node changes.
+        if !Node::is_name_valid(&name) {
+            return Err(BackendError::InvalidNodeName(name));
+        }
+        if let Some(new_parent) = change.parent {
+            parent_id = state.find_directory(owner_id, new_parent)?;
+            // The node would no longer be reachable from a root node.
+            let mut ancestor_id = parent_id;
+            while let Some(id) = ancestor_id {
+                if id == node_id {
+                    return Err(BackendError::InvalidValue);
+                }
+                ancestor_id = state.nodes[&id].parent_id;
+            }
+        }
+        state.check_unique(owner_id, parent_id, &name, Some(node_id))?;
+        if change.content.is_some() && is_directory {
+            return Err(BackendError::InvalidValue);
+        }
+
+        let node = state
+            .nodes
+            .get_mut(&node_id)
+            .ok_or(BackendError::NotFound)?;
+        node.node_name = name;
+        node.parent_id = parent_id;
+        node.parent_is_directory = parent_id.map(|_| true);
+        if let Some(content) = change.content {
+            node.content = Some(String::from(content));
+        }
+        Ok(node.clone())
+    }
+
+    fn delete(&self, owner_id: UserId, node: NodeRef) -> BackendResult<()> {
+        let mut state = self.state();
+        let node_id = state.find(owner_id, node)?;
+        state.remove_recursively(node_id);
+        Ok(())
+    }
+
+    fn delete_all(&self, owner_id: UserId) -> BackendResult<()> {
+        self.state()
+            .nodes
+            .retain(|_, node| node.owner_id != owner_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance;
+
+    #[test]
+    fn it_passes_the_conformance_suite() {
+        conformance::run(|| (MemoryNodeStore::new(), 1, 2));
+    }
+}
@@ -0,0 +1,207 @@
+//! The storage of the nodes behind the api. The routes only use the
+//! `NodeStore` trait, so they can be tested against `MemoryNodeStore` and
+//! the nodes can be stored elsewhere than in the database.
+//!
+//! Every implementation has to behave like `DieselNodeStore`, which is
+//! checked by the shared suite in `conformance`.
+
+#[cfg(test)]
+pub(crate) mod conformance;
+mod db;
+mod filesystem;
+mod memory;
+mod recording;
+
+pub use db::DieselNodeStore;
+pub use filesystem::FsNodeStore;
+pub use memory::MemoryNodeStore;
+pub use recording::RecordingNodeStore;
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::config::StorageSection;
+use crate::database::DbReadPool;
+use crate::history::History;
+use crate::models::{NewNodePayload, Node, NodeId, OwnedPath, Path, UserId};
+use crate::{BackendError, BackendResult, DbConnectionPool};
+
+/// Where the backend stores the nodes.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// In the nodes table of the database.
+    Database,
+    /// As folders and `.md` files, see `FsNodeStore`.
+    Filesystem,
+}
+
+impl FromStr for StorageBackend {
+    type Err = BackendError;
+
+    fn from_str(backend: &str) -> BackendResult<StorageBackend> {
+        match backend {
+            "database" => Ok(StorageBackend::Database),
+            "filesystem" => Ok(StorageBackend::Filesystem),
+            _ => Err(BackendError::InvalidConfig(format!(
+                "Invalid storage backend {}, expected database or filesystem",
+                backend
+            ))),
+        }
+    }
+}
+
+/// Returns the store configured in the `storage` section, which records its
+/// changes in the history if one is given. The pools are only used by the
+/// database store and to read the authors of the history.
+pub fn from_config(
+    cfg: &StorageSection,
+    pool: &DbConnectionPool,
+    read_pool: &DbReadPool,
+    history: Option<History>,
+) -> BackendResult<Box<dyn NodeStore>> {
+    let store: Box<dyn NodeStore> = match (cfg.backend, &cfg.dir) {
+        (StorageBackend::Database, _) => {
+            Box::new(DieselNodeStore::new(pool.clone(), read_pool.clone()))
+        }
+        (StorageBackend::Filesystem, Some(dir)) => {
+            Box::new(FsNodeStore::new(dir)?)
+        }
+        // Rejected when the configuration is loaded.
+        (StorageBackend::Filesystem, None) => {
+            return Err(BackendError::InvalidConfig(String::from(
+                "storage.dir is required for the filesystem backend",
+            )))
+        }
+    };
+    Ok(match history {
+        Some(history) => {
+            Box::new(RecordingNodeStore::new(store, history, read_pool.clone()))
+        }
+        None => store,
+    })
+}
+
+/// Identifies a node of the owner given to a `NodeStore`, either by the names
+/// of the nodes from a root node down to the node, or by its id.
+#[derive(Clone, Copy, Debug)]
+pub enum NodeRef<'a> {
+    Path(&'a Path),
+    Id(NodeId),
+}
+
+impl NodeRef<'static> {
+    /// The empty path, which stands for the root where a directory is
+    /// expected.
+    pub const ROOT: NodeRef<'static> = NodeRef::Path(&[]);
+}
+
+/// The changes of `NodeStore::change`. Fields that are `None` stay as they
+/// are.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct NodeChange<'a> {
+    pub name: Option<&'a str>,
+    /// The new directory of the node, or `NodeRef::ROOT` for the root.
+    pub parent: Option<NodeRef<'a>>,
+    pub content: Option<&'a str>,
+}
+
+/// The nodes of all users. Nodes are only ever addressed within the nodes of
+/// the given owner.
+///
+/// Errors are reported like by the database:
+/// - `PathNotFound` if there is no node at a path, `NotFound` if there is no
+///   node with an id or for the empty path where a node is expected.
+/// - `Conflict` if a directory already contains a node with the name.
+/// - `InvalidNodeName` if a name contains invalid characters.
+/// - `InvalidValue` for changes that break the tree, like a file without
+///   content, content for a directory, a file as parent or moving a directory
+///   into itself.
+pub trait NodeStore: Send + Sync {
+    /// Fetches all nodes of the user.
+    fn fetch_all(&self, owner_id: UserId) -> BackendResult<Vec<Node>>;
+
+    /// Fetches the nodes in the given directory, or the root nodes for
+    /// `NodeRef::ROOT`, ordered by name.
+    fn list(
+        &self,
+        owner_id: UserId,
+        directory: NodeRef,
+    ) -> BackendResult<Vec<Node>>;
+
+    fn fetch(&self, owner_id: UserId, node: NodeRef) -> BackendResult<Node>;
+
+    /// Returns the path of the node with the given id.
+    fn path(
+        &self,
+        owner_id: UserId,
+        node_id: NodeId,
+    ) -> BackendResult<OwnedPath>;
+
+    /// Inserts a new node into the given directory, or as a root node for
+    /// `NodeRef::ROOT`.
+    fn insert(
+        &self,
+        owner_id: UserId,
+        parent: NodeRef,
+        payload: &NewNodePayload,
+    ) -> BackendResult<Node>;
+
+    /// Changes the name, the parent and the content of the node at once. If
+    /// one of the changes fails, none of them is applied.
+    fn change(
+        &self,
+        owner_id: UserId,
+        node: NodeRef,
+        change: &NodeChange,
+    ) -> BackendResult<Node>;
+
+    /// Replaces the content of the file.
+    fn change_content(
+        &self,
+        owner_id: UserId,
+        node: NodeRef,
+        content: &str,
+    ) -> BackendResult<Node> {
+        let change = NodeChange {
+            content: Some(content),
+            ..NodeChange::default()
+        };
+        self.change(owner_id, node, &change)
+    }
+
+    fn rename(
+        &self,
+        owner_id: UserId,
+        node: NodeRef,
+        new_name: &str,
+    ) -> BackendResult<Node> {
+        let change = NodeChange {
+            name: Some(new_name),
+            ..NodeChange::default()
+        };
+        self.change(owner_id, node, &change)
+    }
+
+    /// Moves the node into the given directory, or to the root for
+    /// `NodeRef::ROOT`.
+    fn move_node(
+        &self,
+        owner_id: UserId,
+        node: NodeRef,
+        new_parent: NodeRef,
+    ) -> BackendResult<Node> {
+        let change = NodeChange {
+            parent: Some(new_parent),
+            ..NodeChange::default()
+        };
+        self.change(owner_id, node, &change)
+    }
+
+    /// Deletes the node and everything below it.
+    fn delete(&self, owner_id: UserId, node: NodeRef) -> BackendResult<()>;
+
+    /// Deletes all nodes of the user, e.g. before the user is deleted, so a
+    /// new user that gets the same id does not see them.
+    fn delete_all(&self, owner_id: UserId) -> BackendResult<()>;
+}
@@ -0,0 +1,273 @@
+use super::{NodeChange, NodeRef, NodeStore};
+use crate::database::DbReadPool;
+use crate::history::{Change, History};
+use crate::models::{
+    NewNodePayload, Node, NodeId, OwnedPath, User, UserId, UserQueries,
+};
+use crate::{BackendError, BackendResult};
+
+/// Commits every change to another store into the history, with the owner as
+/// author. The history only logs its errors, a stored change is never failed
+/// because of it.
+pub struct RecordingNodeStore {
+    inner: Box<dyn NodeStore>,
+    history: History,
+    /// Reads the usernames of the authors.
+    read_pool: DbReadPool,
+}
+
+impl RecordingNodeStore {
+    pub fn new(
+        inner: Box<dyn NodeStore>,
+        history: History,
+        read_pool: DbReadPool,
+    ) -> RecordingNodeStore {
+        RecordingNodeStore {
+            inner,
+            history,
+            read_pool,
+        }
+    }
+
+    /// Returns the path of the node before it is changed, if it can be read.
+    fn path_before(
+        &self,
+        owner_id: UserId,
+        node: NodeRef,
+    ) -> Option<OwnedPath> {
+        match node {
+            NodeRef::Path(path) => Some(path.to_vec()),
+            NodeRef::Id(id) => self.inner.path(owner_id, id).ok(),
+        }
+    }
+
+    fn path_after(&self, owner_id: UserId, node: &Node) -> Option<OwnedPath> {
+        self.inner.path(owner_id, node.node_id).ok()
+    }
+
+    /// Commits the change, or all nodes if a path of the change could not be
+    /// read.
+    fn record(&self, owner_id: UserId, change: Option<Change>) {
+        let change = change.unwrap_or(Change::Bulk("Change nodes"));
+        let user = self
+            .read_pool
+            .get()
+            .map_err(BackendError::from)
+            .and_then(|conn| User::load_by_id(&conn, owner_id));
+        match user {
+            Ok(user) => self.history.record(
+                self.inner.as_ref(),
+                owner_id,
+                &user.username,
+                change,
+            ),
+            Err(err) => log::error!(
+                "Failed to commit \"{}\" of user {} to the history: {}",
+                change,
+                owner_id,
+                err
+            ),
+        }
+    }
+}
+
+impl NodeStore for RecordingNodeStore {
+    fn fetch_all(&self, owner_id: UserId) -> BackendResult<Vec<Node>> {
+        self.inner.fetch_all(owner_id)
+    }
+
+    fn list(
+        &self,
+        owner_id: UserId,
+        directory: NodeRef,
+    ) -> BackendResult<Vec<Node>> {
+        self.inner.list(owner_id, directory)
+    }
+
+    fn fetch(&self, owner_id: UserId, node: NodeRef) -> BackendResult<Node> {
+        self.inner.fetch(owner_id, node)
+    }
+
+    fn path(
+        &self,
+        owner_id: UserId,
+        node_id: NodeId,
+    ) -> BackendResult<OwnedPath> {
+        self.inner.path(owner_id, node_id)
+    }
+
+    fn insert(
+        &self,
+        owner_id: UserId,
+        parent: NodeRef,
+        payload: &NewNodePayload,
+    ) -> BackendResult<Node> {
+        let node = self.inner.insert(owner_id, parent, payload)?;
+        let path = self.path_after(owner_id, &node);
+        self.record(owner_id, path.as_deref().map(Change::Create));
+        Ok(node)
+    }
+
+    fn change(
+        &self,
+        owner_id: UserId,
+        node: NodeRef,
+        change: &NodeChange,
+    ) -> BackendResult<Node> {
+        let from = self.path_before(owner_id, node);
+        let node = self.inner.change(owner_id, node, change)?;
+        let to = self.path_after(owner_id, &node);
+        let recorded = from.as_deref().zip(to.as_deref()).map(|(from, to)| {
+            if from == to {
+                Change::Edit(to)
+            } else if change.parent.is_some() {
+                Change::Move { from, to }
+            } else {
+                Change::Rename { from, to }
+            }
+        });
+        self.record(owner_id, recorded);
+        Ok(node)
+    }
+
+    fn delete(&self, owner_id: UserId, node: NodeRef) -> BackendResult<()> {
+        let path = self.path_before(owner_id, node);
+        self.inner.delete(owner_id, node)?;
+        self.record(owner_id, path.as_deref().map(Change::Delete));
+        Ok(())
+    }
+
+    fn delete_all(&self, owner_id: UserId) -> BackendResult<()> {
+        self.inner.delete_all(owner_id)?;
+        self.history.delete(owner_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+    use crate::store::{conformance, MemoryNodeStore};
+    use crate::user_management;
+    use std::cell::Cell;
+
+    #[test]
+    fn it_passes_the_conformance_suite_and_records_every_change(
+    ) -> BackendResult<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let count = Cell::new(0);
+        conformance::run(|| {
+            count.set(count.get() + 1);
+            let pool = database::create_test_pool().unwrap();
+            let conn = pool.get().unwrap();
+            database::run_migrations(&conn).unwrap();
+            let alice = user_management::provision(&conn, "alice").unwrap();
+            let bob = user_management::provision(&conn, "bob").unwrap();
+            drop(conn);
+
+            let history =
+                History::new(&dir.join(count.get().to_string())).unwrap();
+            let store = RecordingNodeStore::new(
+                Box::new(MemoryNodeStore::new()),
+                history.clone(),
+                DbReadPool::shared(&pool),
+            );
+            (CheckedStore { store, history }, alice.id, bob.id)
+        });
+        Ok(())
+    }
+
+    /// Checks after every change that the commits contain the same nodes as
+    /// a commit of all nodes.
+    struct CheckedStore {
+        store: RecordingNodeStore,
+        history: History,
+    }
+
+    impl CheckedStore {
+        fn check<T>(
+            &self,
+            owner_id: UserId,
+            result: BackendResult<T>,
+        ) -> BackendResult<T> {
+            // Nothing is committed for failed changes.
+            if result.is_err() {
+                return result;
+            }
+            let snapshot = self.history.commit(
+                &self.store,
+                owner_id,
+                "snapshot",
+                Change::Bulk("Snapshot"),
+            );
+            assert!(matches!(snapshot, Ok(None)), "{:?}", snapshot);
+            result
+        }
+    }
+
+    impl NodeStore for CheckedStore {
+        fn fetch_all(&self, owner_id: UserId) -> BackendResult<Vec<Node>> {
+            self.store.fetch_all(owner_id)
+        }
+
+        fn list(
+            &self,
+            owner_id: UserId,
+            directory: NodeRef,
+        ) -> BackendResult<Vec<Node>> {
+            self.store.list(owner_id, directory)
+        }
+
+        fn fetch(
+            &self,
+            owner_id: UserId,
+            node: NodeRef,
+        ) -> BackendResult<Node> {
+            self.store.fetch(owner_id, node)
+        }
+
+        fn path(
+            &self,
+            owner_id: UserId,
+            node_id: NodeId,
+        ) -> BackendResult<OwnedPath> {
+            self.store.path(owner_id, node_id)
+        }
+
+        fn insert(
+            &self,
+            owner_id: UserId,
+            parent: NodeRef,
+            payload: &NewNodePayload,
+        ) -> BackendResult<Node> {
+            let result = self.store.insert(owner_id, parent, payload);
+            self.check(owner_id, result)
+        }
+
+        fn change(
+            &self,
+            owner_id: UserId,
+            node: NodeRef,
+            change: &NodeChange,
+        ) -> BackendResult<Node> {
+            let result = self.store.change(owner_id, node, change);
+            self.check(owner_id, result)
+        }
+
+        fn delete(&self, owner_id: UserId, node: NodeRef) -> BackendResult<()> {
+            let result = self.store.delete(owner_id, node);
+            self.check(owner_id, result)
+        }
+
+        fn delete_all(&self, owner_id: UserId) -> BackendResult<()> {
+            self.store.delete_all(owner_id)?;
+            // The history is deleted together with the nodes.
+            assert!(matches!(
+                self.history.bundle(owner_id),
+                Err(BackendError::NotFound)
+            ));
+            Ok(())
+        }
+    }
+}
@@ -1,17 +1,21 @@
-use crate::models::{NewUser, User, UserId};
-use crate::{BackendError, BackendResult, DbConnection};
-use bcrypt::{hash, verify, DEFAULT_COST};
+use crate::database::{retry_on_busy, DbReadPool};
+use crate::models::{NewUser, User, UserId, UserQueries};
+use crate::passwords::{self, PasswordConfig};
+use crate::{BackendError, BackendResult, DbConnection, DbConnectionPool};
 use diesel::prelude::*;
 
-/// Creates a new user with the given username and password.
+/// Creates a new user with the given username and password. Returns
+/// `BackendError::WeakPassword` if the password violates the password policy.
 pub fn create<'a>(
     conn: &DbConnection,
+    cfg: &PasswordConfig,
     username: &'a str,
     password: &'a str,
 ) -> BackendResult<User> {
     use crate::models::schema::users;
 
-    let password_hash = hash(password, DEFAULT_COST)?;
+    passwords::check_policy(password, cfg)?;
+    let password_hash = passwords::hash(password, cfg)?;
     let new_user = NewUser {
         username,
         password_hash: &password_hash,
@@ -27,6 +31,28 @@ pub fn create<'a>(
     })
 }
 
+/// Loads the user with the given username or creates it without a usable
+/// password if it does not exist. Used for users authenticated by a trusted
+/// proxy, which can not log in with a password.
+pub fn provision(conn: &DbConnection, username: &str) -> BackendResult<User> {
+    use crate::models::schema::users;
+
+    conn.transaction(|| match User::load_by_username(conn, username) {
+        Ok(user) => Ok(user),
+        Err(BackendError::NotFound) => {
+            diesel::insert_into(users::table)
+                .values(&NewUser {
+                    username,
+                    password_hash: passwords::UNUSABLE_HASH,
+                })
+                .execute(conn)?;
+            log::info!("Provisioned user {}", username);
+            User::load_by_username(conn, username)
+        }
+        Err(err) => Err(err),
+    })
+}
+
 /// Deletes the user associated to the given user id. Returns
 /// `BackendError::NotFound` if there is no user with the given user id.
 pub fn delete(conn: &DbConnection, user_id: UserId) -> BackendResult<()> {
@@ -40,15 +66,26 @@ pub fn delete(conn: &DbConnection, user_id: UserId) -> BackendResult<()> {
     }
 }
 
-// Changes the password of the user associated to the given id.
+// Changes the password of the user associated to the given id. Returns
+// `BackendError::WeakPassword` if the password violates the password policy.
 pub fn change_password<'a>(
     conn: &DbConnection,
+    cfg: &PasswordConfig,
     user_id: UserId,
     new_password: &'a str,
+) -> BackendResult<User> {
+    passwords::check_policy(new_password, cfg)?;
+    let password_hash = passwords::hash(new_password, cfg)?;
+    store_password_hash(conn, user_id, password_hash)
+}
+
+fn store_password_hash(
+    conn: &DbConnection,
+    user_id: UserId,
+    password_hash: String,
 ) -> BackendResult<User> {
     use crate::models::schema::users;
 
-    let password_hash = hash(new_password, DEFAULT_COST)?;
     conn.transaction(|| {
         let count = diesel::update(users::table.find(user_id))
             .set(users::password_hash.eq(password_hash))
@@ -63,27 +100,55 @@ pub fn change_password<'a>(
 }
 
 /// Checks whether there exists a user with the given username and password
-/// combination.
+/// combination. If the password is correct but its hash was created with an
+/// outdated algorithm or parameters, the hash is replaced with a new one.
+///
+/// The user is read from `read_pool` and the password verified without a
+/// connection, a connection of `pool` is only taken to store the new hash. So
+/// logins do not hold the single sqlite writer while hashing.
 ///
 /// Returns `Ok(None)` if the user does not exist or the password is wrong.
 /// Returns `Err(...)` if an error occured during check that is not caused by
 /// the credentials being wrong.
 /// Returns `Ok(User {...})` if the username password combination is correct.
 pub fn check_user(
-    conn: &DbConnection,
+    read_pool: &DbReadPool,
+    pool: &DbConnectionPool,
+    cfg: &PasswordConfig,
     username: &str,
     password: &str,
 ) -> BackendResult<Option<User>> {
-    let user = match User::load_by_username(conn, username) {
+    let user = User::load_by_username(&read_pool.get()?, username);
+    let user = match user {
         Ok(user) => user,
         Err(err) => match &err {
-            BackendError::NotFound => return Ok(None),
+            BackendError::NotFound => {
+                // Take as long as for an existing user.
+                passwords::dummy_verify(password, cfg);
+                return Ok(None);
+            }
             _ => return Err(err),
         },
     };
-    if verify(password, &user.password_hash)? {
-        Ok(Some(user))
-    } else {
-        Ok(None)
+    let outdated = passwords::needs_rehash(&user.password_hash, cfg);
+    if !passwords::verify(password, &user.password_hash, cfg)? {
+        // A correct password with an outdated hash is hashed again with the
+        // configured parameters below. A wrong one does the same work, so an
+        // outdated hash never costs less than the hash of an unknown user.
+        if outdated {
+            passwords::dummy_verify(password, cfg);
+        }
+        return Ok(None);
+    }
+
+    if outdated {
+        let password_hash = passwords::hash(password, cfg)?;
+        let conn = pool.get()?;
+        let user = retry_on_busy(|| {
+            store_password_hash(&conn, user.id, password_hash.clone())
+        })?;
+        return Ok(Some(user));
     }
+
+    Ok(Some(user))
 }
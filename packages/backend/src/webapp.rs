@@ -0,0 +1,206 @@
+//! Serves the webapp build, so a single binary is enough to run the notebook.
+//!
+//! The files are read from a directory or, with the `embed-webapp` feature,
+//! embedded into the binary by `build.rs`. Paths that are neither a file nor
+//! look like one are answered with `index.html`, so the client side routes of
+//! the webapp can be reloaded. Precompressed `.br` and `.gz` files next to the
+//! original are preferred if the client accepts them.
+
+use rocket::handler::{Handler, Outcome};
+use rocket::http::{ContentType, Method};
+use rocket::{Data, Request, Response, Route};
+use std::borrow::Cow;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::config::WebappSection;
+use crate::{BackendError, BackendResult};
+
+/// The file served for `/` and for the routes of the webapp.
+const INDEX: &str = "index.html";
+
+/// Files in this directory have a content hash in their name and never change.
+const IMMUTABLE_PREFIX: &str = "static/";
+
+/// The rank of the webapp routes, so that every other route is tried first.
+const RANK: isize = 20;
+
+/// The precompressed variants that are looked for, in order of preference.
+const ENCODINGS: &[(&str, &str)] = &[("br", ".br"), ("gzip", ".gz")];
+
+#[cfg(feature = "embed-webapp")]
+static EMBEDDED: &[(&str, &[u8])] =
+    include!(concat!(env!("OUT_DIR"), "/webapp_assets.rs"));
+#[cfg(not(feature = "embed-webapp"))]
+static EMBEDDED: &[(&str, &[u8])] = &[];
+
+#[derive(Clone)]
+enum Source {
+    Dir(PathBuf),
+    /// Pairs of path and content, sorted by path.
+    Embedded(&'static [(&'static str, &'static [u8])]),
+}
+
+impl Source {
+    fn read(&self, path: &str) -> Option<Cow<'static, [u8]>> {
+        match self {
+            Source::Dir(root) => {
+                let file = root.join(path);
+                if file.is_file() {
+                    fs::read(file).ok().map(Cow::Owned)
+                } else {
+                    None
+                }
+            }
+            Source::Embedded(files) => files
+                .binary_search_by(|(name, _)| (*name).cmp(path))
+                .ok()
+                .map(|index| Cow::Borrowed(files[index].1)),
+        }
+    }
+}
+
+/// The handler of the webapp routes. Mount the result of `routes` at `/`.
+#[derive(Clone)]
+pub struct Webapp {
+    source: Source,
+}
+
+impl Webapp {
+    /// Serves the files in the given directory, which must contain an
+    /// `index.html`.
+    pub fn from_dir(dir: &Path) -> BackendResult<Webapp> {
+        Self::new(Source::Dir(dir.to_path_buf()))
+    }
+
+    /// Serves the files embedded with the `embed-webapp` feature.
+    pub fn embedded() -> BackendResult<Webapp> {
+        Self::new(Source::Embedded(EMBEDDED))
+    }
+
+    fn new(source: Source) -> BackendResult<Webapp> {
+        if source.read(INDEX).is_none() {
+            let build = match &source {
+                Source::Dir(dir) => dir.display().to_string(),
+                Source::Embedded(_) => String::from("embedded into the binary"),
+            };
+            return Err(BackendError::InvalidConfig(format!(
+                "The webapp build {} has no {}",
+                build, INDEX
+            )));
+        }
+        Ok(Webapp { source })
+    }
+
+    /// Returns the webapp configured in the `webapp` section, or `None` if
+    /// the webapp is not served by the backend.
+    pub fn from_config(cfg: &WebappSection) -> BackendResult<Option<Webapp>> {
+        match &cfg.dir {
+            Some(dir) => Ok(Some(Self::from_dir(dir)?)),
+            None if cfg.embedded => Ok(Some(Self::embedded()?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn routes(self) -> Vec<Route> {
+        vec![
+            Route::ranked(RANK, Method::Get, "/", self.clone()),
+            Route::ranked(RANK, Method::Get, "/<path..>", self),
+        ]
+    }
+
+    fn respond<'r>(&self, req: &Request, path: &str) -> Option<Response<'r>> {
+        let accepted = accepted_encodings(req);
+        let (body, encoding) = ENCODINGS
+            .iter()
+            .filter(|(encoding, _)| accepted.contains(encoding))
+            .find_map(|(encoding, suffix)| {
+                self.source
+                    .read(&format!("{}{}", path, suffix))
+                    .map(|body| (body, Some(*encoding)))
+            })
+            .or_else(|| self.source.read(path).map(|body| (body, None)))?;
+
+        let mut response = Response::build();
+        let content_type = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(ContentType::from_extension)
+            .unwrap_or(ContentType::Binary);
+        response.header(content_type).raw_header(
+            "Cache-Control",
+            if path.starts_with(IMMUTABLE_PREFIX) {
+                "public, max-age=31536000, immutable"
+            } else {
+                "no-cache"
+            },
+        );
+        response.raw_header("Vary", "Accept-Encoding");
+        if let Some(encoding) = encoding {
+            response.raw_header("Content-Encoding", encoding);
+        }
+        Some(response.sized_body(Cursor::new(body)).finalize())
+    }
+}
+
+impl Handler for Webapp {
+    fn handle<'r>(&self, req: &'r Request, data: Data) -> Outcome<'r> {
+        // Rocket resolves `..` instead of rejecting it.
+        if req.uri().segments().any(|part| part == "..") {
+            return Outcome::forward(data);
+        }
+        let path = match req.get_segments::<PathBuf>(0) {
+            Some(Ok(path)) => path
+                .iter()
+                .filter_map(|part| part.to_str())
+                .collect::<Vec<_>>()
+                .join("/"),
+            // Hidden files are never served.
+            Some(Err(_)) => return Outcome::forward(data),
+            None => String::new(),
+        };
+        let path = if path.is_empty() { INDEX } else { &path };
+
+        if let Some(response) = self.respond(req, path) {
+            return Outcome::Success(response);
+        }
+
+        // Unknown api routes and missing files are not found, everything else
+        // is a route of the webapp.
+        let is_api = path == "api" || path.starts_with("api/");
+        if is_api || Path::new(path).extension().is_some() {
+            return Outcome::forward(data);
+        }
+        match self.respond(req, INDEX) {
+            Some(response) => Outcome::Success(response),
+            None => Outcome::forward(data),
+        }
+    }
+}
+
+/// Returns the content codings of the `Accept-Encoding` header that are not
+/// refused with `q=0`.
+fn accepted_encodings<'a>(req: &'a Request<'_>) -> Vec<&'a str> {
+    req.headers()
+        .get("Accept-Encoding")
+        .flat_map(|value| value.split(','))
+        .filter_map(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next()?;
+            let refused = parts.any(|param| {
+                param
+                    .replace(' ', "")
+                    .trim_start_matches("q=")
+                    .parse::<f32>()
+                    .map(|q| q <= 0.0)
+                    .unwrap_or(false)
+            });
+            if refused {
+                None
+            } else {
+                Some(name)
+            }
+        })
+        .collect()
+}
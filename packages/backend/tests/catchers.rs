@@ -0,0 +1,37 @@
+//! Tests that errors raised before a route handles the request are answered
+//! with the same JSON body as the errors of the handlers.
+
+mod common;
+
+use rocket::http::Status;
+use rocket::local::Client;
+
+fn client() -> Client {
+    Client::new(common::rocket()).unwrap()
+}
+
+#[test]
+fn it_responds_with_json_for_unknown_routes() {
+    let client = client();
+
+    let mut response = client.get("/api/v1/unknown").dispatch();
+
+    assert_eq!(response.status(), Status::NotFound);
+    assert!(response
+        .body_string()
+        .unwrap()
+        .contains("\"code\":\"not_found\""));
+}
+
+#[test]
+fn it_responds_with_json_for_missing_tokens() {
+    let client = client();
+
+    let mut response = client.get("/api/v1/user/profile").dispatch();
+
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(response
+        .body_string()
+        .unwrap()
+        .contains("\"code\":\"token_missing\""));
+}
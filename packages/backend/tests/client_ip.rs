@@ -0,0 +1,72 @@
+//! Tests that the login lockouts only trust the `X-Real-IP` header in requests
+//! from the trusted proxies of the server configuration.
+
+mod common;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::Client;
+use std::net::SocketAddr;
+
+use backend::config::ServerSection;
+use backend::models::LockoutConfig;
+use backend::passwords::PasswordConfig;
+
+fn client() -> Client {
+    let rocket = common::rocket()
+        .manage(LockoutConfig {
+            free_attempts: 0,
+            ..LockoutConfig::default()
+        })
+        .manage(PasswordConfig::default())
+        .manage(ServerSection {
+            trusted_proxies: vec!["127.0.0.1".parse().unwrap()],
+            ..ServerSection::default()
+        });
+    Client::new(rocket).unwrap()
+}
+
+/// Tries to log in as the given unknown user and returns the status.
+fn login(
+    client: &Client,
+    username: &str,
+    remote: &str,
+    real_ip: &str,
+) -> Status {
+    let remote: SocketAddr = remote.parse().unwrap();
+    client
+        .post("/api/v1/user/auth")
+        .header(ContentType::JSON)
+        .header(Header::new("X-Real-IP", real_ip.to_string()))
+        .remote(remote)
+        .body(format!(
+            r#"{{"username":"{}","password":"wrong"}}"#,
+            username
+        ))
+        .dispatch()
+        .status()
+}
+
+#[test]
+fn it_ignores_the_real_ip_from_untrusted_addresses() {
+    let client = client();
+
+    let status = login(&client, "alice", "10.0.0.5:41234", "192.0.2.1");
+    assert_eq!(status, Status::Unauthorized);
+
+    // The address of the connection is locked, whatever the header says.
+    let status = login(&client, "bob", "10.0.0.5:41234", "192.0.2.2");
+    assert_eq!(status, Status::TooManyRequests);
+}
+
+#[test]
+fn it_uses_the_real_ip_from_trusted_proxies() {
+    let client = client();
+
+    let status = login(&client, "alice", "127.0.0.1:41234", "192.0.2.1");
+    assert_eq!(status, Status::Unauthorized);
+
+    let status = login(&client, "bob", "127.0.0.1:41234", "192.0.2.2");
+    assert_eq!(status, Status::Unauthorized);
+    let status = login(&client, "carol", "127.0.0.1:41234", "192.0.2.1");
+    assert_eq!(status, Status::TooManyRequests);
+}
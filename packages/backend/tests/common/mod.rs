@@ -0,0 +1,21 @@
+//! Builds the backend for the api tests with a migrated in-memory database.
+//! Each test manages the additional state it needs.
+
+use rocket::config::{Config, Environment};
+use rocket::Rocket;
+
+use backend::{api, database, jwt};
+
+/// Returns the v1 routes and the catchers with the database pools and a JWT
+/// secret.
+pub fn rocket() -> Rocket {
+    let pool = database::create_test_pool().unwrap();
+    database::run_migrations(&pool.get().unwrap()).unwrap();
+
+    rocket::custom(Config::new(Environment::Development))
+        .manage(jwt::Config::with_secret("test secret", 100, 60))
+        .manage(database::DbReadPool::shared(&pool))
+        .manage(pool)
+        .mount("/api/v1", api::v1::get_routes())
+        .register(api::catchers::get_catchers())
+}
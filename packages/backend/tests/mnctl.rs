@@ -0,0 +1,101 @@
+//! Tests the `mnctl` binary against a fresh sqlite database.
+#![cfg(not(feature = "postgres"))]
+
+use serde_json::Value;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Runs `mnctl` in `dir` against the database `notebook.sqlite` in it.
+fn mnctl(dir: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mnctl"))
+        .args(args)
+        .current_dir(dir)
+        .env("MN_DATABASE_URL", dir.join("notebook.sqlite"))
+        .env("MN_JWT_SECRET", "mnctl test secret")
+        .env_remove("MN_CONFIG")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn it_manages_users_without_a_terminal() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let dir = temp_dir.path();
+
+    let output = mnctl(dir, &["--json", "db", "migrate"], "");
+    assert_eq!(output.status.code(), Some(0));
+    let output = mnctl(dir, &["--json", "db", "status"], "");
+    let status: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(status["pending"], Value::Array(vec![]));
+
+    let password = "correct horse battery staple\n";
+    let args = ["user", "create", "alice", "--password-stdin"];
+    assert_eq!(mnctl(dir, &args, password).status.code(), Some(0));
+    // The username is taken.
+    assert_eq!(mnctl(dir, &args, password).status.code(), Some(4));
+
+    let output = mnctl(dir, &["--json", "user", "list"], "");
+    assert_eq!(output.status.code(), Some(0));
+    let users: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(users[0]["username"], "alice");
+    assert!(users[0].get("password_hash").is_none());
+
+    let output = mnctl(dir, &["--json", "node", "ls", "alice"], "");
+    assert_eq!(output.stdout, b"[]\n");
+
+    let args = [
+        "--json",
+        "token",
+        "create",
+        "alice",
+        "--name",
+        "backup",
+        "--scopes",
+        "read,admin",
+        "--expires-days",
+        "30",
+    ];
+    let output = mnctl(dir, &args, "");
+    assert_eq!(output.status.code(), Some(0));
+    let created: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(created["token"].as_str().unwrap().starts_with("mnpat_"));
+    assert_eq!(created["accessToken"]["name"], "backup");
+    assert_eq!(created["accessToken"]["scopes"][1], "admin");
+    // Unknown scopes are rejected, a missing name is invalid usage.
+    let args = ["token", "create", "alice", "--name", "x", "--scopes", "all"];
+    assert_eq!(mnctl(dir, &args, "").status.code(), Some(4));
+    let args = ["token", "create", "alice", "--scopes", "read"];
+    assert_eq!(mnctl(dir, &args, "").status.code(), Some(2));
+
+    assert_eq!(
+        mnctl(dir, &["user", "delete", "alice"], "").status.code(),
+        Some(0)
+    );
+}
+
+#[test]
+fn it_reports_errors_with_exit_codes() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let dir = temp_dir.path();
+
+    let output = mnctl(dir, &["--json", "user", "delete", "bob"], "");
+    assert_eq!(output.status.code(), Some(3));
+    let error: Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["error"], "not_found");
+
+    assert_eq!(
+        mnctl(dir, &["user", "frobnicate"], "").status.code(),
+        Some(2)
+    );
+}
@@ -0,0 +1,253 @@
+//! Tests the node routes against the in-memory store, which needs no database
+//! since JWTs are verified without one.
+
+use rocket::config::{Config, Environment};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::Client;
+use serde_json::{json, Value};
+
+use backend::models::User;
+use backend::store::{MemoryNodeStore, NodeStore};
+use backend::{api, jwt};
+
+fn client() -> (Client, Header<'static>) {
+    let jwt_config = jwt::Config::with_secret("test secret", 100, 60);
+    let user = User {
+        id: 1,
+        username: String::from("alice"),
+        password_hash: String::new(),
+    };
+    let token = jwt::Claims::from_user(user, &jwt_config)
+        .to_token(&jwt_config)
+        .unwrap();
+
+    let rocket = rocket::custom(Config::new(Environment::Development))
+        .manage(jwt_config)
+        .manage(Box::new(MemoryNodeStore::new()) as Box<dyn NodeStore>)
+        .mount("/api/v1", api::v1::get_routes())
+        .mount("/api/v2", api::v2::get_routes())
+        .register(api::catchers::get_catchers());
+    let auth = Header::new("Authorization", format!("Bearer {}", token));
+    (Client::new(rocket).unwrap(), auth)
+}
+
+fn send(
+    client: &Client,
+    auth: &Header<'static>,
+    method: &str,
+    url: &str,
+    body: Value,
+) -> (Status, Value) {
+    let request = match method {
+        "GET" => client.get(url),
+        "POST" => client.post(url),
+        "PUT" => client.put(url),
+        "PATCH" => client.patch(url),
+        "DELETE" => client.delete(url),
+        _ => unreachable!(),
+    };
+    let mut response = request
+        .header(auth.clone())
+        .header(ContentType::JSON)
+        .body(body.to_string())
+        .dispatch();
+    let body = response
+        .body_string()
+        .filter(|body| !body.is_empty())
+        .map(|body| serde_json::from_str(&body).unwrap())
+        .unwrap_or(Value::Null);
+    (response.status(), body)
+}
+
+#[test]
+fn it_manages_nodes_in_the_store() {
+    let (client, auth) = client();
+
+    let (status, _) = send(
+        &client,
+        &auth,
+        "POST",
+        "/api/v1/node",
+        json!({ "parent": [], "node": { "name": "Projects", "isDirectory": true } }),
+    );
+    assert_eq!(status, Status::Ok);
+    let (status, todo) = send(
+        &client,
+        &auth,
+        "POST",
+        "/api/v1/node",
+        json!({
+            "parent": ["Projects"],
+            "node": { "name": "Todo", "isDirectory": false, "content": "- [ ]" }
+        }),
+    );
+    assert_eq!(status, Status::Ok);
+    assert_eq!(todo["content"], "- [ ]");
+
+    let (status, body) = send(
+        &client,
+        &auth,
+        "POST",
+        "/api/v1/node",
+        json!({ "parent": [], "node": { "name": "Projects", "isDirectory": true } }),
+    );
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(body["code"], "name_conflict");
+
+    let (status, _) = send(
+        &client,
+        &auth,
+        "PUT",
+        "/api/v1/node/content",
+        json!({ "path": ["Projects", "Todo"], "newContent": "- [x]" }),
+    );
+    assert_eq!(status, Status::Ok);
+    let (status, moved) = send(
+        &client,
+        &auth,
+        "PUT",
+        "/api/v1/node/parent",
+        json!({ "nodePath": ["Projects", "Todo"], "newParentPath": [] }),
+    );
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        moved,
+        json!({ "oldPath": ["Projects", "Todo"], "newPath": ["Todo"] })
+    );
+
+    let (status, body) = send(
+        &client,
+        &auth,
+        "PUT",
+        "/api/v1/node/parent",
+        json!({ "nodePath": ["Projects"], "newParentPath": ["Todo"] }),
+    );
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(body["code"], "invalid_value");
+
+    let (status, _) = send(
+        &client,
+        &auth,
+        "DELETE",
+        "/api/v1/node",
+        json!({ "path": ["Projects"] }),
+    );
+    assert_eq!(status, Status::Ok);
+    let (status, nodes) =
+        send(&client, &auth, "GET", "/api/v1/node", Value::Null);
+    assert_eq!(status, Status::Ok);
+    assert_eq!(nodes.as_array().unwrap().len(), 1);
+    assert_eq!(nodes[0]["nodeName"], "Todo");
+    assert_eq!(nodes[0]["content"], "- [x]");
+}
+
+#[test]
+fn it_addresses_nodes_by_id_in_v2() {
+    let (client, auth) = client();
+
+    let mut response = client
+        .post("/api/v2/nodes")
+        .header(auth.clone())
+        .header(ContentType::JSON)
+        .body(json!({ "name": "Projects", "isDirectory": true }).to_string())
+        .dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let projects: Value =
+        serde_json::from_str(&response.body_string().unwrap()).unwrap();
+    let projects_id = projects["nodeId"].as_i64().unwrap();
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some(format!("/api/v2/nodes/{}", projects_id).as_str())
+    );
+
+    let (status, todo) = send(
+        &client,
+        &auth,
+        "POST",
+        &format!("/api/v2/nodes/{}/children", projects_id),
+        json!({ "name": "Todo", "isDirectory": false, "content": "- [ ]" }),
+    );
+    assert_eq!(status, Status::Created);
+    assert_eq!(todo["parentId"], projects_id);
+    let todo_url = format!("/api/v2/nodes/{}", todo["nodeId"]);
+
+    let (status, children) = send(
+        &client,
+        &auth,
+        "GET",
+        &format!("/api/v2/nodes/{}/children", projects_id),
+        Value::Null,
+    );
+    assert_eq!(status, Status::Ok);
+    assert_eq!(children, json!([todo]));
+    let (status, body) = send(
+        &client,
+        &auth,
+        "GET",
+        "/api/v2/nodes/by-path?path=Projects/Todo",
+        Value::Null,
+    );
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body, todo);
+
+    // The id stays the same when the parent is renamed.
+    let (status, _) = send(
+        &client,
+        &auth,
+        "PATCH",
+        &format!("/api/v2/nodes/{}", projects_id),
+        json!({ "name": "Work" }),
+    );
+    assert_eq!(status, Status::Ok);
+    let (status, changed) = send(
+        &client,
+        &auth,
+        "PATCH",
+        &todo_url,
+        json!({ "content": "- [x]", "parentId": null }),
+    );
+    assert_eq!(status, Status::Ok);
+    assert_eq!(changed["nodeId"], todo["nodeId"]);
+    assert_eq!(changed["parentId"], Value::Null);
+    assert_eq!(changed["content"], "- [x]");
+
+    let (status, body) = send(
+        &client,
+        &auth,
+        "PATCH",
+        &format!("/api/v2/nodes/{}", projects_id),
+        json!({ "parentId": todo["nodeId"] }),
+    );
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(body["code"], "invalid_value");
+    // The rename before the failed move is not applied either.
+    let (status, body) = send(
+        &client,
+        &auth,
+        "PATCH",
+        &format!("/api/v2/nodes/{}", projects_id),
+        json!({ "name": "Archive", "parentId": todo["nodeId"] }),
+    );
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(body["code"], "invalid_value");
+    let (status, body) = send(
+        &client,
+        &auth,
+        "GET",
+        &format!("/api/v2/nodes/{}", projects_id),
+        Value::Null,
+    );
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["nodeName"], "Work");
+
+    let (status, body) = send(&client, &auth, "DELETE", &todo_url, Value::Null);
+    assert_eq!(status, Status::NoContent);
+    assert_eq!(body, Value::Null);
+    let (status, body) = send(&client, &auth, "GET", &todo_url, Value::Null);
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(body["code"], "not_found");
+    let (status, roots) =
+        send(&client, &auth, "GET", "/api/v2/nodes", Value::Null);
+    assert_eq!(status, Status::Ok);
+    assert_eq!(roots[0]["nodeName"], "Work");
+}
@@ -0,0 +1,53 @@
+//! Tests the trusted proxy authentication with a local client standing in for
+//! the proxy by setting the `X-Remote-User` header.
+
+mod common;
+
+use rocket::http::{Header, Status};
+use rocket::local::Client;
+use std::net::SocketAddr;
+
+use backend::proxy_auth::ProxyAuthConfig;
+
+fn client() -> Client {
+    let rocket = common::rocket().manage(ProxyAuthConfig {
+        header: Some(String::from("X-Remote-User")),
+        trusted_proxies: vec!["127.0.0.1".parse().unwrap()],
+    });
+    Client::new(rocket).unwrap()
+}
+
+fn address(address: &str) -> SocketAddr {
+    address.parse().unwrap()
+}
+
+#[test]
+fn it_provisions_users_from_a_trusted_proxy() {
+    let client = client();
+
+    let mut response = client
+        .get("/api/v1/user/profile")
+        .header(Header::new("X-Remote-User", "alice"))
+        .remote(address("127.0.0.1:41234"))
+        .dispatch();
+
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.body_string().unwrap().contains("\"alice\""));
+}
+
+#[test]
+fn it_ignores_the_header_from_untrusted_addresses() {
+    let client = client();
+
+    let mut response = client
+        .get("/api/v1/user/profile")
+        .header(Header::new("X-Remote-User", "alice"))
+        .remote(address("10.0.0.5:41234"))
+        .dispatch();
+
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(response
+        .body_string()
+        .unwrap()
+        .contains("\"code\":\"token_missing\""));
+}
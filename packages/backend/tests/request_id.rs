@@ -0,0 +1,35 @@
+//! Tests that every response carries the id of its request, either the one
+//! passed by the load balancer or a generated one.
+
+mod common;
+
+use rocket::http::Header;
+use rocket::local::Client;
+
+use backend::logging::{LogConfig, RequestLogger, REQUEST_ID_HEADER};
+
+fn client() -> Client {
+    let rocket =
+        common::rocket().attach(RequestLogger::new(&LogConfig::default()));
+    Client::new(rocket).unwrap()
+}
+
+#[test]
+fn it_returns_the_request_id() {
+    let client = client();
+
+    let response = client
+        .get("/api/v1/user/profile")
+        .header(Header::new(REQUEST_ID_HEADER, "lb-1234"))
+        .dispatch();
+    assert_eq!(
+        response.headers().get_one(REQUEST_ID_HEADER),
+        Some("lb-1234")
+    );
+
+    let response = client.get("/api/v1/user/profile").dispatch();
+    assert_eq!(
+        response.headers().get_one(REQUEST_ID_HEADER).map(str::len),
+        Some(24)
+    );
+}
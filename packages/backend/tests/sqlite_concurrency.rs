@@ -0,0 +1,79 @@
+//! Tests that the read pool lets requests read in parallel, even while the
+//! single writer connection is in the middle of a transaction.
+#![cfg(not(feature = "postgres"))]
+
+use diesel::connection::SimpleConnection;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+use backend::database::{self, DbConnectionPool, DbReadPool};
+use backend::models::{User, UserQueries};
+use backend::user_management;
+
+const READERS: u32 = 4;
+
+/// Returns the pools of a fresh database in a directory that is removed when
+/// it is dropped.
+fn pools() -> (TempDir, DbConnectionPool, DbReadPool) {
+    let dir = tempfile::tempdir().unwrap();
+    let database_url = dir.path().join("notebook.sqlite");
+    let database_url = database_url.to_str().unwrap();
+
+    let pool = database::create_pool(database_url).unwrap();
+    database::run_migrations(&pool.get().unwrap()).unwrap();
+    let read_pool = database::create_read_pool(
+        database_url,
+        READERS,
+        Box::new(backend::logging::SlowConnectionLogger {
+            threshold: Duration::from_secs(1),
+        }),
+    )
+    .unwrap();
+    (dir, pool, read_pool)
+}
+
+#[test]
+fn readers_do_not_block_each_other_or_the_writer() {
+    let (_dir, pool, read_pool) = pools();
+    let writer = pool.get().unwrap();
+    user_management::provision(&writer, "alice").unwrap();
+
+    // Keep a write transaction open while reading.
+    writer.batch_execute("BEGIN IMMEDIATE").unwrap();
+    writer
+        .batch_execute("UPDATE users SET username = 'bob'")
+        .unwrap();
+
+    // Every reader holds its connection until all readers have one, so this
+    // only finishes if the connections are handed out in parallel.
+    let barrier = Arc::new(Barrier::new(READERS as usize));
+    let readers: Vec<_> = (0..READERS)
+        .map(|_| {
+            let read_pool = read_pool.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let conn =
+                    read_pool.get_timeout(Duration::from_secs(2)).unwrap();
+                barrier.wait();
+                User::load_by_username(&conn, "alice").unwrap()
+            })
+        })
+        .collect();
+    for reader in readers {
+        assert_eq!(reader.join().unwrap().username, "alice");
+    }
+
+    writer.batch_execute("COMMIT").unwrap();
+    let conn = read_pool.get().unwrap();
+    assert!(User::load_by_username(&conn, "bob").is_ok());
+}
+
+#[test]
+fn read_connections_can_not_write() {
+    let (_dir, _pool, read_pool) = pools();
+    let conn = read_pool.get().unwrap();
+
+    assert!(user_management::provision(&conn, "alice").is_err());
+}
@@ -0,0 +1,77 @@
+//! Tests serving a webapp build from a directory.
+
+use rocket::config::{Config, Environment};
+use rocket::http::{Header, Status};
+use rocket::local::Client;
+use std::fs;
+use std::path::PathBuf;
+
+use backend::api;
+use backend::webapp::Webapp;
+
+fn build_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "mn-webapp-{}-{}",
+        name,
+        std::process::id()
+    ));
+    fs::create_dir_all(dir.join("static/js")).unwrap();
+    fs::write(dir.join("index.html"), "<html>notebook</html>").unwrap();
+    fs::write(dir.join("static/js/main.1a2b.js"), "console.log(1)").unwrap();
+    fs::write(dir.join("static/js/main.1a2b.js.gz"), "gzipped").unwrap();
+    dir
+}
+
+fn client(name: &str) -> Client {
+    let webapp = Webapp::from_dir(&build_dir(name)).unwrap();
+    let rocket = rocket::custom(Config::new(Environment::Development))
+        .mount("/", webapp.routes())
+        .register(api::catchers::get_catchers());
+    Client::new(rocket).unwrap()
+}
+
+#[test]
+fn it_falls_back_to_the_index_for_webapp_routes() {
+    let client = client("fallback");
+
+    for path in &["/", "/index.html", "/notes/Projects/Todo"] {
+        let mut response = client.get(*path).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.headers().get_one("Cache-Control"),
+            Some("no-cache")
+        );
+        assert_eq!(response.body_string().unwrap(), "<html>notebook</html>");
+    }
+}
+
+#[test]
+fn it_serves_precompressed_assets_with_long_caching() {
+    let client = client("assets");
+
+    let mut response = client
+        .get("/static/js/main.1a2b.js")
+        .header(Header::new("Accept-Encoding", "br;q=0, gzip, deflate"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Content-Encoding"), Some("gzip"));
+    assert_eq!(
+        response.headers().get_one("Cache-Control"),
+        Some("public, max-age=31536000, immutable")
+    );
+    assert_eq!(response.body_string().unwrap(), "gzipped");
+
+    let mut response = client.get("/static/js/main.1a2b.js").dispatch();
+    assert_eq!(response.headers().get_one("Content-Encoding"), None);
+    assert_eq!(response.body_string().unwrap(), "console.log(1)");
+}
+
+#[test]
+fn it_does_not_serve_the_index_for_missing_files_and_api_routes() {
+    let client = client("not-found");
+
+    for path in &["/static/js/missing.js", "/api/v1/unknown", "/../secret"] {
+        let response = client.get(*path).dispatch();
+        assert_eq!(response.status(), Status::NotFound, "{}", path);
+    }
+}